@@ -0,0 +1,62 @@
+// author - Patryk Jędrzejczak
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use key_value_store::{serve, try_parse_request, Client, Config, Database};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("try_parse_request");
+    let messages = [
+        ("store", "STORE$key$value$"),
+        ("load", "LOAD$key$"),
+        ("storeb", "STOREB$key$10$zażółć"),
+        ("mdel", "MDEL$a$b$c$d$e$f$$"),
+        // Unfinished requests are checked against all requests they could become.
+        ("partial", "STORE$key$val")
+    ];
+
+    for (name, message) in messages {
+        group.bench_with_input(BenchmarkId::from_parameter(name), message, |b, message| {
+            b.iter(|| try_parse_request(&mut message.to_string()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn round_trip(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("round_trip");
+
+    let mut client = runtime.block_on(start_server(Database::new()));
+    group.bench_function("store_load", |b| b.iter(|| runtime.block_on(store_and_load(&mut client))));
+
+    // Every STORE is flushed to the disk, so the log is kept in a fresh
+    // directory, not affected by earlier runs.
+    let dir = std::env::temp_dir().join(format!("kvs-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut db = Database::new();
+    db.open_wal(&dir.join("wal.log"), false).unwrap();
+    let mut client = runtime.block_on(start_server(db));
+    group.bench_function("store_load_wal", |b| b.iter(|| runtime.block_on(store_and_load(&mut client))));
+
+    group.finish();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Starts a server on a random port and returns a client connected to it.
+async fn start_server(db: Database) -> Client {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, Arc::new(db), Arc::new(Config::default()), std::future::pending()));
+    Client::connect(address).await.unwrap()
+}
+
+async fn store_and_load(client: &mut Client) {
+    client.store("key", "value").await.unwrap();
+    assert_eq!(Some("value".to_string()), client.load("key").await.unwrap());
+}
+
+criterion_group!(benches, parsing, round_trip);
+criterion_main!(benches);
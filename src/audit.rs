@@ -0,0 +1,72 @@
+// author - Patryk Jędrzejczak
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Append-only log of operations modifying the database. Every line has
+// the form `<unix_seconds> <operation> <key> <peer_address>`. Values are
+// never logged. The server has no authentication, so there is no
+// principal to record other than the peer address.
+pub struct AuditLog {
+    writer: Mutex<BufWriter<File>>
+}
+
+impl AuditLog {
+    // Opens the log at path, appending to it if it already exists.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog { writer: Mutex::new(BufWriter::new(file)) })
+    }
+
+    // Records an operation. Entries are buffered until the next flush.
+    pub fn record(&self, operation: &str, key: &str, peer: Option<SocketAddr>) -> io::Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let peer = peer.map_or("-".to_string(), |peer| peer.to_string());
+
+        match self.writer.lock() {
+            Ok(mut writer) => writeln!(writer, "{timestamp} {operation} {key} {peer}"),
+            Err(_) => Err(io::Error::other("audit log lock poisoned"))
+        }
+    }
+
+    // Writes buffered entries to the file. Called periodically by the server.
+    pub fn flush(&self) -> io::Result<()> {
+        match self.writer.lock() {
+            Ok(mut writer) => writer.flush(),
+            Err(_) => Err(io::Error::other("audit log lock poisoned"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn audit_log_contains_recorded_operations_after_flush() {
+        let path = std::env::temp_dir().join(format!("kvs-audit-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+
+        let log = AuditLog::open(&path).unwrap();
+        log.record("STORE", "first", Some(peer)).unwrap();
+        log.record("INIT", "second", None).unwrap();
+        log.flush().unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let entries: Vec<Vec<&str>> = contents.lines()
+            .map(|line| line.split(' ').collect())
+            .collect();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(2, entries.len());
+        assert_eq!(&["STORE", "first", "127.0.0.1:4000"], &entries[0][1..]);
+        assert_eq!(&["INIT", "second", "-"], &entries[1][1..]);
+        assert!(entries.iter().all(|entry| entry[0].parse::<u64>().is_ok()));
+    }
+}
@@ -0,0 +1,94 @@
+// author - Patryk Jędrzejczak
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::io::{self, ErrorKind};
+
+// Length of keys of AES-256-GCM in bytes.
+pub(crate) const KEY_LEN: usize = 32;
+
+// Encrypts batches of records written to the write-ahead log with
+// AES-256-GCM, so they cannot be read from the disk without the key.
+// All batches are encrypted with the same key, so every batch gets its own
+// random nonce, stored before its ciphertext. A nonce repeated with
+// the same key would reveal both batches.
+pub(crate) struct Cipher {
+    key: LessSafeKey,
+    random: SystemRandom
+}
+
+impl Cipher {
+    pub(crate) fn new(key: &[u8; KEY_LEN]) -> Self {
+        // Keys of the right length are always accepted.
+        let key = UnboundKey::new(&AES_256_GCM, key).unwrap();
+        Cipher { key: LessSafeKey::new(key), random: SystemRandom::new() }
+    }
+
+    // Returns the nonce followed by the ciphertext and the tag.
+    pub(crate) fn encrypt(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        self.random.fill(&mut nonce).map_err(|_| io::Error::other("cannot generate a nonce"))?;
+        let mut sealed = plaintext.to_vec();
+        self.key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+            .map_err(|_| io::Error::other("cannot encrypt records"))?;
+        Ok([nonce.as_slice(), &sealed].concat())
+    }
+
+    // Fails if encrypted was written with another key or was damaged,
+    // as the tag does not match then.
+    pub(crate) fn decrypt(&self, encrypted: &[u8]) -> io::Result<Vec<u8>> {
+        let wrong_key = || io::Error::new(ErrorKind::InvalidData, "cannot decrypt the log, the key is wrong");
+        if encrypted.len() < NONCE_LEN {
+            return Err(wrong_key());
+        }
+        let (nonce, sealed) = encrypted.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| wrong_key())?;
+        let mut sealed = sealed.to_vec();
+        let plaintext = self.key.open_in_place(nonce, Aad::empty(), &mut sealed).map_err(|_| wrong_key())?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+// Parses a key given as 64 hexadecimal digits.
+pub(crate) fn parse_key(hex: &str) -> Option<[u8; KEY_LEN]> {
+    if hex.len() != 2 * KEY_LEN || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = [0; KEY_LEN];
+    for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_are_decrypted_only_with_the_same_key() {
+        let cipher = Cipher::new(&[7; KEY_LEN]);
+        let first = cipher.encrypt(b"SET$k$2$rv").unwrap();
+        let second = cipher.encrypt(b"SET$k$2$rv").unwrap();
+        // Every batch has its own nonce.
+        assert_ne!(first, second);
+        assert_eq!(b"SET$k$2$rv".as_slice(), cipher.decrypt(&first).unwrap());
+
+        let mut damaged = first.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        for encrypted in [&Cipher::new(&[8; KEY_LEN]).encrypt(b"CLEAR$").unwrap(), &damaged, &first[..5]] {
+            assert_eq!(ErrorKind::InvalidData, cipher.decrypt(encrypted).unwrap_err().kind());
+        }
+    }
+
+    #[test]
+    fn keys_are_parsed_from_hex_digits() {
+        let hex = "000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F";
+        let expected: Vec<u8> = (0..32).collect();
+        assert_eq!(Some(expected.as_slice()), parse_key(hex).as_ref().map(|key| key.as_slice()));
+
+        for hex in ["", &hex[2..], &format!("{hex}00"), &hex.replace('0', "g"), &format!("ż{}", &hex[2..])] {
+            assert!(parse_key(hex).is_none(), "{hex}");
+        }
+    }
+}
@@ -0,0 +1,259 @@
+// author - Patryk Jędrzejczak
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use std::fmt;
+use std::io;
+use super::dump::{InvalidDump, MAGIC, split_dump};
+#[cfg(feature = "json")]
+use super::dump::{JSON_START, split_json_dump};
+
+// Parser of one of the dump formats, e.g. split_dump.
+type SplitDump = fn(&[u8]) -> Result<Option<(Vec<(String, String)>, usize)>, InvalidDump>;
+
+// Client of the key-value store speaking the default protocol, i.e. with
+// text responses and without framing or compression.
+pub struct Client {
+    stream: TcpStream,
+    // Bytes received from the server and not parsed yet. A response may be
+    // split between reads, so its beginning waits here for the rest.
+    received: Vec<u8>
+}
+
+// Error returned by Client.
+#[derive(Debug)]
+pub enum ClientError {
+    // Connecting, writing or reading failed.
+    Io(io::Error),
+    // The server closed the connection before answering.
+    Closed,
+    // Key contains characters other than letters, digits and underscores,
+    // or value contains '$', so it could not be loaded back unambiguously.
+    InvalidArgument,
+    // The server answered with something else than expected, e.g. FULL$.
+    UnexpectedResponse(String),
+    // Dump was cut off, corrupted or is not a dump at all.
+    InvalidDump
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Io(error) => write!(f, "{error}"),
+            ClientError::Closed => write!(f, "connection closed by the server"),
+            ClientError::InvalidArgument => write!(f, "key or value cannot be sent"),
+            ClientError::UnexpectedResponse(response) => write!(f, "unexpected response {response}"),
+            ClientError::InvalidDump => write!(f, "invalid dump")
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        ClientError::Io(error)
+    }
+}
+
+impl Client {
+    pub async fn connect(address: impl ToSocketAddrs) -> Result<Self, ClientError> {
+        let stream = TcpStream::connect(address).await?;
+        Ok(Client { stream, received: Vec::new() })
+    }
+
+    // Stores the pair. Values that STORE request cannot carry are sent
+    // with STOREB request.
+    pub async fn store(&mut self, key: &str, value: &str) -> Result<(), ClientError> {
+        if !is_valid_key(key) || value.contains('$') {
+            return Err(ClientError::InvalidArgument);
+        }
+
+        let request = if value.bytes().all(|byte| byte.is_ascii_lowercase()) {
+            format!("STORE${key}${value}$")
+        } else {
+            format!("STOREB${key}${}${value}", value.len())
+        };
+        match self.exchange(&request).await?.as_slice() {
+            [status] if status == "DONE" => Ok(()),
+            response => Err(unexpected(response))
+        }
+    }
+
+    // Returns the value of the key, or None if the key is absent.
+    pub async fn load(&mut self, key: &str) -> Result<Option<String>, ClientError> {
+        if !is_valid_key(key) {
+            return Err(ClientError::InvalidArgument);
+        }
+
+        match self.exchange(&format!("LOAD${key}$")).await?.as_slice() {
+            [status, value] if status == "FOUND" => Ok(Some(value.clone())),
+            [status] if status == "NOTFOUND" => Ok(None),
+            response => Err(unexpected(response))
+        }
+    }
+
+    // Returns all pairs of the selected namespace in the binary format
+    // described in the dump module, e.g. to be saved as a backup.
+    pub async fn dump(&mut self) -> Result<Vec<u8>, ClientError> {
+        self.receive_dump("DUMP$binary$", MAGIC, split_dump).await
+    }
+
+    // Like dump, but in the JSON format, which other programs can read
+    // line by line.
+    #[cfg(feature = "json")]
+    pub async fn dump_json(&mut self) -> Result<Vec<u8>, ClientError> {
+        self.receive_dump("DUMP$json$", JSON_START, split_json_dump).await
+    }
+
+    // Sends DUMP request and waits for the whole dump, which starts with start.
+    async fn receive_dump(&mut self, request: &str, start: &[u8], split: SplitDump) -> Result<Vec<u8>, ClientError> {
+        self.stream.write_all(request.as_bytes()).await?;
+
+        loop {
+            // The server answers with a text response if it cannot dump, e.g. with ERROR$reason$.
+            if !self.received.starts_with(start) && !start.starts_with(&self.received) {
+                return Err(unexpected(&self.read_response().await?));
+            }
+            if let Some((_, len)) = split(&self.received).map_err(|_| ClientError::InvalidDump)? {
+                return Ok(self.received.drain(..len).collect());
+            }
+            self.receive().await?;
+        }
+    }
+
+    // Stores all pairs from dump, returned by dump or dump_json, and returns
+    // their number. Nothing is stored if the dump is not complete.
+    pub async fn restore(&mut self, dump: &[u8]) -> Result<usize, ClientError> {
+        #[cfg(feature = "json")]
+        let split: SplitDump = if dump.starts_with(JSON_START) { split_json_dump } else { split_dump };
+        #[cfg(not(feature = "json"))]
+        let split: SplitDump = split_dump;
+        let pairs = match split(dump) {
+            Ok(Some((pairs, len))) if len == dump.len() => pairs,
+            _ => return Err(ClientError::InvalidDump)
+        };
+        if !pairs.iter().all(|(key, _)| is_valid_key(key)) {
+            return Err(ClientError::InvalidArgument);
+        }
+
+        // Values come back as they were, so unlike in store, they may contain '$'.
+        for (key, value) in &pairs {
+            match self.exchange(&format!("STOREB${key}${}${value}", value.len())).await?.as_slice() {
+                [status] if status == "DONE" => {},
+                response => return Err(unexpected(response))
+            }
+        }
+        Ok(pairs.len())
+    }
+
+    // Sends request and returns fields of the response.
+    async fn exchange(&mut self, request: &str) -> Result<Vec<String>, ClientError> {
+        self.stream.write_all(request.as_bytes()).await?;
+        self.read_response().await
+    }
+
+    async fn read_response(&mut self) -> Result<Vec<String>, ClientError> {
+        loop {
+            if let Some((fields, len)) = split_response(&self.received)? {
+                self.received.drain(..len);
+                return Ok(fields);
+            }
+            self.receive().await?;
+        }
+    }
+
+    // Reads bytes that the server sent and appends them to received.
+    async fn receive(&mut self) -> Result<(), ClientError> {
+        let mut buf = [0; 1024];
+        match self.stream.read(&mut buf).await? {
+            0 => Err(ClientError::Closed),
+            read_num => {
+                self.received.extend_from_slice(&buf[..read_num]);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    key.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+fn unexpected(fields: &[String]) -> ClientError {
+    let response: String = fields.iter().map(|field| format!("{field}$")).collect();
+    ClientError::UnexpectedResponse(response)
+}
+
+// If received starts with a complete response, returns its fields and its
+// length in bytes. Otherwise, returns None. FOUND$value$ and ERROR$reason$
+// responses have two fields, the ones the client expects otherwise have one.
+fn split_response(received: &[u8]) -> Result<Option<(Vec<String>, usize)>, ClientError> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    while let Some(end) = received[start..].iter().position(|byte| *byte == b'$') {
+        let field = std::str::from_utf8(&received[start..start + end])
+            .map_err(|_| ClientError::UnexpectedResponse(String::from_utf8_lossy(received).into_owned()))?;
+        fields.push(field.to_string());
+        start += end + 1;
+
+        let fields_num = if matches!(fields[0].as_str(), "FOUND" | "ERROR") { 2 } else { 1 };
+        if fields.len() == fields_num {
+            return Ok(Some((fields, start)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn split_response_waits_for_whole_response() {
+        let fields = |fields: &[&str]| fields.iter().map(|field| field.to_string()).collect::<Vec<_>>();
+
+        assert!(split_response(b"").unwrap().is_none());
+        assert!(split_response(b"DON").unwrap().is_none());
+        assert!(split_response(b"FOUND$").unwrap().is_none());
+        assert!(split_response(b"FOUND$val").unwrap().is_none());
+        assert_eq!(Some((fields(&["DONE"]), 5)), split_response(b"DONE$DONE$").unwrap());
+        assert_eq!(Some((fields(&["FOUND", "value"]), 12)), split_response(b"FOUND$value$NOT").unwrap());
+        assert_eq!(Some((fields(&["FOUND", ""]), 7)), split_response(b"FOUND$$").unwrap());
+        assert_eq!(Some((fields(&["NOTFOUND"]), 9)), split_response(b"NOTFOUND$").unwrap());
+        assert!(split_response(b"\xff$").is_err());
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(1000)]
+    async fn load_reassembles_response_split_between_packets() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 7];
+            socket.read_exact(&mut buf).await.unwrap();
+            for part in ["FOU", "ND$zaż", "ółć$NOT", "FOUND$"] {
+                socket.write_all(part.as_bytes()).await.unwrap();
+                socket.flush().await.unwrap();
+                tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+            }
+        });
+
+        let mut client = Client::connect(address).await.unwrap();
+        assert_eq!(Some("zażółć".to_string()), client.load("k").await.unwrap());
+        // The rest of the received bytes is the next response.
+        assert_eq!(None, client.load("k").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn invalid_arguments_are_not_sent() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = Client::connect(listener.local_addr().unwrap()).await.unwrap();
+
+        assert!(matches!(client.store("k$", "v").await, Err(ClientError::InvalidArgument)));
+        assert!(matches!(client.store("k", "a$b").await, Err(ClientError::InvalidArgument)));
+        assert!(matches!(client.load("k-").await, Err(ClientError::InvalidArgument)));
+    }
+}
@@ -0,0 +1,270 @@
+// author - Patryk Jędrzejczak
+
+use std::env;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::TaskError;
+
+// Configuration of the server. It is read from environment variables
+// once at startup and shared by all tasks.
+pub struct Config {
+    // Address on which the server accepts connections. Set with KVS_ADDR.
+    pub address: SocketAddr,
+    // Address on which the server accepts connections of Redis clients
+    // speaking RESP. Disabled if it is None. Set with KVS_RESP_ADDR.
+    pub resp_address: Option<SocketAddr>,
+    // Address on which the server accepts HTTP connections. Disabled if
+    // it is None. Set with KVS_HTTP_ADDR.
+    pub http_address: Option<SocketAddr>,
+    // Address on which metrics are served in the text format of Prometheus.
+    // Disabled if it is None. Set with KVS_METRICS_ADDR.
+    pub metrics_address: Option<SocketAddr>,
+    // Value that INIT requests treat as if the key was not initialized.
+    // Set with KVS_INIT_SENTINEL.
+    pub init_sentinel: String,
+    // Path of the audit log of modifying operations. The log is
+    // disabled if it is None. Set with KVS_AUDIT_LOG.
+    pub audit_log: Option<PathBuf>,
+    // Path of the write-ahead log from which pairs are restored at startup.
+    // Pairs are kept only in memory if it is None. Set with KVS_WAL.
+    pub wal: Option<PathBuf>,
+    // Whether values are compressed with gzip in the write-ahead log.
+    // Requires the compression feature. Set with KVS_WAL_COMPRESSED=1.
+    pub wal_compressed: bool,
+    // Key encrypting changes written to the write-ahead log with AES-256-GCM.
+    // Changes are not encrypted if it is None. Requires the encryption
+    // feature. Set with KVS_WAL_KEY as 64 hexadecimal digits.
+    #[cfg(feature = "encryption")]
+    pub wal_key: Option<[u8; 32]>,
+    // Maximum total length of all keys and values in the database.
+    // Unlimited if it is None. Set with KVS_MAX_TOTAL_BYTES.
+    pub max_total_bytes: Option<usize>,
+    // Maximum length of a single value stored with STORE, STOREB or STOREX
+    // request. Unlimited if it is None. Set with KVS_MAX_VALUE_BYTES.
+    pub max_value_bytes: Option<usize>,
+    // Whether every request is preceded by its length, e.g. 9$LOAD$key$.
+    // Set with KVS_FRAMED=1.
+    pub framed: bool,
+    // Whether diagnostic requests (PEEK) are available.
+    // Set with KVS_DIAGNOSTICS=1.
+    pub diagnostics: bool,
+    // Whether administrative requests (SETCONFIG) are available.
+    // Set with KVS_ADMIN=1.
+    pub admin: bool,
+    // Whether both directions of every connection are compressed with
+    // zlib. Requires the compression feature. Set with KVS_COMPRESSED=1.
+    pub compressed: bool,
+    // Acceptor encrypting connections of all protocols with TLS, with the
+    // certificate chain and the private key read from PEM files given in
+    // KVS_TLS_CERT and KVS_TLS_KEY. Connections are not encrypted if it is
+    // None. Requires the tls feature.
+    #[cfg(feature = "tls")]
+    pub tls: Option<tokio_rustls::TlsAcceptor>,
+    // Number of previous values kept for every key by PUSHVER requests.
+    // Set with KVS_MAX_VERSIONS.
+    pub max_versions: usize,
+    // Time after which writing a response fails and the connection is
+    // closed. Unlimited if it is None. Set in milliseconds with KVS_WRITE_TIMEOUT_MS.
+    pub write_timeout: Option<Duration>,
+    // Directory in which SNAPSHOT requests create snapshots. SNAPSHOT
+    // requests are incorrect if it is None. Set with KVS_SNAPSHOT_DIR.
+    pub snapshot_dir: Option<PathBuf>,
+    // Time without any received bytes after which a connection is closed,
+    // so clients that never send anything do not hold sockets forever.
+    // Unlimited if it is None. Set in milliseconds with KVS_IDLE_TIMEOUT_MS,
+    // where 0 means unlimited.
+    pub idle_timeout: Option<Duration>,
+    // Maximum length of the unprocessed part of a message, i.e. of a single
+    // unfinished request. Longer messages close the connection, so clients
+    // cannot exhaust the server's memory. Set with KVS_MAX_MESSAGE_BYTES.
+    pub max_message_bytes: usize,
+    // Maximum number of connections handled at once on each address.
+    // Further connections wait until one of them is closed. Unlimited
+    // if it is None. Set with KVS_MAX_CONNECTIONS.
+    pub max_connections: Option<usize>,
+    // Maximum length of an answer listing stored pairs or keys, i.e. to
+    // DUMP, KEYS, SCAN and MGET requests. Longer answers are cut and followed
+    // by TRUNCATED$. Unlimited if it is None. Set with KVS_MAX_RESPONSE_BYTES.
+    pub max_response_bytes: Option<usize>,
+    // Number of bytes read from a connection at once. Larger buffers need
+    // fewer reads for long requests. A read into an empty buffer would look
+    // like a closed connection, so it cannot be 0. Set with KVS_READ_BUFFER_BYTES.
+    pub read_buffer_bytes: NonZeroUsize
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: SocketAddr::from(([0, 0, 0, 0], 5555)),
+            resp_address: None,
+            http_address: None,
+            metrics_address: None,
+            init_sentinel: String::new(),
+            audit_log: None,
+            wal: None,
+            wal_compressed: false,
+            #[cfg(feature = "encryption")]
+            wal_key: None,
+            max_total_bytes: None,
+            max_value_bytes: None,
+            framed: false,
+            diagnostics: false,
+            admin: false,
+            compressed: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+            max_versions: 5,
+            write_timeout: None,
+            snapshot_dir: None,
+            idle_timeout: Some(Duration::from_secs(5 * 60)),
+            max_message_bytes: 64 * 1024,
+            max_connections: None,
+            max_response_bytes: None,
+            read_buffer_bytes: NonZeroUsize::new(8 * 1024).unwrap()
+        }
+    }
+}
+
+impl Config {
+    // Reads configuration from environment variables. Variables that
+    // are not set keep their default values.
+    #[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+    pub fn from_env() -> Self {
+        let mut config = Config::default();
+
+        if let Ok(address) = env::var("KVS_ADDR") {
+            config.address = address.parse().expect("KVS_ADDR must be an address like 0.0.0.0:5555");
+        }
+        if let Ok(resp_address) = env::var("KVS_RESP_ADDR") {
+            config.resp_address = Some(resp_address.parse().expect("KVS_RESP_ADDR must be an address like 0.0.0.0:6379"));
+        }
+        if let Ok(http_address) = env::var("KVS_HTTP_ADDR") {
+            config.http_address = Some(http_address.parse().expect("KVS_HTTP_ADDR must be an address like 0.0.0.0:8080"));
+        }
+        if let Ok(metrics_address) = env::var("KVS_METRICS_ADDR") {
+            config.metrics_address = Some(
+                metrics_address.parse().expect("KVS_METRICS_ADDR must be an address like 0.0.0.0:9100")
+            );
+        }
+        if let Ok(init_sentinel) = env::var("KVS_INIT_SENTINEL") {
+            config.init_sentinel = init_sentinel;
+        }
+        if let Ok(audit_log) = env::var("KVS_AUDIT_LOG") {
+            config.audit_log = Some(PathBuf::from(audit_log));
+        }
+        if let Ok(wal) = env::var("KVS_WAL") {
+            config.wal = Some(PathBuf::from(wal));
+        }
+        if let Ok(max_total_bytes) = env::var("KVS_MAX_TOTAL_BYTES") {
+            config.max_total_bytes = Some(max_total_bytes.parse().expect("KVS_MAX_TOTAL_BYTES must be a number"));
+        }
+        if let Ok(max_value_bytes) = env::var("KVS_MAX_VALUE_BYTES") {
+            config.max_value_bytes = Some(max_value_bytes.parse().expect("KVS_MAX_VALUE_BYTES must be a number"));
+        }
+        if let Ok(framed) = env::var("KVS_FRAMED") {
+            config.framed = framed == "1";
+        }
+        if let Ok(diagnostics) = env::var("KVS_DIAGNOSTICS") {
+            config.diagnostics = diagnostics == "1";
+        }
+        if let Ok(admin) = env::var("KVS_ADMIN") {
+            config.admin = admin == "1";
+        }
+        if let Ok(max_versions) = env::var("KVS_MAX_VERSIONS") {
+            config.max_versions = max_versions.parse().expect("KVS_MAX_VERSIONS must be a number");
+        }
+        if let Ok(write_timeout) = env::var("KVS_WRITE_TIMEOUT_MS") {
+            let write_timeout = write_timeout.parse().expect("KVS_WRITE_TIMEOUT_MS must be a number");
+            config.write_timeout = Some(Duration::from_millis(write_timeout));
+        }
+        if let Ok(snapshot_dir) = env::var("KVS_SNAPSHOT_DIR") {
+            config.snapshot_dir = Some(PathBuf::from(snapshot_dir));
+        }
+        if let Ok(idle_timeout) = env::var("KVS_IDLE_TIMEOUT_MS") {
+            let idle_timeout = idle_timeout.parse().expect("KVS_IDLE_TIMEOUT_MS must be a number");
+            config.idle_timeout = (idle_timeout > 0).then(|| Duration::from_millis(idle_timeout));
+        }
+        if let Ok(max_message_bytes) = env::var("KVS_MAX_MESSAGE_BYTES") {
+            config.max_message_bytes = max_message_bytes.parse().expect("KVS_MAX_MESSAGE_BYTES must be a number");
+        }
+        if let Ok(wal_compressed) = env::var("KVS_WAL_COMPRESSED") {
+            config.wal_compressed = wal_compressed == "1";
+            assert!(
+                !config.wal_compressed || cfg!(feature = "compression"),
+                "KVS_WAL_COMPRESSED=1 requires the compression feature"
+            );
+        }
+        #[cfg(feature = "encryption")]
+        if let Ok(wal_key) = env::var("KVS_WAL_KEY") {
+            config.wal_key = Some(super::cipher::parse_key(&wal_key).expect("KVS_WAL_KEY must be 64 hexadecimal digits"));
+        }
+        #[cfg(not(feature = "encryption"))]
+        assert!(env::var_os("KVS_WAL_KEY").is_none(), "KVS_WAL_KEY requires the encryption feature");
+        if let Ok(max_connections) = env::var("KVS_MAX_CONNECTIONS") {
+            config.max_connections = Some(max_connections.parse().expect("KVS_MAX_CONNECTIONS must be a number"));
+        }
+        if let Ok(max_response_bytes) = env::var("KVS_MAX_RESPONSE_BYTES") {
+            config.max_response_bytes = Some(max_response_bytes.parse().expect("KVS_MAX_RESPONSE_BYTES must be a number"));
+        }
+        if let Ok(read_buffer_bytes) = env::var("KVS_READ_BUFFER_BYTES") {
+            config.read_buffer_bytes = read_buffer_bytes.parse().expect("KVS_READ_BUFFER_BYTES must be a positive number");
+        }
+        if let Ok(compressed) = env::var("KVS_COMPRESSED") {
+            config.compressed = compressed == "1";
+            assert!(
+                !config.compressed || cfg!(feature = "compression"),
+                "KVS_COMPRESSED=1 requires the compression feature"
+            );
+        }
+        if let Ok(tls_cert) = env::var("KVS_TLS_CERT") {
+            #[cfg(not(feature = "tls"))]
+            panic!("KVS_TLS_CERT requires the tls feature");
+            #[cfg(feature = "tls")]
+            {
+                let tls_key = env::var("KVS_TLS_KEY").expect("KVS_TLS_CERT requires KVS_TLS_KEY");
+                let tls = super::stream::tls_acceptor(tls_cert.as_ref(), tls_key.as_ref());
+                config.tls = Some(tls.unwrap_or_else(|error| panic!("Cannot load KVS_TLS_CERT and KVS_TLS_KEY: {error}")));
+            }
+        }
+
+        config
+    }
+}
+
+// Settings changed by SETCONFIG requests while the server runs. They are
+// kept in the database, so a change applies to all servers sharing it.
+// Until a setting is changed, its value from Config is used.
+#[derive(Default)]
+pub(crate) struct Settings {
+    // None if the setting was not changed.
+    max_value_bytes: Mutex<Option<Option<usize>>>,
+    idle_timeout: Mutex<Option<Option<Duration>>>
+}
+
+impl Settings {
+    pub(crate) fn max_value_bytes(&self, config: &Config) -> Result<Option<usize>, TaskError> {
+        let changed = self.max_value_bytes.lock().map_err(|_| TaskError::Poisoned)?;
+        Ok(changed.unwrap_or(config.max_value_bytes))
+    }
+
+    pub(crate) fn set_max_value_bytes(&self, max_value_bytes: Option<usize>) -> Result<(), TaskError> {
+        *self.max_value_bytes.lock().map_err(|_| TaskError::Poisoned)? = Some(max_value_bytes);
+        Ok(())
+    }
+
+    // Idle connections are reaped only if the timeout was configured,
+    // so it is passed instead of the whole configuration.
+    pub(crate) fn idle_timeout(&self, configured: Duration) -> Result<Option<Duration>, TaskError> {
+        let changed = self.idle_timeout.lock().map_err(|_| TaskError::Poisoned)?;
+        Ok(changed.unwrap_or(Some(configured)))
+    }
+
+    pub(crate) fn set_idle_timeout(&self, idle_timeout: Option<Duration>) -> Result<(), TaskError> {
+        *self.idle_timeout.lock().map_err(|_| TaskError::Poisoned)? = Some(idle_timeout);
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+// author - Patryk Jędrzejczak
+
+use tokio::sync::{watch, Notify};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::TaskError;
+
+// Activity of a single open connection.
+pub(crate) struct Activity {
+    last_activity: Mutex<Instant>,
+    // Notified when the connection has been idle for too long.
+    reaped: Notify
+}
+
+impl Activity {
+    fn new() -> Self {
+        Activity { last_activity: Mutex::new(Instant::now()), reaped: Notify::new() }
+    }
+
+    // Marks the connection as active now.
+    pub(crate) fn touch(&self) -> Result<(), TaskError> {
+        *self.last_activity.lock().map_err(|_| TaskError::Poisoned)? = Instant::now();
+        Ok(())
+    }
+
+    fn idle_time(&self) -> Result<Duration, TaskError> {
+        Ok(self.last_activity.lock().map_err(|_| TaskError::Poisoned)?.elapsed())
+    }
+
+    // Completes when the connection should be closed because it is idle.
+    // Completes immediately if the connection was reaped in the meantime.
+    pub(crate) async fn reaped(&self) {
+        self.reaped.notified().await
+    }
+}
+
+// Registry of activity of all open connections, so idle ones can be
+// found and closed by a single reaper instead of every connection task.
+pub(crate) struct Connections {
+    open: Mutex<(u64, HashMap<u64, Arc<Activity>>)>, // Next id and open connections.
+    // Set to true once, when the server starts shutting down.
+    shutting_down: watch::Sender<bool>
+}
+
+impl Connections {
+    pub(crate) fn new() -> Self {
+        Connections { open: Mutex::new((0, HashMap::new())), shutting_down: watch::Sender::new(false) }
+    }
+
+    // Tells all connections, including the ones opened later, that they
+    // should be closed after processing requests they have already received.
+    pub(crate) fn shut_down(&self) {
+        self.shutting_down.send_replace(true);
+    }
+
+    // Completes when the server is shutting down. Completes immediately
+    // if it already started shutting down.
+    pub(crate) async fn shutting_down(&self) {
+        // Fails only if the sender is dropped, which cannot happen while self exists.
+        let _ = self.shutting_down.subscribe().wait_for(|shutting_down| *shutting_down).await;
+    }
+
+    // Registers a new connection. Returns its id and activity.
+    pub(crate) fn open(&self) -> Result<(u64, Arc<Activity>), TaskError> {
+        let mut open = self.open.lock().map_err(|_| TaskError::Poisoned)?;
+        let id = open.0;
+        let activity = Arc::new(Activity::new());
+        open.0 += 1;
+        open.1.insert(id, activity.clone());
+        Ok((id, activity))
+    }
+
+    pub(crate) fn close(&self, id: u64) -> Result<(), TaskError> {
+        self.open.lock().map_err(|_| TaskError::Poisoned)?.1.remove(&id);
+        Ok(())
+    }
+
+    // Notifies all connections idle for at least idle_timeout that they
+    // should be closed. Returns the number of such connections.
+    pub(crate) fn reap(&self, idle_timeout: Duration) -> Result<usize, TaskError> {
+        let open = self.open.lock().map_err(|_| TaskError::Poisoned)?;
+        let mut reaped = 0;
+        for activity in open.1.values() {
+            if activity.idle_time()? >= idle_timeout {
+                // Stores a permit, so the connection notices it even if it
+                // is not waiting at the moment.
+                activity.reaped.notify_one();
+                reaped += 1;
+            }
+        }
+        Ok(reaped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn reap_notifies_only_idle_connections() {
+        let connections = Connections::new();
+        let (_, idle) = connections.open().unwrap();
+        let (active_id, active) = connections.open().unwrap();
+        let (closed_id, _) = connections.open().unwrap();
+        connections.close(closed_id).unwrap();
+        assert_ne!(active_id, closed_id);
+
+        time::sleep(Duration::from_millis(20)).await;
+        active.touch().unwrap();
+
+        assert_eq!(1, connections.reap(Duration::from_millis(10)).unwrap());
+        assert!(time::timeout(Duration::ZERO, idle.reaped()).await.is_ok());
+        assert!(time::timeout(Duration::from_millis(10), active.reaped()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn shutting_down_completes_after_shut_down() {
+        let connections = Connections::new();
+        assert!(time::timeout(Duration::from_millis(10), connections.shutting_down()).await.is_err());
+
+        connections.shut_down();
+        assert!(time::timeout(Duration::ZERO, connections.shutting_down()).await.is_ok());
+    }
+}
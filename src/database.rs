@@ -0,0 +1,679 @@
+// author - Patryk Jędrzejczak
+
+use tokio::sync::{broadcast, Notify};
+use tokio::time::Instant;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::warn;
+
+use super::{AuditLog, TaskError};
+use super::config::Settings;
+use super::connections::Connections;
+use super::metrics::Metrics;
+use super::wal::{Cipher, Record, Wal};
+
+// Database of the key-value pairs shared by all tasks.
+pub struct Database {
+    pub(crate) entries: Mutex<Entries>,
+    // Notified after every STORE. Used by tasks waiting for a key to appear.
+    pub(crate) stored: Notify,
+    pub(crate) metrics: Metrics,
+    // Activity of open connections, used to close the idle ones.
+    pub(crate) connections: Connections,
+    // Settings changed with SETCONFIG requests.
+    pub(crate) settings: Settings,
+    audit_log: Option<Arc<AuditLog>>,
+    wal: Option<Mutex<Wal>>
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Database {
+            entries: Mutex::new(Entries::new()),
+            stored: Notify::new(),
+            metrics: Metrics::new(),
+            connections: Connections::new(),
+            settings: Settings::default(),
+            audit_log: None,
+            wal: None
+        }
+    }
+
+    // Creates a database recording all modifying operations in audit_log.
+    pub fn with_audit_log(audit_log: Arc<AuditLog>) -> Self {
+        Database { audit_log: Some(audit_log), ..Database::new() }
+    }
+
+    // Restores pairs from the write-ahead log at path, creating it if it
+    // does not exist, and from now on writes all changes to it, compressing
+    // values if compressed is true. Called once at startup, before
+    // the database is shared.
+    pub fn open_wal(&mut self, path: &Path, compressed: bool) -> io::Result<()> {
+        self.restore_from_wal(path, compressed, None)
+    }
+
+    // Like open_wal, but encrypts changes written to the log with key, which
+    // is also needed to restore pairs from changes encrypted before.
+    #[cfg(feature = "encryption")]
+    pub fn open_encrypted_wal(&mut self, path: &Path, compressed: bool, key: &[u8; 32]) -> io::Result<()> {
+        self.restore_from_wal(path, compressed, Some(Cipher::new(key)))
+    }
+
+    fn restore_from_wal(&mut self, path: &Path, compressed: bool, cipher: Option<Cipher>) -> io::Result<()> {
+        let (wal, records) = Wal::open(path, compressed, cipher)?;
+        let entries = self.entries.get_mut().unwrap_or_else(PoisonError::into_inner);
+        for record in records {
+            entries.replay(record);
+        }
+        entries.enable_wal();
+        self.wal = Some(Mutex::new(wal));
+        Ok(())
+    }
+
+    // Records a modifying operation in the audit log, if it is enabled.
+    pub(crate) fn audit(&self, operation: &str, key: &str, peer: Option<SocketAddr>) -> Result<(), TaskError> {
+        match &self.audit_log {
+            Some(audit_log) => audit_log.record(operation, key, peer).map_err(TaskError::Io),
+            None => Ok(())
+        }
+    }
+
+    // Writes changes waiting for the write-ahead log to the disk. Every
+    // request that may change pairs calls it (or queue_wal after ACK$queued$)
+    // after releasing the lock of entries and before answering, so answered
+    // changes survive a crash.
+    // Writing and flushing block, so they run on a thread for blocking
+    // operations instead of stalling other connections on the runtime.
+    // Changes of the caller may have been taken by another task that is
+    // still writing them, so even with no changes waiting, write_wal has
+    // to wait for the lock of the log, which that task holds until they
+    // are on the disk.
+    pub(crate) async fn sync_wal(self: &Arc<Self>) -> Result<(), TaskError> {
+        if self.wal.is_none() {
+            return Ok(());
+        }
+        let db = Arc::clone(self);
+        tokio::task::spawn_blocking(move || db.write_wal()).await.map_err(|error| TaskError::Io(error.into()))?
+    }
+
+    // Like sync_wal, but returns at once and writes the changes in
+    // the background, for requests answered before their changes are on
+    // the disk. If writing fails, the failure is logged and the changes
+    // stay waiting, so they are written together with the next changes,
+    // and the next request waiting for the log fails if the disk is still
+    // failing.
+    pub(crate) fn queue_wal(self: &Arc<Self>) {
+        if self.wal.is_none() {
+            return;
+        }
+        let db = Arc::clone(self);
+        tokio::task::spawn_blocking(move || {
+            if let Err(error) = db.write_wal() {
+                warn!(%error, "writing queued changes to the write-ahead log failed");
+            }
+        });
+    }
+
+    // Returns the length of the write-ahead log on the disk, or 0 without
+    // the log. The log is locked while it is written, so it is read on
+    // a thread for blocking operations, like in sync_wal.
+    pub(crate) async fn wal_bytes(self: &Arc<Self>) -> Result<u64, TaskError> {
+        if self.wal.is_none() {
+            return Ok(0);
+        }
+        let db = Arc::clone(self);
+        tokio::task::spawn_blocking(move || match &db.wal {
+            Some(wal) => wal.lock().map(|wal| wal.len()).map_err(|_| TaskError::Poisoned),
+            None => Ok(0)
+        }).await.map_err(|error| TaskError::Io(error.into()))?
+    }
+
+    // Writes changes waiting for the write-ahead log to the disk, compacting
+    // the log if it has grown too much. Changes are taken while holding the
+    // lock of the log, so they are written in the order they were made.
+    // A task whose changes were taken by another task gets the lock only
+    // after they are written, and then has nothing to write.
+    fn write_wal(&self) -> Result<(), TaskError> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        let Ok(mut wal) = wal.lock() else {
+            return Err(TaskError::Poisoned);
+        };
+        let records = match self.entries.lock() {
+            Ok(mut entries) => entries.take_wal_records(),
+            Err(_) => return Err(TaskError::Poisoned)
+        };
+        if records.is_empty() {
+            return Ok(());
+        }
+        if let Err(error) = wal.append(&records) {
+            // They are written by the next task that gets the lock, so no
+            // task answers with changes missing from the log.
+            self.return_wal_records(records)?;
+            return Err(TaskError::Io(error));
+        }
+
+        if wal.should_compact() {
+            // Changes made in the meantime are part of the state, so they
+            // do not have to be written separately.
+            let (taken, records) = match self.entries.lock() {
+                Ok(mut entries) => (entries.take_wal_records(), entries.records()),
+                Err(_) => return Err(TaskError::Poisoned)
+            };
+            if let Err(error) = wal.compact(&records) {
+                self.return_wal_records(taken)?;
+                return Err(TaskError::Io(error));
+            }
+        }
+        Ok(())
+    }
+
+    // Puts back changes that could not be written to the log.
+    fn return_wal_records(&self, records: Vec<Record>) -> Result<(), TaskError> {
+        let mut entries = self.entries.lock().map_err(|_| TaskError::Poisoned)?;
+        entries.return_wal_records(records);
+        Ok(())
+    }
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Type of the database handle kept by every task.
+pub type Db = Arc<Database>;
+
+// Number of changes kept for subscribers that have not received them yet.
+const CHANGES_CAPACITY: usize = 1024;
+
+// Value stored as the value of a key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Change {
+    pub(crate) key: String,
+    pub(crate) value: String
+}
+
+// Key-value pairs together with their total size, which is the sum
+// of lengths of all keys and values, including previous versions.
+pub(crate) struct Entries {
+    pairs: HashMap<String, String>,
+    // Previous values of keys kept by PUSHVER requests, the newest first.
+    versions: HashMap<String, VecDeque<String>>,
+    // Expiry times of pairs stored by STOREX requests. Expired pairs are
+    // treated as absent, but they are removed only by LOAD requests or
+    // by overriding, so until then they count towards total_bytes and len.
+    expiries: HashMap<String, Instant>,
+    total_bytes: usize,
+    // Every stored value is sent to subscribers in the order of storing.
+    changes: broadcast::Sender<Change>,
+    // Changes not written to the write-ahead log yet, in the order they were
+    // made. None if the log is disabled.
+    wal_records: Option<Vec<Record>>
+}
+
+impl Entries {
+    fn new() -> Self {
+        let (changes, _) = broadcast::channel(CHANGES_CAPACITY);
+        Entries {
+            pairs: HashMap::new(),
+            versions: HashMap::new(),
+            expiries: HashMap::new(),
+            total_bytes: 0,
+            changes,
+            wal_records: None
+        }
+    }
+
+    fn enable_wal(&mut self) {
+        self.wal_records = Some(Vec::new());
+    }
+
+    // Returns changes made since the previous call.
+    fn take_wal_records(&mut self) -> Vec<Record> {
+        self.wal_records.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // Puts back changes taken by take_wal_records, before changes made
+    // since then, so they are written in the order they were made.
+    fn return_wal_records(&mut self, mut records: Vec<Record>) {
+        if let Some(newer) = &mut self.wal_records {
+            records.append(newer);
+            *newer = records;
+        }
+    }
+
+    // Applies a change read from the write-ahead log. Limits are not
+    // checked, because the change was made within them.
+    fn replay(&mut self, record: Record) {
+        match record {
+            Record::Insert { key, value } => {
+                self.insert(key, value, None);
+            },
+            Record::PushVersion { key, value, max_versions } => {
+                self.push_next_version(key, value, max_versions, None);
+            },
+            Record::Expire { key, expires_at } => {
+                if self.pairs.contains_key(&key) {
+                    self.expiries.insert(key, expires_at);
+                }
+            },
+            Record::Remove { key } => {
+                self.remove(&key);
+            },
+            Record::Clear => {
+                self.clear();
+            }
+        }
+    }
+
+    // Returns changes that recreate all pairs that have not expired,
+    // together with their previous versions and expiry times.
+    fn records(&self) -> Vec<Record> {
+        let mut records = Vec::new();
+        for (key, value) in self.iter() {
+            let versions = self.versions.get(key).cloned().unwrap_or_default();
+            let max_versions = versions.len();
+            // Pushing values from the oldest one recreates previous versions.
+            let mut values = versions.into_iter().rev().chain([value.clone()]);
+            records.push(Record::Insert { key: key.clone(), value: values.next().unwrap() });
+            records.extend(values.map(|value| Record::PushVersion { key: key.clone(), value, max_versions }));
+            if let Some(expires_at) = self.expiries.get(key) {
+                records.push(Record::Expire { key: key.clone(), expires_at: *expires_at });
+            }
+        }
+        records
+    }
+
+    // Returns a receiver of all values stored from now on.
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Change> {
+        self.changes.subscribe()
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&String> {
+        if self.is_expired(key) {
+            return None;
+        }
+        self.pairs.get(key)
+    }
+
+    // Returns all pairs that have not expired in an arbitrary order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.pairs.iter().filter(|(key, _)| !self.is_expired(key))
+    }
+
+    // Counters of the accounting, checked by tests. Requests report their
+    // own figures for the pairs they see, e.g. USAGE request.
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    #[cfg(test)]
+    fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    // Returns the value of the key from index versions ago. Index 0 means
+    // the current value.
+    pub(crate) fn get_version(&self, key: &str, index: usize) -> Option<&String> {
+        match index {
+            0 => self.get(key),
+            _ => self.versions.get(key)?.get(index - 1)
+        }
+    }
+
+    // Inserts the pair, overriding the previous value of the key and
+    // forgetting its previous versions, unless the total size would
+    // exceed limit. Returns true if the pair was inserted.
+    pub(crate) fn insert(&mut self, key: String, value: String, limit: Option<usize>) -> bool {
+        if !self.fits(&key, &value, limit) {
+            return false;
+        }
+
+        self.total_bytes = self.total_bytes_after_insert(&key, &value);
+        self.versions.remove(&key);
+        self.expiries.remove(&key);
+        self.log(|| Record::Insert { key: key.clone(), value: value.clone() });
+        self.publish(&key, &value);
+        self.pairs.insert(key, value);
+        true
+    }
+
+    // Inserts the pair like insert, but the pair expires at expires_at.
+    pub(crate) fn insert_expiring(&mut self, key: String, value: String, expires_at: Instant, limit: Option<usize>) -> bool {
+        if !self.insert(key.clone(), value, limit) {
+            return false;
+        }
+        self.log(|| Record::Expire { key: key.clone(), expires_at });
+        self.expiries.insert(key, expires_at);
+        true
+    }
+
+    // Inserts the pair like insert, but keeps up to max_versions previous
+    // values of the key. The oldest ones are forgotten first. An expired
+    // value is absent, so it is removed with its versions instead of being
+    // kept. Its removal is logged, so replaying the log later, when more
+    // values have expired, recreates the same versions.
+    pub(crate) fn push_version(&mut self, key: String, value: String, max_versions: usize, limit: Option<usize>) -> bool {
+        self.remove_expired(&key);
+        self.push_next_version(key, value, max_versions, limit)
+    }
+
+    fn push_next_version(&mut self, key: String, value: String, max_versions: usize, limit: Option<usize>) -> bool {
+        let mut versions = self.versions.get(&key).cloned().unwrap_or_default();
+        if let Some(old_value) = self.pairs.get(&key) {
+            versions.push_front(old_value.clone());
+        }
+        versions.truncate(max_versions);
+
+        let versions_bytes: usize = versions.iter().map(String::len).sum();
+        let total_bytes = self.total_bytes - self.size_of(&key) + key.len() + value.len() + versions_bytes;
+        if limit.is_some_and(|limit| total_bytes > limit) {
+            return false;
+        }
+
+        self.total_bytes = total_bytes;
+        self.expiries.remove(&key);
+        if versions.is_empty() {
+            self.versions.remove(&key);
+        } else {
+            self.versions.insert(key.clone(), versions);
+        }
+        self.log(|| Record::PushVersion { key: key.clone(), value: value.clone(), max_versions });
+        self.publish(&key, &value);
+        self.pairs.insert(key, value);
+        true
+    }
+
+    // Removes the key together with its previous versions.
+    // Returns its value if the key was present and has not expired.
+    pub(crate) fn remove(&mut self, key: &str) -> Option<String> {
+        let expired = self.is_expired(key);
+        if self.pairs.contains_key(key) {
+            self.log(|| Record::Remove { key: key.to_string() });
+        }
+        self.total_bytes -= self.size_of(key);
+        self.versions.remove(key);
+        self.expiries.remove(key);
+        self.pairs.remove(key).filter(|_| !expired)
+    }
+
+    // Removes all pairs together with their previous versions. Returns keys
+    // of the removed pairs that had not expired.
+    pub(crate) fn clear(&mut self) -> Vec<String> {
+        let keys = self.iter().map(|(key, _)| key.clone()).collect();
+        if !self.pairs.is_empty() {
+            self.log(|| Record::Clear);
+        }
+        self.pairs.clear();
+        self.versions.clear();
+        self.expiries.clear();
+        self.total_bytes = 0;
+        keys
+    }
+
+    // Removes the key if its pair has expired.
+    pub(crate) fn remove_expired(&mut self, key: &str) {
+        if self.is_expired(key) {
+            self.remove(key);
+        }
+    }
+
+    fn is_expired(&self, key: &str) -> bool {
+        self.expiries.get(key).is_some_and(|expires_at| *expires_at <= Instant::now())
+    }
+
+    // Queues the change for the write-ahead log. Nothing is copied if the
+    // log is disabled.
+    fn log(&mut self, record: impl FnOnce() -> Record) {
+        if let Some(records) = &mut self.wal_records {
+            records.push(record());
+        }
+    }
+
+    // Sends the change to subscribers. Nothing is copied if there are none.
+    fn publish(&self, key: &str, value: &str) {
+        if self.changes.receiver_count() > 0 {
+            // Sending fails only if all subscribers are gone in the meantime.
+            let _ = self.changes.send(Change { key: key.to_string(), value: value.to_string() });
+        }
+    }
+
+    // Returns the size of the key with its value and previous versions,
+    // or 0 if the key is absent.
+    // Returns true if inserting the pair would not make the total size
+    // exceed limit, i.e. if insert would insert it.
+    pub(crate) fn fits(&self, key: &str, value: &str, limit: Option<usize>) -> bool {
+        limit.is_none_or(|limit| self.total_bytes_after_insert(key, value) <= limit)
+    }
+
+    // Total size after replacing the value of the key and its previous
+    // values with the given value.
+    fn total_bytes_after_insert(&self, key: &str, value: &str) -> usize {
+        self.total_bytes - self.size_of(key) + key.len() + value.len()
+    }
+
+    fn size_of(&self, key: &str) -> usize {
+        let versions_bytes: usize = self.versions.get(key).map_or(0, |versions| versions.iter().map(String::len).sum());
+        self.pairs.get(key).map_or(0, |value| key.len() + value.len() + versions_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Duration;
+
+    #[test]
+    fn insert_keeps_total_size_within_limit() {
+        let mut entries = Entries::new();
+        let limit = Some(10);
+
+        assert!(entries.insert("ab".to_string(), "cd".to_string(), limit));
+        assert!(entries.insert("ef".to_string(), "ghij".to_string(), limit));
+        assert_eq!(10, entries.total_bytes);
+
+        // New pair does not fit.
+        assert!(!entries.insert("k".to_string(), String::new(), limit));
+        assert_eq!(None, entries.get("k"));
+
+        // Overriding with a longer value does not fit.
+        assert!(!entries.insert("ab".to_string(), "cde".to_string(), limit));
+        assert_eq!(Some(&"cd".to_string()), entries.get("ab"));
+
+        // Overriding with a shorter value frees space.
+        assert!(entries.insert("ef".to_string(), "g".to_string(), limit));
+        assert_eq!(7, entries.total_bytes);
+        assert!(entries.insert("k".to_string(), "lm".to_string(), limit));
+        assert_eq!(10, entries.total_bytes());
+        assert_eq!(3, entries.len());
+    }
+
+    #[test]
+    fn returned_wal_records_are_taken_before_newer_ones() {
+        let mut entries = Entries::new();
+        entries.enable_wal();
+        let insert = |value: &str| Record::Insert { key: "k".to_string(), value: value.to_string() };
+
+        entries.insert("k".to_string(), "a".to_string(), None);
+        let taken = entries.take_wal_records();
+        entries.insert("k".to_string(), "b".to_string(), None);
+        entries.return_wal_records(taken);
+        assert_eq!(vec![insert("a"), insert("b")], entries.take_wal_records());
+    }
+
+    #[test]
+    fn insert_without_limit_always_succeeds() {
+        let mut entries = Entries::new();
+
+        assert!(entries.insert("key".to_string(), "a".repeat(1000), None));
+        assert!(entries.insert("key".to_string(), "b".to_string(), None));
+        assert_eq!(4, entries.total_bytes());
+        assert_eq!(1, entries.len());
+        assert_eq!(Some(&"b".to_string()), entries.get("key"));
+    }
+
+    #[test]
+    fn remove_frees_space() {
+        let mut entries = Entries::new();
+
+        assert!(entries.insert("ab".to_string(), "cd".to_string(), None));
+        assert!(entries.insert("ef".to_string(), "g".to_string(), None));
+        assert_eq!(Some("cd".to_string()), entries.remove("ab"));
+        assert_eq!(None, entries.remove("ab"));
+        assert_eq!(3, entries.total_bytes());
+        assert_eq!(1, entries.len());
+    }
+
+    #[test]
+    fn clear_removes_all_pairs() {
+        let mut entries = Entries::new();
+
+        assert!(entries.insert("ab".to_string(), "cd".to_string(), None));
+        assert!(entries.push_version("ab".to_string(), "e".to_string(), 5, None));
+        assert!(entries.insert_expiring("f".to_string(), "g".to_string(), Instant::now(), None));
+        assert_eq!(vec!["ab".to_string()], entries.clear());
+        assert_eq!(0, entries.total_bytes());
+        assert_eq!(0, entries.len());
+        assert_eq!(None, entries.get_version("ab", 1));
+
+        // Keys can be stored again, without previous versions or expiry.
+        assert!(entries.insert("f".to_string(), "h".to_string(), None));
+        assert_eq!(Some(&"h".to_string()), entries.get("f"));
+    }
+
+    #[test]
+    fn push_version_keeps_limited_number_of_versions() {
+        let mut entries = Entries::new();
+
+        for value in ["a", "bb", "ccc", "dddd"] {
+            assert!(entries.push_version("k".to_string(), value.to_string(), 2, None));
+        }
+
+        assert_eq!(Some(&"dddd".to_string()), entries.get_version("k", 0));
+        assert_eq!(Some(&"ccc".to_string()), entries.get_version("k", 1));
+        assert_eq!(Some(&"bb".to_string()), entries.get_version("k", 2));
+        assert_eq!(None, entries.get_version("k", 3));
+        assert_eq!(10, entries.total_bytes());
+
+        // Storing a value forgets previous versions.
+        assert!(entries.insert("k".to_string(), "e".to_string(), None));
+        assert_eq!(None, entries.get_version("k", 1));
+        assert_eq!(2, entries.total_bytes());
+    }
+
+    #[test]
+    fn push_version_counts_versions_towards_limit() {
+        let mut entries = Entries::new();
+        let limit = Some(6);
+
+        assert!(entries.push_version("k".to_string(), "ab".to_string(), 1, limit));
+        assert!(entries.push_version("k".to_string(), "cd".to_string(), 1, limit));
+        // The new value and the kept version "cd" do not fit.
+        assert!(!entries.push_version("k".to_string(), "efgh".to_string(), 1, limit));
+        assert_eq!(Some(&"cd".to_string()), entries.get_version("k", 0));
+        assert_eq!(Some(&"ab".to_string()), entries.get_version("k", 1));
+
+        assert_eq!(Some("cd".to_string()), entries.remove("k"));
+        assert_eq!(None, entries.get_version("k", 1));
+        assert_eq!(0, entries.total_bytes());
+    }
+
+    #[test]
+    fn push_version_does_not_keep_expired_value() {
+        let mut entries = Entries::new();
+        entries.enable_wal();
+
+        assert!(entries.push_version("k".to_string(), "a".to_string(), 2, None));
+        assert!(entries.insert_expiring("k".to_string(), "old".to_string(), Instant::now(), None));
+        assert!(entries.push_version("k".to_string(), "new".to_string(), 2, None));
+        assert_eq!(Some(&"new".to_string()), entries.get_version("k", 0));
+        assert_eq!(None, entries.get_version("k", 1));
+        assert_eq!(4, entries.total_bytes());
+
+        let mut replayed = Entries::new();
+        for record in entries.take_wal_records() {
+            replayed.replay(record);
+        }
+        assert_eq!(Some(&"new".to_string()), replayed.get_version("k", 0));
+        assert_eq!(None, replayed.get_version("k", 1));
+        assert_eq!(4, replayed.total_bytes());
+    }
+
+    #[test]
+    fn expired_pairs_are_absent() {
+        let mut entries = Entries::new();
+        let later = Instant::now() + Duration::from_secs(60);
+
+        assert!(entries.insert_expiring("a".to_string(), "x".to_string(), later, None));
+        assert!(entries.insert_expiring("b".to_string(), "y".to_string(), Instant::now(), None));
+        assert_eq!(Some(&"x".to_string()), entries.get("a"));
+        assert_eq!(None, entries.get("b"));
+        assert_eq!(vec![(&"a".to_string(), &"x".to_string())], entries.iter().collect::<Vec<_>>());
+
+        // Expired pairs take space until they are removed.
+        assert_eq!(4, entries.total_bytes());
+        entries.remove_expired("a");
+        entries.remove_expired("b");
+        assert_eq!(2, entries.total_bytes());
+        assert_eq!(1, entries.len());
+    }
+
+    #[test]
+    fn storing_forgets_expiry() {
+        let mut entries = Entries::new();
+
+        assert!(entries.insert_expiring("a".to_string(), "x".to_string(), Instant::now(), None));
+        assert_eq!(None, entries.remove("a"));
+        assert!(entries.insert_expiring("a".to_string(), "x".to_string(), Instant::now(), None));
+        assert!(entries.insert("a".to_string(), "y".to_string(), None));
+        assert_eq!(Some(&"y".to_string()), entries.get("a"));
+    }
+
+    #[test]
+    fn replaying_records_recreates_pairs() {
+        let mut entries = Entries::new();
+        let later = Instant::now() + Duration::from_secs(60);
+
+        for value in ["x", "y", "z"] {
+            assert!(entries.push_version("a".to_string(), value.to_string(), 2, None));
+        }
+        assert!(entries.insert_expiring("b".to_string(), "w".to_string(), later, None));
+        assert!(entries.insert_expiring("c".to_string(), "v".to_string(), Instant::now(), None));
+
+        let mut replayed = Entries::new();
+        for record in entries.records() {
+            replayed.replay(record);
+        }
+        assert_eq!(Some(&"z".to_string()), replayed.get_version("a", 0));
+        assert_eq!(Some(&"y".to_string()), replayed.get_version("a", 1));
+        assert_eq!(Some(&"x".to_string()), replayed.get_version("a", 2));
+        assert_eq!(Some(&later), replayed.expiries.get("b"));
+        // Expired pairs are not recreated.
+        assert_eq!(2, replayed.len());
+        assert_eq!(entries.total_bytes() - 2, replayed.total_bytes());
+    }
+
+    #[test]
+    fn subscribers_receive_stored_values_in_order() {
+        let mut entries = Entries::new();
+        assert!(entries.insert("a".to_string(), "x".to_string(), None));
+
+        let mut changes = entries.subscribe();
+        assert!(entries.insert("b".to_string(), "y".to_string(), None));
+        assert!(entries.push_version("a".to_string(), "z".to_string(), 1, None));
+        // Values that are not stored are not published.
+        assert!(!entries.insert("c".to_string(), "w".to_string(), Some(0)));
+
+        let change = |key: &str, value: &str| Change { key: key.to_string(), value: value.to_string() };
+        assert_eq!(change("b", "y"), changes.try_recv().unwrap());
+        assert_eq!(change("a", "z"), changes.try_recv().unwrap());
+        assert!(changes.try_recv().is_err());
+    }
+}
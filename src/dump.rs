@@ -0,0 +1,222 @@
+// author - Patryk Jędrzejczak
+
+// Binary format of answers to DUMP requests, used for backups. A dump
+// starts with the magic KVSDUMP1, where 1 is the version of the format.
+// Then every pair is written as key_len|key|val_len|val, where lengths
+// are numbers of bytes encoded as 8-byte big-endian integers. The dump
+// ends with u64::MAX in place of key_len, followed by the number of pairs,
+// so a dump that was cut off after any record is detected.
+//
+// In the JSON format, every pair is a line with an object such as
+// {"key":"k","value":"v"}. Values that are not plain text are base64-encoded
+// and marked with "encoding":"base64". The dump ends with a line with
+// the number of pairs, e.g. {"count":1}, for the same reason.
+
+#[cfg(feature = "json")]
+use base64::Engine;
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+#[cfg(feature = "json")]
+use super::response::{is_plain_text, to_base64};
+
+pub(crate) const MAGIC: &[u8] = b"KVSDUMP1";
+// Every dump in the JSON format starts with the first object.
+#[cfg(feature = "json")]
+pub(crate) const JSON_START: &[u8] = b"{";
+const END: u64 = u64::MAX;
+
+// Keys and values in the order they were dumped.
+type Pairs = Vec<(String, String)>;
+
+// Bytes are not a dump, or the dump is corrupted.
+#[derive(Debug)]
+pub(crate) struct InvalidDump;
+
+// Format selected with the argument of DUMP request.
+pub(crate) enum DumpFormat {
+    Binary,
+    #[cfg(feature = "json")]
+    Json
+}
+
+pub(crate) fn encode_dump(pairs: &[(String, String)]) -> Vec<u8> {
+    let mut dump = MAGIC.to_vec();
+    for (key, value) in pairs {
+        for field in [key, value] {
+            dump.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            dump.extend_from_slice(field.as_bytes());
+        }
+    }
+    dump.extend_from_slice(&END.to_be_bytes());
+    dump.extend_from_slice(&(pairs.len() as u64).to_be_bytes());
+    dump
+}
+
+// If bytes start with a complete dump, returns its pairs and its length
+// in bytes. Returns None if the dump is not complete yet and InvalidDump
+// if bytes cannot become a dump.
+pub(crate) fn split_dump(bytes: &[u8]) -> Result<Option<(Pairs, usize)>, InvalidDump> {
+    if !bytes.starts_with(MAGIC) {
+        return if MAGIC.starts_with(bytes) { Ok(None) } else { Err(InvalidDump) };
+    }
+
+    let mut pairs = Vec::new();
+    let mut pos = MAGIC.len();
+    loop {
+        let Some(key_len) = split_number(bytes, &mut pos) else {
+            return Ok(None);
+        };
+        if key_len == END {
+            return match split_number(bytes, &mut pos) {
+                Some(count) if count == pairs.len() as u64 => Ok(Some((pairs, pos))),
+                Some(_) => Err(InvalidDump),
+                None => Ok(None)
+            };
+        }
+
+        let Some(key) = split_field(bytes, &mut pos, key_len)? else {
+            return Ok(None);
+        };
+        let Some(value_len) = split_number(bytes, &mut pos) else {
+            return Ok(None);
+        };
+        let Some(value) = split_field(bytes, &mut pos, value_len)? else {
+            return Ok(None);
+        };
+        pairs.push((key, value));
+    }
+}
+
+#[cfg(feature = "json")]
+pub(crate) fn encode_json_dump(pairs: &[(String, String)]) -> Vec<u8> {
+    let mut lines: Vec<Value> = pairs.iter().map(|(key, value)| {
+        if is_plain_text(value) {
+            json!({ "key": key, "value": value })
+        } else {
+            json!({ "key": key, "value": to_base64(value), "encoding": "base64" })
+        }
+    }).collect();
+    lines.push(json!({ "count": pairs.len() }));
+    lines.iter().map(|line| format!("{line}\n")).collect::<String>().into_bytes()
+}
+
+// Like split_dump, but for the JSON format.
+#[cfg(feature = "json")]
+pub(crate) fn split_json_dump(bytes: &[u8]) -> Result<Option<(Pairs, usize)>, InvalidDump> {
+    let mut pairs = Vec::new();
+    let mut pos = 0;
+    while let Some(len) = bytes[pos..].iter().position(|&byte| byte == b'\n') {
+        let line: Value = serde_json::from_slice(&bytes[pos..pos + len]).map_err(|_| InvalidDump)?;
+        pos += len + 1;
+        if let Some(count) = line.get("count") {
+            return match count.as_u64() {
+                Some(count) if count == pairs.len() as u64 => Ok(Some((pairs, pos))),
+                _ => Err(InvalidDump)
+            };
+        }
+        pairs.push(decode_json_pair(&line).ok_or(InvalidDump)?);
+    }
+
+    // Every line is an object, so anything else cannot become a dump.
+    match bytes.get(pos) {
+        Some(&byte) if byte != b'{' => Err(InvalidDump),
+        _ => Ok(None)
+    }
+}
+
+#[cfg(feature = "json")]
+fn decode_json_pair(line: &Value) -> Option<(String, String)> {
+    let key = line.get("key")?.as_str()?.to_string();
+    let value = line.get("value")?.as_str()?;
+    let value = match line.get("encoding") {
+        None => value.to_string(),
+        Some(encoding) if encoding == "base64" => {
+            let bytes = base64::engine::general_purpose::STANDARD.decode(value).ok()?;
+            String::from_utf8(bytes).ok()?
+        },
+        Some(_) => return None
+    };
+    Some((key, value))
+}
+
+// Reads an 8-byte big-endian integer at pos and moves pos past it.
+fn split_number(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let number = bytes.get(*pos..*pos + 8)?;
+    *pos += 8;
+    Some(u64::from_be_bytes(number.try_into().unwrap()))
+}
+
+// Reads a UTF-8 string of len bytes at pos and moves pos past it.
+fn split_field(bytes: &[u8], pos: &mut usize, len: u64) -> Result<Option<String>, InvalidDump> {
+    let end = usize::try_from(len).ok().and_then(|len| pos.checked_add(len)).ok_or(InvalidDump)?;
+    let Some(field) = bytes.get(*pos..end) else {
+        return Ok(None);
+    };
+    *pos = end;
+    String::from_utf8(field.to_vec()).map(Some).map_err(|_| InvalidDump)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Pairs {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn split_dump_waits_for_whole_dump() {
+        let expected = pairs(&[("k", "zażółć"), ("e", ""), ("d", "a$\r\nb")]);
+        let dump = encode_dump(&expected);
+        for len in 0..dump.len() {
+            assert!(split_dump(&dump[..len]).unwrap().is_none(), "{len}");
+        }
+
+        let mut bytes = dump.clone();
+        bytes.extend_from_slice(b"DONE$");
+        assert_eq!(Some((expected, dump.len())), split_dump(&bytes).unwrap());
+        assert_eq!(Some((vec![], 24)), split_dump(&encode_dump(&[])).unwrap());
+    }
+
+    #[test]
+    fn split_dump_rejects_corrupted_dumps() {
+        let mut wrong_count = encode_dump(&pairs(&[("k", "v")]));
+        *wrong_count.last_mut().unwrap() = 2;
+        let mut invalid_utf8 = encode_dump(&pairs(&[("k", "v")]));
+        invalid_utf8[MAGIC.len() + 8] = 0xff;
+        let mut too_long = MAGIC.to_vec();
+        too_long.extend_from_slice(&(u64::MAX - 1).to_be_bytes());
+
+        for bytes in [b"ERROR$".to_vec(), b"KVSDUMP2".to_vec(), wrong_count, invalid_utf8, too_long] {
+            assert!(split_dump(&bytes).is_err());
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn split_json_dump_waits_for_whole_dump() {
+        let expected = pairs(&[("k", "zażółć"), ("e", ""), ("d", "a$\r\nb")]);
+        let dump = encode_json_dump(&expected);
+        assert!(String::from_utf8_lossy(&dump).contains(r#"{"encoding":"base64","key":"d","value":"YSQNCmI="}"#));
+        for len in 0..dump.len() {
+            assert!(split_json_dump(&dump[..len]).unwrap().is_none(), "{len}");
+        }
+
+        let mut bytes = dump.clone();
+        bytes.extend_from_slice(b"DONE$");
+        assert_eq!(Some((expected, dump.len())), split_json_dump(&bytes).unwrap());
+        assert_eq!(Some((vec![], 12)), split_json_dump(&encode_json_dump(&[])).unwrap());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn split_json_dump_rejects_corrupted_dumps() {
+        let wrong_count = b"{\"key\":\"k\",\"value\":\"v\"}\n{\"count\":2}\n";
+        let wrong_encoding = b"{\"encoding\":\"hex\",\"key\":\"k\",\"value\":\"76\"}\n";
+        let invalid_base64 = b"{\"encoding\":\"base64\",\"key\":\"k\",\"value\":\"%\"}\n";
+
+        for bytes in [&b"ERROR$"[..], b"{\n", wrong_count, wrong_encoding, invalid_base64, &encode_dump(&[])] {
+            assert!(split_json_dump(bytes).is_err());
+        }
+    }
+}
@@ -0,0 +1,123 @@
+// author - Patryk Jędrzejczak
+
+// Subset of HTTP/1.1, so web tools can talk to the server. GET /kv/<key>
+// is translated into LOAD request and PUT /kv/<key> with the value as the
+// body into STORE request. Bodies are delimited with Content-Length, and
+// a request without it has an empty body. Other requests are incorrect.
+
+use super::{TaskError, Request, StoreRequest, LoadRequest, ParseResult};
+
+// If message contains a prefix that is a complete HTTP request, returns
+// Some((request, rest)), like parse_request. If message is incorrect or
+// the request is not supported, returns TaskError. Otherwise, returns None.
+pub fn parse_http_request(message: &str) -> ParseResult<'_> {
+    let Some(head_len) = message.find("\r\n\r\n") else {
+        return Ok(None);
+    };
+    let mut lines = message[..head_len].split("\r\n");
+    let (method, key) = parse_request_line(lines.next().ok_or(TaskError::ParseError)?)?;
+    let body_len = parse_content_length(lines)?;
+
+    let body_start = head_len + 4;
+    let body_end = body_start.checked_add(body_len).ok_or(TaskError::ParseError)?;
+    if message.len() < body_end {
+        return Ok(None);
+    }
+    // Fails if the body ends in the middle of a character.
+    let body = message.get(body_start..body_end).ok_or(TaskError::ParseError)?.to_string();
+
+    let request = match method {
+        "GET" if body.is_empty() => Request::Load(LoadRequest::new(key)),
+        "PUT" => Request::Store(StoreRequest::new(key, body)),
+        _ => return Err(TaskError::ParseError)
+    };
+    Ok(Some((request, &message[body_end..])))
+}
+
+// Parses a line like GET /kv/key HTTP/1.1. Returns the method and the key.
+fn parse_request_line(line: &str) -> Result<(&str, String), TaskError> {
+    let [method, target, version] = line.split(' ').collect::<Vec<_>>()[..] else {
+        return Err(TaskError::ParseError);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(TaskError::ParseError);
+    }
+
+    // Keys have the same form as in the default protocol,
+    // so they never need to be percent-encoded.
+    let key = target.strip_prefix("/kv/").ok_or(TaskError::ParseError)?;
+    if !key.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_') {
+        return Err(TaskError::ParseError);
+    }
+    Ok((method, key.to_string()))
+}
+
+// Returns the value of Content-Length header, or 0 if there is none.
+// Chunked bodies are not supported.
+fn parse_content_length<'a>(headers: impl Iterator<Item = &'a str>) -> Result<usize, TaskError> {
+    let mut content_length = None;
+    for header in headers {
+        let (name, value) = header.split_once(':').ok_or(TaskError::ParseError)?;
+        if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            return Err(TaskError::ParseError);
+        }
+        if name.eq_ignore_ascii_case("Content-Length") {
+            let len = value.trim().parse().map_err(|_| TaskError::ParseError)?;
+            if content_length.replace(len).is_some_and(|previous| previous != len) {
+                return Err(TaskError::ParseError);
+            }
+        }
+    }
+    Ok(content_length.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_parsing::take_request;
+
+    #[test]
+    fn parse_http_request_waits_for_whole_request() {
+        let request = "PUT /kv/key HTTP/1.1\r\nHost: localhost\r\ncontent-length: 10\r\n\r\nzażółć";
+        for len in (0..request.len()).filter(|len| request.is_char_boundary(*len)) {
+            assert!(take_request(&mut request[..len].to_string(), parse_http_request).unwrap().is_none());
+        }
+
+        let mut message = format!("{request}GET /kv/key HTTP/1.1\r\n\r\nGET");
+        assert!(matches!(
+            take_request(&mut message, parse_http_request),
+            Ok(Some(Request::Store(request))) if request.key == "key" && request.value == "zażółć"
+        ));
+        assert!(matches!(take_request(&mut message, parse_http_request), Ok(Some(Request::Load(request))) if request.key == "key"));
+        assert_eq!("GET", message);
+    }
+
+    #[test]
+    fn request_without_content_length_has_empty_body() {
+        let mut message = "PUT /kv/k HTTP/1.1\r\n\r\n".to_string();
+        assert!(matches!(
+            take_request(&mut message, parse_http_request),
+            Ok(Some(Request::Store(request))) if request.key == "k" && request.value.is_empty()
+        ));
+    }
+
+    #[test]
+    fn parse_http_request_rejects_unsupported_requests() {
+        let incorrect_messages = vec![
+            "POST /kv/k HTTP/1.1\r\n\r\n",
+            "GET /k HTTP/1.1\r\n\r\n",
+            "GET /kv/k- HTTP/1.1\r\n\r\n",
+            "GET /kv/k HTTP/2\r\n\r\n",
+            "GET /kv/k\r\n\r\n",
+            "GET /kv/k HTTP/1.1\r\nContent-Length: 1\r\n\r\nv",
+            "PUT /kv/k HTTP/1.1\r\nContent-Length: x\r\n\r\n",
+            "PUT /kv/k HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nvv",
+            "PUT /kv/k HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "PUT /kv/k HTTP/1.1\r\nHost\r\n\r\n"
+        ];
+
+        for message in incorrect_messages {
+            assert!(take_request(&mut message.to_string(), parse_http_request).is_err(), "{message}");
+        }
+    }
+}
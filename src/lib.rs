@@ -1,33 +1,194 @@
 // author - Patryk Jędrzejczak
 
-use tokio::net::TcpStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+use std::fmt;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+mod audit;
+#[cfg(feature = "encryption")]
+mod cipher;
+mod client;
+mod config;
+mod connections;
+mod database;
+mod dump;
+mod http;
+mod metrics;
 mod request_parsing;
+mod resp;
+mod response;
+mod stream;
+mod wal;
 
-use request_parsing::{try_parse_request};
-
-// Type of the database of the key-value pairs.
-pub type Db = Arc<Mutex<HashMap<String, String>>>;
+pub use audit::AuditLog;
+pub use client::{Client, ClientError};
+pub use config::Config;
+use connections::Activity;
+pub use database::{Database, Db};
+use database::Entries;
+use dump::DumpFormat;
+pub use metrics::serve_metrics;
+use http::parse_http_request;
+use request_parsing::{parse_request, parse_framed_request, could_become_requests, ParseResult};
+// Exported only so that benchmarks can measure parsing.
+#[doc(hidden)]
+pub use request_parsing::try_parse_request;
+use resp::parse_resp_request;
+use response::{encode_chunks, Response, ResponseFormat};
+use stream::{Stream, connection_stream, secure_stream};
 
 // Struct keeping data of a single task. Its only purpose is
 // simplifying definitions of some functions.
 pub struct TaskData {
-    socket: TcpStream,
-    db: Db
+    stream: Box<dyn Stream>,
+    // Address of the client, if it is known. Recorded in the audit log.
+    peer: Option<SocketAddr>,
+    db: Db,
+    config: Arc<Config>,
+    response_format: ResponseFormat,
+    // Prepended to keys of all requests. Set with PREFIX request.
+    key_prefix: String,
+    // Prepended to keys of all requests before key_prefix, e.g. ns/ for
+    // namespace ns. Empty for the default namespace. Set with SELECT request.
+    namespace: String,
+    // Responses withheld since NOFLUSH request. None if responses
+    // are written immediately, which is the default.
+    pending_responses: Option<Vec<u8>>,
+    // Whether the connection is being closed because of an error, either
+    // because reading or writing failed or because ERROR response was sent.
+    // Nothing is written to the stream anymore then.
+    closing: bool,
+    // Whether the client sent QUIT request. Following requests are not
+    // processed and the connection is finished, see handle_connection.
+    quit: bool,
+    protocol: Protocol,
+    // Whether changes are answered once they are queued for the write-ahead
+    // log instead of once they are on the disk. Set with ACK request.
+    queued_acks: bool,
+    // Maximum length of chunks in which values are sent in FOUND answers,
+    // see send_found_response. Values are not chunked if it is None.
+    // Set with CHUNKED request.
+    chunk_bytes: Option<usize>
+}
+
+// Protocol in which the client sends requests.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Protocol {
+    // Default protocol, framed if the configuration says so.
+    Default,
+    // Subset of RESP, see the resp module.
+    Resp,
+    // Subset of HTTP, see the http module.
+    Http
 }
 
 impl TaskData {
-    pub fn new(socket: TcpStream, db: Db) -> Self {
-        TaskData { socket, db }
+    pub(crate) fn new(stream: Box<dyn Stream>, peer: Option<SocketAddr>, db: Db, config: Arc<Config>) -> Self {
+        TaskData::with_stream(connection_stream(stream, &config), peer, db, config)
+    }
+
+    fn with_stream(stream: Box<dyn Stream>, peer: Option<SocketAddr>, db: Db, config: Arc<Config>) -> Self {
+        TaskData {
+            peer,
+            stream,
+            db,
+            config,
+            response_format: ResponseFormat::Text,
+            key_prefix: String::new(),
+            namespace: String::new(),
+            pending_responses: None,
+            closing: false,
+            quit: false,
+            protocol: Protocol::Default,
+            queued_acks: false,
+            chunk_bytes: None
+        }
+    }
+
+    // Creates data of a connection of a Redis client, see the resp module.
+    // Such connections are never framed or compressed.
+    pub(crate) fn new_resp(stream: Box<dyn Stream>, peer: Option<SocketAddr>, db: Db, config: Arc<Config>) -> Self {
+        TaskData {
+            response_format: ResponseFormat::Resp,
+            protocol: Protocol::Resp,
+            ..TaskData::with_stream(stream, peer, db, config)
+        }
+    }
+
+    // Creates data of a connection of an HTTP client, see the http module.
+    // Such connections are never framed or compressed.
+    pub(crate) fn new_http(stream: Box<dyn Stream>, peer: Option<SocketAddr>, db: Db, config: Arc<Config>) -> Self {
+        TaskData {
+            response_format: ResponseFormat::Http,
+            protocol: Protocol::Http,
+            ..TaskData::with_stream(stream, peer, db, config)
+        }
     }
 }
 
 pub enum Request {
     Store(StoreRequest),
-    Load(LoadRequest)
+    StoreCheck(StoreRequest),
+    StoreExpiring(StoreExpiringRequest),
+    Load(LoadRequest),
+    LoadTransformed(LoadTransformedRequest),
+    Delete(DeleteRequest),
+    Exists(ExistsRequest),
+    Wait(WaitRequest),
+    Init(InitRequest),
+    Copy(CopyRequest),
+    GetOrSet(GetOrSetRequest),
+    AppendIfNew(AppendIfNewRequest),
+    PushVersion(PushVersionRequest),
+    GetVersion(GetVersionRequest),
+    MaxSet(ExtremumRequest),
+    MinSet(ExtremumRequest),
+    Increment(IncrementRequest),
+    DecrementFloor(DecrementFloorRequest),
+    DecrementDelete(DecrementDeleteRequest),
+    Prefix(PrefixRequest),
+    Select(SelectRequest),
+    Ack(AckRequest),
+    Chunked(ChunkedRequest),
+    PatternSubscribe(PatternSubscribeRequest),
+    MultiDelete(MultiDeleteRequest),
+    MultiExists(MultiExistsRequest),
+    MultiGet(MultiGetRequest),
+    CompareAndSwap(CompareAndSwapRequest),
+    MultiCompareAndSet(MultiCompareAndSetRequest),
+    Snapshot(SnapshotRequest),
+    Keys,
+    Scan(ScanRequest),
+    Usage,
+    Count,
+    Dump(DumpRequest),
+    Limits,
+    Conns,
+    Time,
+    Ping,
+    NoFlush,
+    Flush,
+    FlushAll,
+    Quit,
+    #[cfg(feature = "json")]
+    Json,
+    #[cfg(feature = "json")]
+    Metrics,
+    Peek,
+    SetConfig(SetConfigRequest),
+    Traced(TracedRequest)
 }
 
 pub struct StoreRequest {
@@ -35,42 +196,578 @@ pub struct StoreRequest {
     value: String
 }
 
+pub struct StoreExpiringRequest {
+    key: String,
+    value: String,
+    ttl: u32 // In seconds.
+}
+
 pub struct LoadRequest {
     key: String
 }
 
+pub struct LoadTransformedRequest {
+    key: String,
+    // Name of the transformation, which may be unknown.
+    transform: String
+}
+
+pub struct DeleteRequest {
+    key: String
+}
+
+pub struct ExistsRequest {
+    key: String
+}
+
+pub struct WaitRequest {
+    key: String,
+    timeout: u32 // In milliseconds.
+}
+
+pub struct InitRequest {
+    key: String,
+    value: String
+}
+
+pub struct CopyRequest {
+    source: String,
+    destination: String
+}
+
+pub struct GetOrSetRequest {
+    key: String,
+    default: String
+}
+
+pub struct AppendIfNewRequest {
+    key: String,
+    // Item appended to the value of the key.
+    value: String
+}
+
+pub struct PushVersionRequest {
+    key: String,
+    value: String
+}
+
+pub struct GetVersionRequest {
+    key: String,
+    index: usize // 0 means the current value.
+}
+
+// MAXSET or MINSET request.
+pub struct ExtremumRequest {
+    key: String,
+    value: i64
+}
+
+// INCR or DECR request.
+pub struct IncrementRequest {
+    key: String,
+    delta: i64 // 1 for INCR request, -1 for DECR request.
+}
+
+pub struct DecrementFloorRequest {
+    key: String,
+    floor: i64
+}
+
+pub struct DecrementDeleteRequest {
+    key: String
+}
+
+pub struct PrefixRequest {
+    prefix: String
+}
+
+pub struct SelectRequest {
+    namespace: String
+}
+
+pub struct AckRequest {
+    mode: String
+}
+
+pub struct ChunkedRequest {
+    chunk_bytes: usize
+}
+
+pub struct MultiGetRequest {
+    keys: Vec<String>
+}
+
+pub struct CompareAndSwapRequest {
+    key: String,
+    expected: String,
+    value: String
+}
+
+pub struct MultiCompareAndSetRequest {
+    // Pairs of keys and values they are expected to have.
+    conditions: Vec<(String, String)>,
+    key: String,
+    value: String
+}
+
+pub struct SnapshotRequest {
+    name: String
+}
+
+pub struct PatternSubscribeRequest {
+    prefix: String
+}
+
+pub struct ScanRequest {
+    prefix: String
+}
+
+pub struct DumpRequest {
+    // Name of the format, which may be unknown.
+    format: String
+}
+
+pub struct SetConfigRequest {
+    // Name of the setting, which may be unknown or immutable.
+    name: String,
+    value: String
+}
+
+// Request preceded by trace:id:, processed in a span carrying the id.
+pub struct TracedRequest {
+    id: String,
+    request: Box<Request>
+}
+
+pub struct MultiDeleteRequest {
+    keys: Vec<String>
+}
+
+pub struct MultiExistsRequest {
+    keys: Vec<String>
+}
+
 impl StoreRequest {
     fn new(key: String, value: String) -> Self {
         StoreRequest { key, value }
     }
 }
 
+impl StoreExpiringRequest {
+    fn new(key: String, value: String, ttl: u32) -> Self {
+        StoreExpiringRequest { key, value, ttl }
+    }
+}
+
 impl LoadRequest {
     fn new(key: String) -> Self {
         LoadRequest { key }
     }
 }
 
+impl DeleteRequest {
+    fn new(key: String) -> Self {
+        DeleteRequest { key }
+    }
+}
+
+impl ExistsRequest {
+    fn new(key: String) -> Self {
+        ExistsRequest { key }
+    }
+}
+
+impl LoadTransformedRequest {
+    fn new(key: String, transform: String) -> Self {
+        LoadTransformedRequest { key, transform }
+    }
+}
+
+impl WaitRequest {
+    fn new(key: String, timeout: u32) -> Self {
+        WaitRequest { key, timeout }
+    }
+}
+
+impl InitRequest {
+    fn new(key: String, value: String) -> Self {
+        InitRequest { key, value }
+    }
+}
+
+impl CopyRequest {
+    fn new(source: String, destination: String) -> Self {
+        CopyRequest { source, destination }
+    }
+}
+
+impl GetOrSetRequest {
+    fn new(key: String, default: String) -> Self {
+        GetOrSetRequest { key, default }
+    }
+}
+
+impl AppendIfNewRequest {
+    fn new(key: String, value: String) -> Self {
+        AppendIfNewRequest { key, value }
+    }
+}
+
+impl PushVersionRequest {
+    fn new(key: String, value: String) -> Self {
+        PushVersionRequest { key, value }
+    }
+}
+
+impl GetVersionRequest {
+    fn new(key: String, index: usize) -> Self {
+        GetVersionRequest { key, index }
+    }
+}
+
+impl ExtremumRequest {
+    fn new(key: String, value: i64) -> Self {
+        ExtremumRequest { key, value }
+    }
+}
+
+impl IncrementRequest {
+    fn new(key: String, delta: i64) -> Self {
+        IncrementRequest { key, delta }
+    }
+}
+
+impl DecrementFloorRequest {
+    fn new(key: String, floor: i64) -> Self {
+        DecrementFloorRequest { key, floor }
+    }
+}
+
+impl DecrementDeleteRequest {
+    fn new(key: String) -> Self {
+        DecrementDeleteRequest { key }
+    }
+}
+
+impl PrefixRequest {
+    fn new(prefix: String) -> Self {
+        PrefixRequest { prefix }
+    }
+}
+
+impl SelectRequest {
+    fn new(namespace: String) -> Self {
+        SelectRequest { namespace }
+    }
+}
+
+impl AckRequest {
+    fn new(mode: String) -> Self {
+        AckRequest { mode }
+    }
+}
+
+impl ChunkedRequest {
+    fn new(chunk_bytes: usize) -> Self {
+        ChunkedRequest { chunk_bytes }
+    }
+}
+
+impl MultiGetRequest {
+    fn new(keys: Vec<String>) -> Self {
+        MultiGetRequest { keys }
+    }
+}
+
+impl CompareAndSwapRequest {
+    fn new(key: String, expected: String, value: String) -> Self {
+        CompareAndSwapRequest { key, expected, value }
+    }
+}
+
+impl MultiCompareAndSetRequest {
+    fn new(conditions: Vec<(String, String)>, key: String, value: String) -> Self {
+        MultiCompareAndSetRequest { conditions, key, value }
+    }
+}
+
+impl SnapshotRequest {
+    fn new(name: String) -> Self {
+        SnapshotRequest { name }
+    }
+}
+
+impl PatternSubscribeRequest {
+    fn new(prefix: String) -> Self {
+        PatternSubscribeRequest { prefix }
+    }
+}
+
+impl ScanRequest {
+    fn new(prefix: String) -> Self {
+        ScanRequest { prefix }
+    }
+}
+
+impl DumpRequest {
+    fn new(format: String) -> Self {
+        DumpRequest { format }
+    }
+}
+
+impl SetConfigRequest {
+    fn new(name: String, value: String) -> Self {
+        SetConfigRequest { name, value }
+    }
+}
+
+impl TracedRequest {
+    fn new(id: String, request: Request) -> Self {
+        TracedRequest { id, request: Box::new(request) }
+    }
+}
+
+impl MultiDeleteRequest {
+    fn new(keys: Vec<String>) -> Self {
+        MultiDeleteRequest { keys }
+    }
+}
+
+impl MultiExistsRequest {
+    fn new(keys: Vec<String>) -> Self {
+        MultiExistsRequest { keys }
+    }
+}
+
+impl Request {
+    // Name of the request in the default protocol. Used in logs, so it
+    // does not depend on anything the client sent, e.g. on values.
+    fn name(&self) -> &'static str {
+        match self {
+            Request::Store(_) => "STORE",
+            Request::StoreCheck(_) => "STORECHECK",
+            Request::StoreExpiring(_) => "STOREX",
+            Request::Load(_) => "LOAD",
+            Request::LoadTransformed(_) => "LOADT",
+            Request::Delete(_) => "DELETE",
+            Request::Exists(_) => "EXISTS",
+            Request::Wait(_) => "WAIT",
+            Request::Init(_) => "INIT",
+            Request::Copy(_) => "COPY",
+            Request::GetOrSet(_) => "GETORSET",
+            Request::AppendIfNew(_) => "APPENDIFNEW",
+            Request::PushVersion(_) => "PUSHVER",
+            Request::GetVersion(_) => "GETVER",
+            Request::MaxSet(_) => "MAXSET",
+            Request::MinSet(_) => "MINSET",
+            Request::Increment(request) if request.delta < 0 => "DECR",
+            Request::Increment(_) => "INCR",
+            Request::DecrementFloor(_) => "DECRFLOOR",
+            Request::DecrementDelete(_) => "DECRDEL",
+            Request::Prefix(_) => "PREFIX",
+            Request::Select(_) => "SELECT",
+            Request::Ack(_) => "ACK",
+            Request::Chunked(_) => "CHUNKED",
+            Request::PatternSubscribe(_) => "PSUBSCRIBE",
+            Request::MultiDelete(_) => "MDEL",
+            Request::MultiExists(_) => "MEXISTS",
+            Request::MultiGet(_) => "MGET",
+            Request::CompareAndSwap(_) => "CAS",
+            Request::MultiCompareAndSet(_) => "MCAS",
+            Request::Snapshot(_) => "SNAPSHOT",
+            Request::Keys => "KEYS",
+            Request::Scan(_) => "SCAN",
+            Request::Usage => "USAGE",
+            Request::Count => "COUNT",
+            Request::Dump(_) => "DUMP",
+            Request::Limits => "LIMITS",
+            Request::Conns => "CONNS",
+            Request::Time => "TIME",
+            Request::Ping => "PING",
+            Request::NoFlush => "NOFLUSH",
+            Request::Flush => "FLUSH",
+            Request::FlushAll => "FLUSHALL",
+            Request::Quit => "QUIT",
+            #[cfg(feature = "json")]
+            Request::Json => "JSON",
+            #[cfg(feature = "json")]
+            Request::Metrics => "METRICS",
+            Request::Peek => "PEEK",
+            Request::SetConfig(_) => "SETCONFIG",
+            Request::Traced(request) => request.request.name()
+        }
+    }
+
+    // Prepends prefix to all keys of the request.
+    fn add_key_prefix(&mut self, prefix: &str) {
+        let keys = match self {
+            Request::Store(request) | Request::StoreCheck(request) => vec![&mut request.key],
+            Request::StoreExpiring(request) => vec![&mut request.key],
+            Request::Load(request) => vec![&mut request.key],
+            Request::LoadTransformed(request) => vec![&mut request.key],
+            Request::Delete(request) => vec![&mut request.key],
+            Request::Exists(request) => vec![&mut request.key],
+            Request::Wait(request) => vec![&mut request.key],
+            Request::Init(request) => vec![&mut request.key],
+            Request::Copy(request) => vec![&mut request.source, &mut request.destination],
+            Request::GetOrSet(request) => vec![&mut request.key],
+            Request::AppendIfNew(request) => vec![&mut request.key],
+            Request::PushVersion(request) => vec![&mut request.key],
+            Request::GetVersion(request) => vec![&mut request.key],
+            Request::MaxSet(request) | Request::MinSet(request) => vec![&mut request.key],
+            Request::Increment(request) => vec![&mut request.key],
+            Request::DecrementFloor(request) => vec![&mut request.key],
+            Request::DecrementDelete(request) => vec![&mut request.key],
+            Request::PatternSubscribe(request) => vec![&mut request.prefix],
+            Request::MultiDelete(request) => request.keys.iter_mut().collect(),
+            Request::MultiExists(request) => request.keys.iter_mut().collect(),
+            Request::MultiGet(request) => request.keys.iter_mut().collect(),
+            Request::CompareAndSwap(request) => vec![&mut request.key],
+            Request::MultiCompareAndSet(request) => request.conditions.iter_mut()
+                .map(|(key, _)| key)
+                .chain([&mut request.key])
+                .collect(),
+            _ => vec![]
+        };
+
+        for key in keys {
+            key.insert_str(0, prefix);
+        }
+    }
+}
+
 // Error returned when something goes wrong during a task's work.
-// We do not care what really happened because in every case we just
-// finish the task and close the connection with the client.
+// In every case the task is finished and the connection with the client
+// is closed, but the cause is logged and decides the reason of ERROR
+// response, see reason.
 #[derive(Debug)]
-pub struct TaskError;
+pub enum TaskError {
+    // The request is incorrect, or is not available with this configuration.
+    ParseError,
+    // The message is not valid UTF-8.
+    InvalidUtf8,
+    // Reading from or writing to the connection or a file failed.
+    Io(io::Error),
+    // The channel of changes that PSUBSCRIBE request receives from is closed.
+    ChannelClosed,
+    // PSUBSCRIBE request fell behind the channel of changes by the given
+    // number of changes, so some of them would not be reported.
+    Lagged(u64),
+    // A lock is poisoned, because a task panicked while holding it.
+    Poisoned
+}
+
+impl TaskError {
+    // Reason reported to the client in ERROR response.
+    fn reason(&self) -> &'static str {
+        match self {
+            TaskError::ParseError | TaskError::InvalidUtf8 => "bad_request",
+            TaskError::Lagged(_) => "lagged",
+            TaskError::Io(_) | TaskError::ChannelClosed | TaskError::Poisoned => "internal"
+        }
+    }
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskError::ParseError => write!(f, "incorrect request"),
+            TaskError::InvalidUtf8 => write!(f, "message is not valid UTF-8"),
+            TaskError::Io(error) => write!(f, "I/O error: {error}"),
+            TaskError::ChannelClosed => write!(f, "channel of changes closed"),
+            TaskError::Lagged(skipped) => write!(f, "subscriber lagged behind by {skipped} changes"),
+            TaskError::Poisoned => write!(f, "lock poisoned by a panicked task")
+        }
+    }
+}
+
+impl std::error::Error for TaskError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TaskError::Io(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+// Handles a connection of the default protocol over any stream, e.g. over
+// an in-memory pipe created with tokio::io::duplex, so the server can be
+// tested without sockets. The stream is wrapped according to config, like
+// accepted sockets are, see handle_connection.
+pub async fn handle_stream(stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static, db: Db, config: Arc<Config>) {
+    handle_connection(TaskData::new(Box::new(stream), None, db, config)).await
+}
 
 // Handles receiving requests from a single client.
 // When execution of the function ends, connection also ends.
+// The connection is also closed, without writing withheld responses,
+// if reap_idle_connections finds that it has been idle for too long.
+// If writing a response fails, e.g. because the client closed the
+// connection in the middle of a batch of requests, the remaining requests
+// of the batch are not processed and withheld responses are dropped.
+// Responses written before the failure are not taken back.
+// If the client closes only its writing half, responses to all complete
+// requests it sent are still written, see finish_connection. The same
+// happens when the server is shutting down, see serve.
 pub async fn handle_connection(mut data: TaskData) {
-    static BUF_SIZE: usize = 1024;
-    let mut buf = vec![0; BUF_SIZE];
+    let Ok(active_connection) = ActiveConnection::new(data.db.clone()) else { return };
+    info!(protocol = ?data.protocol, "connection opened");
+    let mut buf = vec![0; data.config.read_buffer_bytes.get()];
     let mut message = String::new(); // Fragment of the message read so far.
+    let mut incomplete = Vec::new(); // Bytes of a character split between reads.
 
     loop {
-        match data.socket.read(&mut buf).await {
-            Ok(0) | Err(_) => return,
-            Ok(read_num) => {
-                buf[0..read_num].iter().for_each(|byte| message.push(*byte as char));
+        let read = tokio::select! {
+            // Requests received before shutting down are still processed.
+            biased;
+            read = read_retrying(&mut data.stream, &mut buf) => read,
+            _ = active_connection.activity.reaped() => {
+                data.db.metrics.record_connection_reaped();
+                info!("idle connection reaped");
+                return
+            },
+            // The connection is finished as if the client closed it.
+            _ = active_connection.db.connections.shutting_down() => Ok(0)
+        };
 
-                if process_message(&mut message, &mut data).await.is_err() {
+        match read {
+            Ok(0) => {
+                // Ending of the connection cannot be reported to anyone.
+                let _ = finish_connection(&mut data).await;
+                info!("connection closed");
+                return
+            },
+            Err(error) => {
+                warn!(%error, "reading failed, closing connection");
+                return
+            },
+            Ok(read_num) => {
+                if active_connection.activity.touch().is_err() {
+                    return
+                }
+                let processed = match push_utf8(&mut message, &mut incomplete, &buf[0..read_num]) {
+                    Ok(()) => process_message(&mut message, &mut data).await,
+                    Err(error) => {
+                        warn!(%error, "closing connection");
+                        send_error_response(&mut data, error.reason()).await;
+                        Err(error)
+                    }
+                };
+                if processed.is_ok() && data.quit {
+                    let _ = finish_connection(&mut data).await;
+                    info!("connection closed after QUIT");
+                    return
+                }
+                // Complete requests are already processed, so what is left
+                // is a single unfinished request.
+                let too_large = message.len() + incomplete.len() > data.config.max_message_bytes;
+                if processed.is_ok() && too_large {
+                    warn!(bytes = message.len() + incomplete.len(), "unfinished request is too large");
+                    send_error_response(&mut data, "too_large").await;
+                }
+                if processed.is_err() || too_large {
+                    data.db.metrics.record_error();
+                    info!("connection closed after an error");
                     return
                 }
             }
@@ -78,68 +775,1481 @@ pub async fn handle_connection(mut data: TaskData) {
     }
 }
 
-// Processes message until it has a prefix being a STORE or LOAD request.
-// Returns TaskError, if message is for sure incorrect.
-async fn process_message(message: &mut String, data: &mut TaskData) -> Result<(), TaskError> {
-    loop {
-        match try_parse_request(message) {
-            Err(_) => return Err(TaskError),
-            Ok(None) => return Ok(()),
-            Ok(Some(request)) => process_request(request, data).await?
-        }
-    }
+// Finishes a connection after the client stopped sending requests.
+// All complete requests have already been processed, so it only writes
+// responses withheld since NOFLUSH request, as FLUSH request cannot come
+// anymore, and closes the writing half of the connection.
+async fn finish_connection(data: &mut TaskData) -> Result<(), TaskError> {
+    process_flush_request(data).await?;
+    data.stream.shutdown().await.map_err(TaskError::Io)
 }
 
-async fn process_request(request: Request, data: &mut TaskData) -> Result<(), TaskError> {
-    match request {
-        Request::Store(request) => process_store_request(request, data).await,
-        Request::Load(request) => process_load_request(request, data).await
+// Counts a connection as active and registers its activity until it is
+// dropped, so the connection stops being counted however handling it ends.
+struct ActiveConnection {
+    db: Db,
+    id: u64,
+    activity: Arc<Activity>
+}
+
+impl ActiveConnection {
+    fn new(db: Db) -> Result<Self, TaskError> {
+        let (id, activity) = db.connections.open()?;
+        db.metrics.record_connection_opened();
+        Ok(ActiveConnection { db, id, activity })
     }
 }
 
-async fn process_store_request(request: StoreRequest, data: &mut TaskData) -> Result<(), TaskError> {
-    match data.db.lock() {
-        Ok(mut db) => {
-            db.insert(request.key, request.value);
-        },
-        Err(_) => return Err(TaskError)
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.db.metrics.record_connection_closed();
+        // Nothing can be done if the registry is poisoned.
+        let _ = self.db.connections.close(self.id);
     }
+}
 
-    send_done_response(&mut data.socket).await
+// Accepts connections on listener and handles them until shutdown
+// completes. Then stops accepting connections, lets open connections
+// process requests they have already received, and returns after all
+// of them are closed. Responses withheld since NOFLUSH request are
+// written before closing, like when the client closes the connection.
+pub async fn serve(listener: TcpListener, db: Db, config: Arc<Config>, shutdown: impl Future<Output = ()>) {
+    serve_with(listener, db, config, shutdown, TaskData::new).await
 }
 
-async fn process_load_request(request: LoadRequest, data: &mut TaskData) -> Result<(), TaskError> {
-    let value;
-    match data.db.lock() {
-        Ok(db) => {
-            value = db.get(&request.key).map(|val| val.clone());
-        },
-        Err(_) => return Err(TaskError)
-    }
+// Works like serve, but for Redis clients speaking RESP.
+pub async fn serve_resp(listener: TcpListener, db: Db, config: Arc<Config>, shutdown: impl Future<Output = ()>) {
+    serve_with(listener, db, config, shutdown, TaskData::new_resp).await
+}
 
-    match value {
-        None => send_not_found_response(&mut data.socket).await,
-        Some(value) => send_found_response(&mut data.socket, value).await
+// Works like serve, but for HTTP clients.
+pub async fn serve_http(listener: TcpListener, db: Db, config: Arc<Config>, shutdown: impl Future<Output = ()>) {
+    serve_with(listener, db, config, shutdown, TaskData::new_http).await
+}
+
+// Creates data of a connection from its stream and the address of the client.
+type NewTaskData = fn(Box<dyn Stream>, Option<SocketAddr>, Db, Arc<Config>) -> TaskData;
+
+async fn serve_with(
+    listener: TcpListener,
+    db: Db,
+    config: Arc<Config>,
+    shutdown: impl Future<Output = ()>,
+    task_data: NewTaskData
+) {
+    let mut connections = JoinSet::new();
+    let permits = config.max_connections.map(|max_connections| Arc::new(Semaphore::new(max_connections)));
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            (socket, peer, permit) = accept_within_limit(&listener, permits.as_ref()) => {
+                // Events of the connection are tagged with the address of the client.
+                let span = info_span!("connection", %peer);
+                let (db, config) = (db.clone(), config.clone());
+                // The TLS handshake is done by the task of the connection,
+                // so slow clients do not delay accepting other connections.
+                connections.spawn(async move {
+                    match secure_stream(socket, &config).await {
+                        Ok(stream) => handle_connection(task_data(stream, Some(peer), db, config)).await,
+                        Err(error) => {
+                            db.metrics.record_error();
+                            warn!(%error, "TLS handshake failed, closing connection");
+                        }
+                    }
+                    drop(permit);
+                }.instrument(span));
+            },
+            // Finished connections are forgotten, so they do not pile up.
+            Some(_) = connections.join_next() => (),
+            _ = &mut shutdown => break
+        }
     }
+
+    drop(listener);
+    info!(open_connections = connections.len(), "shutting down");
+    db.connections.shut_down();
+    while connections.join_next().await.is_some() {}
+}
+
+// Accepts a connection once there is a free permit, if the number of
+// connections is limited. Until then, new connections wait in the queue
+// of the listener, so clients are slowed down instead of refused.
+async fn accept_within_limit(
+    listener: &TcpListener,
+    permits: Option<&Arc<Semaphore>>
+) -> (TcpStream, SocketAddr, Option<OwnedSemaphorePermit>) {
+    let permit = match permits {
+        // The semaphore is never closed.
+        Some(permits) => Some(permits.clone().acquire_owned().await.unwrap()),
+        None => None
+    };
+    let (socket, peer) = accept_retrying(|| listener.accept()).await;
+    (socket, peer, permit)
 }
 
-async fn send_done_response(socket: &mut TcpStream) -> Result<(), TaskError> {
-    match socket.write("DONE$".as_bytes()).await {
-        Ok(_) => Ok(()),
-        Err(_) => Err(TaskError)
+// Time to wait after accepting a connection failed before trying again.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// Calls accept until it succeeds. Accepting fails e.g. when the process
+// runs out of file descriptors under a flood of connections, or when
+// a client gives up before its connection is accepted. Neither should stop
+// the server, and waiting gives handled connections time to be closed.
+pub(crate) async fn accept_retrying<T, F: Future<Output = io::Result<T>>>(mut accept: impl FnMut() -> F) -> T {
+    loop {
+        match accept().await {
+            Ok(accepted) => return accepted,
+            Err(error) => {
+                warn!(%error, "accepting a connection failed, retrying");
+                time::sleep(ACCEPT_RETRY_DELAY).await;
+            }
+        }
     }
 }
 
-async fn send_found_response(socket: &mut TcpStream, value: String) -> Result<(), TaskError> {
-    match socket.write(format!("FOUND${value}$").as_bytes()).await {
-        Ok(_) => Ok(()),
-        Err(_) => Err(TaskError)
+// Closes connections of db that have not received anything for at least
+// idle_timeout, or the timeout set with SETCONFIG request. Runs until
+// the registry of connections gets poisoned, so it should be spawned
+// as a separate task.
+pub async fn reap_idle_connections(db: Db, idle_timeout: Duration) {
+    loop {
+        let Ok(current) = db.settings.idle_timeout(idle_timeout) else {
+            return
+        };
+        // Connections are closed at most half of the timeout too late,
+        // and a timeout changed with SETCONFIG request applies within a second.
+        let period = current.map_or(Duration::from_secs(1), |current| current / 2);
+        time::sleep(period.clamp(Duration::from_millis(1), Duration::from_secs(1))).await;
+        if current.is_some_and(|current| db.connections.reap(current).is_err()) {
+            return
+        }
     }
 }
 
-async fn send_not_found_response(socket: &mut TcpStream) -> Result<(), TaskError> {
-    match socket.write("NOTFOUND$".as_bytes()).await {
-        Ok(_) => Ok(()),
-        Err(_) => Err(TaskError)
+// Appends bytes to message, decoding them as UTF-8. Bytes of a character
+// split between reads are kept in incomplete until the rest of them comes.
+// Returns TaskError if bytes are not valid UTF-8.
+fn push_utf8(message: &mut String, incomplete: &mut Vec<u8>, bytes: &[u8]) -> Result<(), TaskError> {
+    incomplete.extend_from_slice(bytes);
+    let valid_len = match std::str::from_utf8(incomplete) {
+        Ok(valid) => valid.len(),
+        // The last character may be incomplete, but not invalid.
+        Err(error) if error.error_len().is_none() => error.valid_up_to(),
+        Err(_) => return Err(TaskError::InvalidUtf8)
+    };
+
+    message.push_str(std::str::from_utf8(&incomplete[..valid_len]).map_err(|_| TaskError::InvalidUtf8)?);
+    incomplete.drain(..valid_len);
+    Ok(())
+}
+
+// Reads from reader like AsyncReadExt::read, but retries reads interrupted
+// by a signal instead of returning ErrorKind::Interrupted.
+async fn read_retrying<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buf).await {
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            result => return result
+        }
+    }
+}
+
+// Processes message until it has a prefix being a correct request
+// (or a complete frame, if the framed protocol is used).
+// Returns TaskError, if message is for sure incorrect.
+// Processed requests are removed from message all at once at the end, so
+// the rest of a long batch is not copied after every single request.
+async fn process_message(message: &mut String, data: &mut TaskData) -> Result<(), TaskError> {
+    // Length of the processed part of message.
+    let mut processed_len = 0;
+    let result = process_requests(message, &mut processed_len, data).await;
+    message.drain(..processed_len);
+    result
+}
+
+// Processes requests of message following its first processed_len bytes
+// and advances processed_len past every parsed request.
+async fn process_requests(message: &str, processed_len: &mut usize, data: &mut TaskData) -> Result<(), TaskError> {
+    loop {
+        let unprocessed = &message[*processed_len..];
+        let parse: fn(&str) -> ParseResult<'_> = match data.protocol {
+            Protocol::Resp => parse_resp_request,
+            Protocol::Http => parse_http_request,
+            Protocol::Default if data.config.framed => parse_framed_request,
+            Protocol::Default => parse_request
+        };
+
+        let (request, rest) = match parse(unprocessed) {
+            Err(error) => {
+                // The message is not logged, as it may contain values.
+                warn!(%error, unparsed_bytes = unprocessed.len(), "parsing request failed");
+                send_error_response(data, error.reason()).await;
+                return Err(error)
+            },
+            Ok(None) => return Ok(()),
+            Ok(Some(parsed)) => parsed
+        };
+        *processed_len = message.len() - rest.len();
+
+        // Events of a request sent with trace:id: are tagged with the id,
+        // so they can be correlated with logs of other services.
+        let (request, span) = match request {
+            Request::Traced(traced) => (*traced.request, info_span!("request", trace_id = %traced.id)),
+            request => (request, Span::none())
+        };
+        let processed = async {
+            match request {
+                // PEEK request reports the state of message, so it is not
+                // processed like other requests.
+                Request::Peek => process_peek_request(rest, data).await,
+                // Requests sent after PSUBSCRIBE request are ignored.
+                request @ Request::PatternSubscribe(_) => {
+                    *processed_len = message.len();
+                    process_request(request, data).await
+                },
+                request => process_request(request, data).await
+            }
+        }.instrument(span.clone()).await;
+
+        if let Err(error) = processed {
+            if !data.closing {
+                span.in_scope(|| error!(%error, "processing request failed"));
+            }
+            // Does nothing if the error was caused by the stream or was already reported.
+            send_error_response(data, error.reason()).await;
+            return Err(error)
+        }
+        // Requests sent after QUIT request are ignored.
+        if data.quit {
+            *processed_len = message.len();
+            return Ok(())
+        }
+    }
+}
+
+async fn process_request(mut request: Request, data: &mut TaskData) -> Result<(), TaskError> {
+    request.add_key_prefix(&format!("{}{}", data.namespace, data.key_prefix));
+    debug!(request = request.name(), "processing request");
+
+    match request {
+        Request::Store(request) => process_store_request(request, data).await,
+        Request::StoreCheck(request) => process_store_check_request(request, data).await,
+        Request::StoreExpiring(request) => process_store_expiring_request(request, data).await,
+        Request::Load(request) => process_load_request(request, data).await,
+        Request::LoadTransformed(request) => process_load_transformed_request(request, data).await,
+        Request::Delete(request) => process_delete_request(request, data).await,
+        Request::Exists(request) => process_exists_request(request, data).await,
+        Request::Wait(request) => process_wait_request(request, data).await,
+        Request::Init(request) => process_init_request(request, data).await,
+        Request::Copy(request) => process_copy_request(request, data).await,
+        Request::GetOrSet(request) => process_get_or_set_request(request, data).await,
+        Request::AppendIfNew(request) => process_append_if_new_request(request, data).await,
+        Request::PushVersion(request) => process_push_version_request(request, data).await,
+        Request::GetVersion(request) => process_get_version_request(request, data).await,
+        Request::MaxSet(request) => process_extremum_request(request, data, "MAXSET", i64::max).await,
+        Request::MinSet(request) => process_extremum_request(request, data, "MINSET", i64::min).await,
+        Request::Increment(request) => process_increment_request(request, data).await,
+        Request::DecrementFloor(request) => process_decrement_floor_request(request, data).await,
+        Request::DecrementDelete(request) => process_decrement_delete_request(request, data).await,
+        Request::Prefix(request) => process_prefix_request(request, data).await,
+        Request::Select(request) => process_select_request(request, data).await,
+        Request::Ack(request) => process_ack_request(request, data).await,
+        Request::Chunked(request) => process_chunked_request(request, data).await,
+        Request::PatternSubscribe(request) => process_pattern_subscribe_request(request, data).await,
+        Request::MultiDelete(request) => process_multi_delete_request(request, data).await,
+        Request::MultiExists(request) => process_multi_exists_request(request, data).await,
+        Request::MultiGet(request) => process_multi_get_request(request, data).await,
+        Request::CompareAndSwap(request) => process_compare_and_swap_request(request, data).await,
+        Request::MultiCompareAndSet(request) => process_multi_compare_and_set_request(request, data).await,
+        Request::Snapshot(request) => process_snapshot_request(request, data).await,
+        Request::Keys => process_keys_request("", data).await,
+        Request::Scan(request) => process_keys_request(&request.prefix, data).await,
+        Request::Usage => process_usage_request(data).await,
+        Request::Count => process_count_request(data).await,
+        Request::Dump(request) => process_dump_request(request, data).await,
+        Request::Limits => process_limits_request(data).await,
+        Request::Conns => process_conns_request(data).await,
+        Request::Time => process_time_request(data).await,
+        Request::Ping => send_response(data, Response::Pong).await,
+        Request::NoFlush => process_no_flush_request(data),
+        Request::Flush => process_flush_request(data).await,
+        Request::FlushAll => process_flush_all_request(data).await,
+        Request::Quit => process_quit_request(data).await,
+        #[cfg(feature = "json")]
+        Request::Json => process_json_request(data).await,
+        #[cfg(feature = "json")]
+        Request::Metrics => process_metrics_request(data).await,
+        Request::SetConfig(request) => process_set_config_request(request, data).await,
+        Request::Peek => unreachable!("PEEK request is processed by process_message"),
+        Request::Traced(_) => unreachable!("traced requests are unwrapped by process_message")
+    }
+}
+
+async fn process_store_request(request: StoreRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    data.db.metrics.record_store();
+    if is_value_too_large(&request.value, data)? {
+        return send_response(data, Response::Error("value_too_large")).await;
+    }
+    let stored = match data.db.entries.lock() {
+        Ok(mut entries) => entries.insert(request.key.clone(), request.value, data.config.max_total_bytes),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    debug!(key = %request.key, stored, "STORE processed");
+    if stored {
+        data.db.audit("STORE", &request.key, data.peer)?;
+        data.db.stored.notify_waiters();
+        send_done_response(data).await
+    } else {
+        send_full_response(data).await
+    }
+}
+
+// Answers with OK$ if STORE request with the same key and value would
+// store the pair, or with the error it would be rejected with otherwise,
+// without storing anything. FULL$ answer of STORE becomes ERROR$full$.
+async fn process_store_check_request(request: StoreRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    if is_value_too_large(&request.value, data)? {
+        return send_response(data, Response::Error("value_too_large")).await;
+    }
+    let fits = match data.db.entries.lock() {
+        Ok(entries) => entries.fits(&request.key, &request.value, data.config.max_total_bytes),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+
+    if fits {
+        send_response(data, Response::Ok).await
+    } else {
+        send_response(data, Response::Error("full")).await
+    }
+}
+
+// Values longer than the configured limit, or the one set with SETCONFIG
+// request, are rejected before taking the lock, so they never reach the database.
+fn is_value_too_large(value: &str, data: &TaskData) -> Result<bool, TaskError> {
+    let max_value_bytes = data.db.settings.max_value_bytes(&data.config)?;
+    Ok(max_value_bytes.is_some_and(|max_value_bytes| value.len() > max_value_bytes))
+}
+
+// Stores the value like STORE request, but the pair expires after ttl
+// seconds. With ttl 0, the pair expires immediately, so it is never found,
+// but it still overrides the previous value of the key.
+async fn process_store_expiring_request(request: StoreExpiringRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    data.db.metrics.record_store();
+    if is_value_too_large(&request.value, data)? {
+        return send_response(data, Response::Error("value_too_large")).await;
+    }
+    let expires_at = Instant::now() + Duration::from_secs(request.ttl.into());
+    let stored = match data.db.entries.lock() {
+        Ok(mut entries) => {
+            entries.insert_expiring(request.key.clone(), request.value, expires_at, data.config.max_total_bytes)
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    if stored {
+        data.db.audit("STOREX", &request.key, data.peer)?;
+        data.db.stored.notify_waiters();
+        send_done_response(data).await
+    } else {
+        send_full_response(data).await
+    }
+}
+
+// Answers with the value of the key. Removes the pair if it has expired.
+async fn process_load_request(request: LoadRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let value = match data.db.entries.lock() {
+        Ok(mut entries) => {
+            entries.remove_expired(&request.key);
+            entries.get(&request.key).cloned()
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    data.db.metrics.record_load(value.is_some());
+    debug!(key = %request.key, found = value.is_some(), "LOAD processed");
+    match value {
+        None => send_not_found_response(data).await,
+        Some(value) => send_found_response(data, value).await
+    }
+}
+
+// Answers like LOAD request, but with the value transformed: upper and
+// lower change the case of the value, len replaces it with its length.
+async fn process_load_transformed_request(
+    request: LoadTransformedRequest,
+    data: &mut TaskData
+) -> Result<(), TaskError> {
+    let transform: fn(&str) -> String = match request.transform.as_str() {
+        "upper" => str::to_uppercase,
+        "lower" => str::to_lowercase,
+        "len" => |value| value.len().to_string(),
+        _ => return send_response(data, Response::Error("badtransform")).await
+    };
+
+    let value = match data.db.entries.lock() {
+        Ok(mut entries) => {
+            entries.remove_expired(&request.key);
+            entries.get(&request.key).map(|value| transform(value))
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    match value {
+        None => send_not_found_response(data).await,
+        Some(value) => send_found_response(data, value).await
+    }
+}
+
+// Answers with the value as soon as the key is present in the database,
+// or with TIMEOUT$ if it does not appear before the deadline. If the key
+// already exists, answers immediately. Other requests of the client are
+// not processed while waiting.
+async fn process_wait_request(request: WaitRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let deadline = Instant::now() + Duration::from_millis(request.timeout.into());
+    let db = data.db.clone();
+
+    loop {
+        // Registering for the notification before looking the key up
+        // guarantees that a STORE between these two steps is not missed.
+        let stored = db.stored.notified();
+        tokio::pin!(stored);
+        stored.as_mut().enable();
+
+        let value = match db.entries.lock() {
+            Ok(entries) => entries.get(&request.key).cloned(),
+            Err(_) => return Err(TaskError::Poisoned)
+        };
+
+        if let Some(value) = value {
+            return send_found_response(data, value).await;
+        }
+
+        if time::timeout_at(deadline, stored).await.is_err() {
+            return send_timeout_response(data).await;
+        }
+    }
+}
+
+// Stores the value only if the key is absent or its value is equal
+// to the configured sentinel. Otherwise, leaves the key unchanged.
+async fn process_init_request(request: InitRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    // None if the key is already initialized, otherwise whether the value was stored.
+    let stored = match data.db.entries.lock() {
+        Ok(mut entries) => {
+            let absent = entries.get(&request.key)
+                .is_none_or(|value| *value == data.config.init_sentinel);
+            absent.then(|| entries.insert(request.key.clone(), request.value, data.config.max_total_bytes))
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    match stored {
+        Some(true) => {
+            data.db.audit("INIT", &request.key, data.peer)?;
+            data.db.stored.notify_waiters();
+            send_done_response(data).await
+        },
+        Some(false) => send_full_response(data).await,
+        None => send_exists_response(data).await
+    }
+}
+
+// Copies the value of the source key to the destination key, overriding
+// its previous value. The source key is left unchanged.
+async fn process_copy_request(request: CopyRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    // None if the source key is absent, otherwise whether the value was copied.
+    let copied = match data.db.entries.lock() {
+        Ok(mut entries) => entries.get(&request.source).cloned().map(|value| {
+            entries.insert(request.destination.clone(), value, data.config.max_total_bytes)
+        }),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    match copied {
+        Some(true) => {
+            data.db.audit("COPY", &request.destination, data.peer)?;
+            data.db.stored.notify_waiters();
+            send_done_response(data).await
+        },
+        Some(false) => send_full_response(data).await,
+        None => send_not_found_response(data).await
+    }
+}
+
+// Answers with the value of the key if it is present. Otherwise, stores
+// the default value and answers with it. Both steps happen under one lock,
+// so two clients cannot both store their defaults.
+async fn process_get_or_set_request(request: GetOrSetRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    // None if the default value does not fit, otherwise the value and whether it was stored.
+    let result = match data.db.entries.lock() {
+        Ok(mut entries) => match entries.get(&request.key).cloned() {
+            Some(value) => Some((value, false)),
+            None => entries.insert(request.key.clone(), request.default.clone(), data.config.max_total_bytes)
+                .then_some((request.default, true))
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    match result {
+        Some((value, stored)) => {
+            if stored {
+                data.db.audit("GETORSET", &request.key, data.peer)?;
+                data.db.stored.notify_waiters();
+            }
+            send_found_response(data, value).await
+        },
+        None => send_full_response(data).await
+    }
+}
+
+// Separates items of a value built by APPENDIFNEW requests. Values sent
+// in APPENDIFNEW requests cannot contain it, so no item contains it.
+const ITEM_SEPARATOR: char = '\n';
+
+// Appends the item to the value of the key, unless the value already
+// contains it. An absent key and an empty value have no items. Checking
+// and appending happen under one lock, so an item is never appended twice.
+async fn process_append_if_new_request(request: AppendIfNewRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    // None if the new value does not fit, otherwise whether the item was appended.
+    let result = match data.db.entries.lock() {
+        Ok(mut entries) => {
+            let value = match entries.get(&request.key) {
+                Some(current) if current.is_empty() => Some(request.value),
+                Some(current) if current.split(ITEM_SEPARATOR).any(|item| item == request.value) => None,
+                Some(current) => Some(format!("{current}{ITEM_SEPARATOR}{}", request.value)),
+                None => Some(request.value)
+            };
+            match value {
+                Some(value) => entries.insert(request.key.clone(), value, data.config.max_total_bytes).then_some(true),
+                None => Some(false)
+            }
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    match result {
+        Some(true) => {
+            data.db.audit("APPENDIFNEW", &request.key, data.peer)?;
+            data.db.stored.notify_waiters();
+            send_done_response(data).await
+        },
+        Some(false) => send_response(data, Response::Skipped).await,
+        None => send_full_response(data).await
+    }
+}
+
+// Stores the value like STORE request, but keeps previous values of
+// the key, up to the configured number of them.
+async fn process_push_version_request(request: PushVersionRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let stored = match data.db.entries.lock() {
+        Ok(mut entries) => entries.push_version(
+            request.key.clone(), request.value, data.config.max_versions, data.config.max_total_bytes
+        ),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    if stored {
+        data.db.audit("PUSHVER", &request.key, data.peer)?;
+        data.db.stored.notify_waiters();
+        send_done_response(data).await
+    } else {
+        send_full_response(data).await
+    }
+}
+
+// Answers with the value of the key from the given number of versions ago.
+async fn process_get_version_request(request: GetVersionRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let value = match data.db.entries.lock() {
+        Ok(entries) => entries.get_version(&request.key, request.index).cloned(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+
+    match value {
+        None => send_not_found_response(data).await,
+        Some(value) => send_found_response(data, value).await
+    }
+}
+
+// Stores extremum of the current value of the key and the value from
+// the request, e.g. the greater one for MAXSET request, and answers with it.
+// Absent key is treated as if the value from the request was the extremum.
+// If the current value is not an integer, leaves it unchanged.
+async fn process_extremum_request(
+    request: ExtremumRequest,
+    data: &mut TaskData,
+    operation: &str,
+    extremum: fn(i64, i64) -> i64
+) -> Result<(), TaskError> {
+    let (response, stored) = match data.db.entries.lock() {
+        Ok(mut entries) => match entries.get(&request.key).map(|value| value.parse()) {
+            Some(Err(_)) => (Response::Error("not_an_integer"), false),
+            Some(Ok(current)) if extremum(current, request.value) == current => (Response::Counter(current), false),
+            _ if entries.insert(request.key.clone(), request.value.to_string(), data.config.max_total_bytes) => {
+                (Response::Counter(request.value), true)
+            },
+            _ => (Response::Full, false)
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    if stored {
+        data.db.audit(operation, &request.key, data.peer)?;
+        data.db.stored.notify_waiters();
+    }
+    send_response(data, response).await
+}
+
+// Adds delta to the value of the key and answers with the result.
+// Absent key is treated as if its value was 0. If the current value is
+// not an integer or the result does not fit in i64, leaves it unchanged.
+async fn process_increment_request(request: IncrementRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let (response, stored) = match data.db.entries.lock() {
+        Ok(mut entries) => match load_counter(&entries, &request.key) {
+            None => (Response::Error("not_an_integer"), false),
+            Some(current) => match current.checked_add(request.delta) {
+                None => (Response::Error("overflow"), false),
+                Some(value) if entries.insert(request.key.clone(), value.to_string(), data.config.max_total_bytes) => {
+                    (Response::Counter(value), true)
+                },
+                Some(_) => (Response::Full, false)
+            }
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    if stored {
+        let operation = if request.delta > 0 { "INCR" } else { "DECR" };
+        data.db.audit(operation, &request.key, data.peer)?;
+        data.db.stored.notify_waiters();
+    }
+    send_response(data, response).await
+}
+
+// Subtracts 1 from the value of the key like DECR request, unless the
+// result would be smaller than floor. Then the value is left unchanged,
+// also if it is already below floor, and the answer is marked as clamped.
+async fn process_decrement_floor_request(request: DecrementFloorRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let (response, stored) = match data.db.entries.lock() {
+        Ok(mut entries) => match load_counter(&entries, &request.key) {
+            None => (Response::Error("not_an_integer"), false),
+            Some(current) if current <= request.floor => (Response::Clamped(current), false),
+            // current is greater than floor, so subtracting does not overflow.
+            Some(current) if entries.insert(request.key.clone(), (current - 1).to_string(), data.config.max_total_bytes) => {
+                (Response::Counter(current - 1), true)
+            },
+            Some(_) => (Response::Full, false)
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    if stored {
+        data.db.audit("DECRFLOOR", &request.key, data.peer)?;
+        data.db.stored.notify_waiters();
+    }
+    send_response(data, response).await
+}
+
+// Subtracts 1 from the value of the key like DECR request, but removes
+// the key instead of storing a result that is not positive. Absent key
+// is left absent, so counters of released references do not reappear.
+async fn process_decrement_delete_request(request: DecrementDeleteRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let (response, changed) = match data.db.entries.lock() {
+        Ok(mut entries) => match entries.get(&request.key).map(|value| value.parse::<i64>()) {
+            None => (Response::NotFound, false),
+            Some(Err(_)) => (Response::Error("not_an_integer"), false),
+            Some(Ok(current)) if current <= 1 => {
+                entries.remove(&request.key);
+                (Response::Removed, true)
+            },
+            // current is greater than 1, so subtracting does not overflow.
+            Some(Ok(current)) if entries.insert(request.key.clone(), (current - 1).to_string(), data.config.max_total_bytes) => {
+                (Response::Counter(current - 1), true)
+            },
+            Some(Ok(_)) => (Response::Full, false)
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    if changed {
+        data.db.audit("DECRDEL", &request.key, data.peer)?;
+        data.db.stored.notify_waiters();
+    }
+    send_response(data, response).await
+}
+
+// Returns the value of the key as a counter of INCR and similar requests,
+// where absent key counts as 0, or None if the value is not an integer.
+fn load_counter(entries: &Entries, key: &str) -> Option<i64> {
+    entries.get(key).map_or(Some(0), |value| value.parse().ok())
+}
+
+// Sets the prefix prepended to keys of all following requests of the
+// client. Empty prefix turns prepending off.
+async fn process_prefix_request(request: PrefixRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    data.key_prefix = request.prefix;
+    send_done_response(data).await
+}
+
+// Name of the namespace that connections use before any SELECT request.
+const DEFAULT_NAMESPACE: &str = "default";
+
+// Selects the namespace of keys of all following requests of the client.
+// Keys are stored in the namespace with its name and '/' prepended, which
+// never occurs in keys sent by clients, so requests cannot reach keys of
+// other namespaces. Keys of the default namespace are stored as they are.
+async fn process_select_request(request: SelectRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    data.namespace = match request.namespace.as_str() {
+        DEFAULT_NAMESPACE => String::new(),
+        namespace => format!("{namespace}/")
+    };
+    send_done_response(data).await
+}
+
+// Selects when changes made by all following requests of the client are
+// answered: once they are written to the write-ahead log (persisted, the
+// default) or once they are queued for it (queued), which is faster, but
+// answered changes may be lost in a crash. Without the log, changes are
+// answered at once either way.
+async fn process_ack_request(request: AckRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    data.queued_acks = match request.mode.as_str() {
+        "persisted" => false,
+        "queued" => true,
+        _ => return send_response(data, Response::Error("badmode")).await
+    };
+    send_done_response(data).await
+}
+
+// Sets the maximum length of chunks in which values are sent in FOUND
+// answers to all following requests of the client. Zero turns chunking off.
+async fn process_chunked_request(request: ChunkedRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    data.chunk_bytes = (request.chunk_bytes > 0).then_some(request.chunk_bytes);
+    send_done_response(data).await
+}
+
+// Writes changes made by the request to the write-ahead log before it is
+// answered, or only queues them with ACK$queued$.
+async fn sync_wal(data: &mut TaskData) -> Result<(), TaskError> {
+    if data.queued_acks {
+        data.db.queue_wal();
+        Ok(())
+    } else {
+        data.db.sync_wal().await
+    }
+}
+
+// Returns the key without the namespace if the key belongs to it.
+fn strip_namespace<'a>(key: &'a str, namespace: &str) -> Option<&'a str> {
+    key.strip_prefix(namespace).filter(|key| !key.contains('/'))
+}
+
+// Removes the key together with its previous values.
+async fn process_delete_request(request: DeleteRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let deleted = match data.db.entries.lock() {
+        Ok(mut entries) => entries.remove(&request.key).is_some(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    if deleted {
+        data.db.audit("DELETE", &request.key, data.peer)?;
+        send_response(data, Response::Removed).await
+    } else {
+        send_not_found_response(data).await
+    }
+}
+
+// Answers whether the key is present without sending its value.
+async fn process_exists_request(request: ExistsRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let present = match data.db.entries.lock() {
+        Ok(entries) => entries.get(&request.key).is_some(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+
+    send_response(data, Response::Present(present)).await
+}
+
+// Removes all listed keys at once, so no other request observes only some
+// of them removed. Answers with the number of removed keys, so absent keys
+// and keys listed more than once are counted once at most.
+async fn process_multi_delete_request(request: MultiDeleteRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let deleted: Vec<String> = match data.db.entries.lock() {
+        Ok(mut entries) => request.keys.into_iter().filter(|key| entries.remove(key).is_some()).collect(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    for key in &deleted {
+        data.db.audit("MDEL", key, data.peer)?;
+    }
+    send_response(data, Response::Deleted(deleted.len())).await
+}
+
+// Answers with DONE$ and then pushes CHANGED$key$value$ every time a value
+// of a key starting with the prefix is stored, until the client closes its
+// writing half of the connection. Empty prefix matches all keys. Nothing
+// else is processed on the connection anymore and everything the client
+// sends is ignored. If the client does not read changes fast enough and
+// misses some of them, the connection is closed.
+async fn process_pattern_subscribe_request(
+    request: PatternSubscribeRequest,
+    data: &mut TaskData
+) -> Result<(), TaskError> {
+    let mut changes = match data.db.entries.lock() {
+        Ok(entries) => entries.subscribe(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    send_done_response(data).await?;
+
+    let db = data.db.clone();
+    let mut buf = vec![0; 1024];
+    loop {
+        tokio::select! {
+            // The subscription ends when the server is shutting down.
+            _ = db.connections.shutting_down() => return Ok(()),
+            change = changes.recv() => {
+                let change = match change {
+                    Ok(change) => change,
+                    Err(RecvError::Lagged(skipped)) => {
+                        send_error_response(data, "lagged").await;
+                        return Err(TaskError::Lagged(skipped))
+                    },
+                    Err(RecvError::Closed) => return Err(TaskError::ChannelClosed)
+                };
+                let in_namespace = strip_namespace(&change.key, &data.namespace).is_some();
+                if in_namespace && change.key.starts_with(&request.prefix) {
+                    // Keys are reported without the namespace and the prefix set with PREFIX request.
+                    let key = change.key[data.namespace.len() + data.key_prefix.len()..].to_string();
+                    send_response(data, Response::Changed { key, value: change.value }).await?;
+                }
+            },
+            read = read_retrying(&mut data.stream, &mut buf) => match read {
+                Ok(0) => return Ok(()),
+                Ok(_) => (),
+                Err(error) => {
+                    data.closing = true;
+                    return Err(TaskError::Io(error))
+                }
+            }
+        }
+    }
+}
+
+// Reports which of the listed keys are present, in the order they were listed.
+async fn process_multi_exists_request(request: MultiExistsRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let present = match data.db.entries.lock() {
+        Ok(entries) => request.keys.iter().map(|key| entries.get(key).is_some()).collect(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+
+    send_response(data, Response::ExistsList(present)).await
+}
+
+// Loads values of the listed keys at once and answers with all of them
+// in one response, in the order the keys were listed.
+async fn process_multi_get_request(request: MultiGetRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let values: Vec<_> = match data.db.entries.lock() {
+        Ok(mut entries) => request.keys.iter().map(|key| {
+            entries.remove_expired(key);
+            entries.get(key).cloned()
+        }).collect(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    for value in &values {
+        data.db.metrics.record_load(value.is_some());
+    }
+    send_listing_response(data, Response::Values(values)).await
+}
+
+// Stores the value only if the key has the expected value, which an absent
+// key never has. Checking and storing is a single atomic operation, so
+// no other request is processed in between.
+async fn process_compare_and_swap_request(request: CompareAndSwapRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    // None if the key does not have the expected value, otherwise whether the value was stored.
+    let stored = match data.db.entries.lock() {
+        Ok(mut entries) => (entries.get(&request.key) == Some(&request.expected))
+            .then(|| entries.insert(request.key.clone(), request.value, data.config.max_total_bytes)),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    match stored {
+        Some(true) => {
+            data.db.audit("CAS", &request.key, data.peer)?;
+            data.db.stored.notify_waiters();
+            send_response(data, Response::Swapped).await
+        },
+        Some(false) => send_full_response(data).await,
+        None => send_response(data, Response::Mismatch).await
+    }
+}
+
+// Stores the value only if every listed key has its expected value. An absent
+// key never has the expected value. Checking and storing is a single atomic
+// operation, so no other request is processed in between.
+async fn process_multi_compare_and_set_request(
+    request: MultiCompareAndSetRequest,
+    data: &mut TaskData
+) -> Result<(), TaskError> {
+    // None if some key does not have its expected value, otherwise whether the value was stored.
+    let stored = match data.db.entries.lock() {
+        Ok(mut entries) => {
+            let matches = request.conditions.iter()
+                .all(|(key, expected)| entries.get(key) == Some(expected));
+            matches.then(|| entries.insert(request.key.clone(), request.value, data.config.max_total_bytes))
+        },
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    match stored {
+        Some(true) => {
+            data.db.audit("MCAS", &request.key, data.peer)?;
+            data.db.stored.notify_waiters();
+            send_done_response(data).await
+        },
+        Some(false) => send_full_response(data).await,
+        None => send_response(data, Response::Mismatch).await
+    }
+}
+
+// Writes all pairs to the file called name in the snapshot directory,
+// replacing a previous snapshot with the same name. Pairs are copied at
+// once, so the snapshot does not contain values stored in the meantime.
+// The file consists of STORE requests (STOREB requests for values that
+// STORE requests cannot carry), so sending it to a server restores
+// the pairs. Available only if the snapshot directory is configured,
+// otherwise the request is treated as incorrect. The file is written
+// under a temporary name and then renamed, so a snapshot is never left
+// half-written, even if the server dies while writing it.
+async fn process_snapshot_request(request: SnapshotRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let Some(snapshot_dir) = &data.config.snapshot_dir else {
+        send_error_response(data, "bad_request").await;
+        return Err(TaskError::ParseError)
+    };
+    let path = snapshot_dir.join(&request.name);
+    // Names of snapshots contain only letters, so temporary files never
+    // collide with snapshots. The process id and the counter keep other
+    // servers and concurrent SNAPSHOT requests with the same name from
+    // writing to the same temporary file.
+    static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+    let temp_id = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+    let temp_path = snapshot_dir.join(format!("{}.{}.{temp_id}.tmp", request.name, std::process::id()));
+
+    let requests: String = sorted_pairs(data)?.into_iter().map(|(key, value)| {
+        if value.bytes().all(|byte| byte.is_ascii_lowercase()) {
+            format!("STORE${key}${value}$")
+        } else {
+            format!("STOREB${key}${}${value}", value.len())
+        }
+    }).collect();
+    write_atomically(&temp_path, &path, requests.as_bytes()).await.inspect_err(|_| {
+        // The temporary file is useless now. Nothing can be done if removing it fails.
+        let _ = std::fs::remove_file(&temp_path);
+    })?;
+    send_done_response(data).await
+}
+
+// Returns all pairs of the selected namespace that have not expired,
+// sorted by keys, so snapshots and dumps do not depend on the order
+// in which the pairs were stored.
+fn sorted_pairs(data: &TaskData) -> Result<Vec<(String, String)>, TaskError> {
+    let mut pairs: Vec<(String, String)> = match data.db.entries.lock() {
+        Ok(entries) => entries.iter()
+            .filter_map(|(key, value)| Some((strip_namespace(key, &data.namespace)?.to_string(), value.clone())))
+            .collect(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    pairs.sort();
+    Ok(pairs)
+}
+
+// Writes contents to temp_path, flushes them to the disk and renames
+// temp_path to path, which replaces the file at path atomically if both
+// paths are on the same filesystem.
+async fn write_atomically(temp_path: &Path, path: &Path, contents: &[u8]) -> Result<(), TaskError> {
+    let mut file = tokio::fs::File::create(temp_path).await.map_err(TaskError::Io)?;
+    file.write_all(contents).await.map_err(TaskError::Io)?;
+    file.sync_all().await.map_err(TaskError::Io)?;
+    tokio::fs::rename(temp_path, path).await.map_err(TaskError::Io)?;
+
+    // The rename itself survives a power loss only once the directory is
+    // flushed as well.
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let dir = tokio::fs::File::open(dir).await.map_err(TaskError::Io)?;
+    dir.sync_all().await.map_err(TaskError::Io)
+}
+
+// Answers with all keys starting with prefix sorted, so the answer does
+// not depend on the order in which they were stored. Empty prefix, used
+// by KEYS request, matches all keys. Unlike in PSUBSCRIBE request, the
+// prefix set with PREFIX request is not prepended, so SCAN request with
+// empty prefix answers like KEYS request.
+async fn process_keys_request(prefix: &str, data: &mut TaskData) -> Result<(), TaskError> {
+    let mut keys: Vec<String> = match data.db.entries.lock() {
+        Ok(entries) => entries.iter()
+            .filter_map(|(key, _)| strip_namespace(key, &data.namespace))
+            .filter(|key| key.starts_with(prefix))
+            .map(String::from)
+            .collect(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    keys.sort();
+
+    send_listing_response(data, Response::Keys(keys)).await
+}
+
+// Reports the length of the write-ahead log on the disk (0 without it),
+// and the total length of keys and values and the number of keys of
+// the selected namespace in server's memory. Like COUNT request, expired
+// pairs that have not been removed yet are not counted, and keys are
+// counted without the name of the namespace. Previous versions of values
+// are not counted, so the figures are approximate.
+async fn process_usage_request(data: &mut TaskData) -> Result<(), TaskError> {
+    let (cache, keys) = match data.db.entries.lock() {
+        Ok(entries) => entries.iter()
+            .filter_map(|(key, value)| Some(strip_namespace(key, &data.namespace)?.len() + value.len()))
+            .fold((0, 0), |(cache, keys), bytes| (cache + bytes, keys + 1)),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    let disk = data.db.wal_bytes().await?;
+
+    send_response(data, Response::Usage { disk, cache, keys }).await
+}
+
+// Reports the number of keys in server's memory. Expired pairs that have
+// not been removed yet are not counted.
+async fn process_count_request(data: &mut TaskData) -> Result<(), TaskError> {
+    let count = match data.db.entries.lock() {
+        Ok(entries) => entries.iter().filter(|(key, _)| strip_namespace(key, &data.namespace).is_some()).count(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+
+    send_response(data, Response::Count(count)).await
+}
+
+// Sends all pairs of the selected namespace in one of the formats of
+// the dump module, which Client::restore replays as STOREB requests.
+// Like SNAPSHOT request, but the pairs go to the client instead of a file.
+async fn process_dump_request(request: DumpRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    let format = match request.format.as_str() {
+        "binary" => DumpFormat::Binary,
+        #[cfg(feature = "json")]
+        "json" => DumpFormat::Json,
+        _ => return send_response(data, Response::Error("badformat")).await
+    };
+
+    let pairs = sorted_pairs(data)?;
+    send_listing_response(data, Response::Dump(pairs, format)).await
+}
+
+// Reports the current limits of keys and values.
+async fn process_limits_request(data: &mut TaskData) -> Result<(), TaskError> {
+    // The server does not limit lengths of single keys.
+    let limits = Response::Limits {
+        max_key: None,
+        max_value: data.db.settings.max_value_bytes(&data.config)?,
+        max_total: data.config.max_total_bytes
+    };
+    send_response(data, limits).await
+}
+
+// Reports the number of currently open connections, including this one.
+async fn process_conns_request(data: &mut TaskData) -> Result<(), TaskError> {
+    let count = data.db.metrics.active_connections();
+    send_response(data, Response::Conns(count)).await
+}
+
+// Reports the current time of the server's clock since the Unix epoch.
+// It is the clock used for all time calculations on the server side.
+async fn process_time_request(data: &mut TaskData) -> Result<(), TaskError> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|error| TaskError::Io(io::Error::other(error)))?;
+    let time = Response::Time { secs: since_epoch.as_secs(), nanos: since_epoch.subsec_nanos() };
+    send_response(data, time).await
+}
+
+// Starts withholding responses until a FLUSH request. Does not send
+// any response itself.
+fn process_no_flush_request(data: &mut TaskData) -> Result<(), TaskError> {
+    data.pending_responses.get_or_insert_with(Vec::new);
+    Ok(())
+}
+
+// Writes all responses withheld since NOFLUSH request at once and goes
+// back to writing responses immediately. Does not send any response itself.
+async fn process_flush_request(data: &mut TaskData) -> Result<(), TaskError> {
+    match data.pending_responses.take() {
+        Some(responses) if !responses.is_empty() => write_to_stream(data, &responses).await,
+        _ => Ok(())
+    }
+}
+
+// Answers with BYE and marks the connection to be finished by
+// handle_connection like after the client closed it, so withheld
+// responses are still written.
+async fn process_quit_request(data: &mut TaskData) -> Result<(), TaskError> {
+    data.quit = true;
+    send_response(data, Response::Bye).await
+}
+
+// Removes all keys at once, so no other request observes only some of
+// them removed, and no STORE request is processed in the middle. Keys of
+// all clients are removed, regardless of the prefix set with PREFIX request.
+async fn process_flush_all_request(data: &mut TaskData) -> Result<(), TaskError> {
+    let deleted = match data.db.entries.lock() {
+        Ok(mut entries) => entries.clear(),
+        Err(_) => return Err(TaskError::Poisoned)
+    };
+    sync_wal(data).await?;
+
+    for key in &deleted {
+        data.db.audit("FLUSHALL", key, data.peer)?;
+    }
+    send_response(data, Response::Flushed).await
+}
+
+// Switches the connection to JSON responses, starting with
+// the response to this request.
+#[cfg(feature = "json")]
+async fn process_json_request(data: &mut TaskData) -> Result<(), TaskError> {
+    data.response_format = ResponseFormat::Json;
+    send_done_response(data).await
+}
+
+// Reports counters of the server's activity as a JSON object,
+// regardless of the response format of the connection.
+#[cfg(feature = "json")]
+async fn process_metrics_request(data: &mut TaskData) -> Result<(), TaskError> {
+    let metrics = data.db.metrics.snapshot();
+    send_response(data, Response::Metrics(metrics)).await
+}
+
+// Reports the length of the part of the message following PEEK request
+// and names of requests it could become. Available only if diagnostics
+// are enabled, otherwise the request is treated as incorrect.
+async fn process_peek_request(message: &str, data: &mut TaskData) -> Result<(), TaskError> {
+    if !data.config.diagnostics {
+        send_error_response(data, "bad_request").await;
+        return Err(TaskError::ParseError);
+    }
+
+    let could_become = could_become_requests(message)?;
+    send_response(data, Response::Peek { len: message.len(), could_become }).await
+}
+
+// Changes a setting while the server runs. Only the maximum length of
+// a value (maxval, in bytes or none) and the idle timeout (idletimeout,
+// in milliseconds, where 0 disables it) can be changed, others are read
+// once at startup. Idle connections are reaped only if the idle timeout
+// was configured, so otherwise it cannot be changed either. Available only
+// if administrative requests are enabled, otherwise the request is treated
+// as incorrect.
+async fn process_set_config_request(request: SetConfigRequest, data: &mut TaskData) -> Result<(), TaskError> {
+    if !data.config.admin {
+        send_error_response(data, "bad_request").await;
+        return Err(TaskError::ParseError);
+    }
+
+    match request.name.as_str() {
+        "maxval" => {
+            let max_value_bytes = match request.value.as_str() {
+                "none" => None,
+                value => match value.parse() {
+                    Ok(max_value_bytes) => Some(max_value_bytes),
+                    Err(_) => return send_response(data, Response::Error("badvalue")).await
+                }
+            };
+            data.db.settings.set_max_value_bytes(max_value_bytes)?;
+        },
+        "idletimeout" if data.config.idle_timeout.is_some() => {
+            let Ok(millis) = request.value.parse() else {
+                return send_response(data, Response::Error("badvalue")).await;
+            };
+            data.db.settings.set_idle_timeout((millis > 0).then(|| Duration::from_millis(millis)))?;
+        },
+        _ => return send_response(data, Response::Immutable).await
+    }
+    info!(setting = request.name, value = request.value, "setting changed");
+    send_done_response(data).await
+}
+
+// Writes response to the client or withholds it if the client sent NOFLUSH.
+async fn send_response(data: &mut TaskData, response: Response) -> Result<(), TaskError> {
+    let response = response.encode(data.response_format);
+    send_encoded_response(data, response).await
+}
+
+// Like send_response, but for answers whose length grows with the number
+// of pairs, e.g. to DUMP request. An answer longer than the configured
+// maximum is cut there and followed by TRUNCATED$, so a single request
+// cannot make the server write an unbounded response.
+async fn send_listing_response(data: &mut TaskData, response: Response) -> Result<(), TaskError> {
+    let mut response = response.encode(data.response_format);
+    if let Some(max_response_bytes) = data.config.max_response_bytes {
+        if response.len() > max_response_bytes {
+            response.truncate(max_response_bytes);
+            response.extend_from_slice(b"TRUNCATED$");
+            data.db.metrics.record_response_truncated();
+        }
+    }
+    send_encoded_response(data, response).await
+}
+
+async fn send_encoded_response(data: &mut TaskData, response: Vec<u8>) -> Result<(), TaskError> {
+    match &mut data.pending_responses {
+        Some(responses) => {
+            responses.extend_from_slice(&response);
+            Ok(())
+        },
+        None => write_to_stream(data, &response).await
+    }
+}
+
+// Flushing is needed by streams that buffer written bytes, e.g. compressed ones.
+// If the write does not finish within the configured timeout, e.g. because
+// the client stopped reading, it fails and the connection is closed.
+async fn write_to_stream(data: &mut TaskData, bytes: &[u8]) -> Result<(), TaskError> {
+    let stream = &mut data.stream;
+    let write = async move {
+        // A single write may write only a part of bytes.
+        match stream.write_all(bytes).await {
+            Ok(_) => stream.flush().await,
+            Err(error) => Err(error)
+        }
+    };
+
+    let result = match data.config.write_timeout {
+        Some(write_timeout) => time::timeout(write_timeout, write).await.unwrap_or(Err(ErrorKind::TimedOut.into())),
+        None => write.await
+    };
+    if let Err(error) = &result {
+        debug!(%error, "writing failed, closing connection");
+        data.closing = true;
+    }
+    result.map_err(TaskError::Io)
+}
+
+// Writes ERROR$reason$ before the connection is closed because of an error,
+// unless the connection is already closing. The response is written
+// immediately, even if responses are withheld, and withheld responses are
+// dropped. Failure of writing is ignored, as the connection is closed anyway.
+async fn send_error_response(data: &mut TaskData, reason: &'static str) {
+    if !data.closing {
+        let bytes = Response::Error(reason).encode(data.response_format);
+        let _ = write_to_stream(data, &bytes).await;
+        data.closing = true;
+    }
+}
+
+async fn send_done_response(data: &mut TaskData) -> Result<(), TaskError> {
+    send_response(data, Response::Done).await
+}
+
+// After CHUNKED request, values are sent in chunks followed by ENDCHUNK$
+// instead of as FOUND$value$, see response::encode_chunks. Other formats
+// than the default one delimit values on their own, so they are not chunked.
+async fn send_found_response(data: &mut TaskData, value: String) -> Result<(), TaskError> {
+    match data.chunk_bytes {
+        Some(chunk_bytes) if data.response_format == ResponseFormat::Text => {
+            send_encoded_response(data, encode_chunks(&value, chunk_bytes)).await
+        },
+        _ => send_response(data, Response::Found(value)).await
+    }
+}
+
+async fn send_not_found_response(data: &mut TaskData) -> Result<(), TaskError> {
+    send_response(data, Response::NotFound).await
+}
+
+async fn send_exists_response(data: &mut TaskData) -> Result<(), TaskError> {
+    send_response(data, Response::Exists).await
+}
+
+async fn send_full_response(data: &mut TaskData) -> Result<(), TaskError> {
+    send_response(data, Response::Full).await
+}
+
+async fn send_timeout_response(data: &mut TaskData) -> Result<(), TaskError> {
+    send_response(data, Response::Timeout).await
+}
+
+// Only tests that need private items are here, tests of a server started
+// through the public API are in tests/system_tests.rs.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    // Reader returning the given results of consecutive reads.
+    struct MockReader {
+        reads: Vec<io::Result<&'static [u8]>>
+    }
+
+    impl AsyncRead for MockReader {
+        fn poll_read(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            let result = self.reads.remove(0).map(|bytes| buf.put_slice(bytes));
+            Poll::Ready(result)
+        }
+    }
+
+    #[tokio::test]
+    async fn read_retrying_retries_interrupted_reads() {
+        let mut reader = MockReader {
+            reads: vec![Err(ErrorKind::Interrupted.into()), Err(ErrorKind::Interrupted.into()), Ok(b"LOAD$k$")]
+        };
+        let mut buf = [0; 16];
+
+        assert_eq!(7, read_retrying(&mut reader, &mut buf).await.unwrap());
+        assert_eq!(b"LOAD$k$", &buf[0..7]);
+    }
+
+    #[test]
+    fn push_utf8_joins_characters_split_between_reads() {
+        let mut message = String::new();
+        let mut incomplete = Vec::new();
+        let bytes = "LOAD$é$".as_bytes();
+
+        push_utf8(&mut message, &mut incomplete, &bytes[..6]).unwrap();
+        assert_eq!(("LOAD$", 1), (message.as_str(), incomplete.len()));
+        push_utf8(&mut message, &mut incomplete, &bytes[6..]).unwrap();
+        assert_eq!(("LOAD$é$", 0), (message.as_str(), incomplete.len()));
+    }
+
+    #[test]
+    fn push_utf8_rejects_invalid_bytes() {
+        assert!(matches!(push_utf8(&mut String::new(), &mut Vec::new(), b"LOAD$\xff$"), Err(TaskError::InvalidUtf8)));
+        assert!(matches!(push_utf8(&mut String::new(), &mut vec![0xc3], b"$"), Err(TaskError::InvalidUtf8)));
+    }
+
+    #[test]
+    fn task_errors_report_their_causes() {
+        assert_eq!("bad_request", TaskError::ParseError.reason());
+        assert_eq!("bad_request", TaskError::InvalidUtf8.reason());
+        assert_eq!("lagged", TaskError::Lagged(3).reason());
+        assert_eq!("subscriber lagged behind by 3 changes", TaskError::Lagged(3).to_string());
+
+        let error = TaskError::Io(io::Error::from(ErrorKind::BrokenPipe));
+        assert_eq!("internal", error.reason());
+        assert!(std::error::Error::source(&error).is_some());
+        assert_eq!("internal", TaskError::Poisoned.reason());
+    }
+
+    #[tokio::test]
+    async fn read_retrying_returns_other_errors() {
+        let mut reader = MockReader {
+            reads: vec![Err(ErrorKind::ConnectionReset.into()), Ok(b"LOAD$k$")]
+        };
+        let mut buf = [0; 16];
+
+        let error = read_retrying(&mut reader, &mut buf).await.unwrap_err();
+        assert_eq!(ErrorKind::ConnectionReset, error.kind());
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(1000)]
+    async fn long_responses_are_written_whole() {
+        // The stream accepts at most 64 bytes at once, until they are read.
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut data = TaskData::with_stream(Box::new(server), None, Arc::new(Database::new()), Arc::new(Config::default()));
+        let value = "v".repeat(10000);
+
+        let reader = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            client.read_to_end(&mut buf).await.unwrap();
+            buf
+        });
+        send_found_response(&mut data, value.clone()).await.unwrap();
+        drop(data);
+
+        assert!(format!("FOUND${value}$").into_bytes() == reader.await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(1000)]
+    async fn accept_retrying_survives_accept_errors() {
+        // EMFILE, i.e. too many open files, and a client that gave up.
+        let mut errors = vec![io::Error::from_raw_os_error(24), ErrorKind::ConnectionAborted.into()];
+        let start = Instant::now();
+        let accepted = accept_retrying(|| {
+            let result = errors.pop().map_or(Ok(7), Err);
+            async move { result }
+        }).await;
+
+        assert_eq!(7, accepted);
+        assert!(start.elapsed() >= 2 * ACCEPT_RETRY_DELAY);
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    #[ntest::timeout(1000)]
+    async fn compressed_connection_processes_requests() {
+        let (client, server) = tokio::io::duplex(1024);
+        let config = Config { compressed: true, ..Config::default() };
+        tokio::spawn(handle_stream(server, Arc::new(Database::new()), Arc::new(config)));
+        let mut client = stream::compressed(client);
+        client.write_all(b"STORE$key$value$LOAD$key$").await.unwrap();
+        client.flush().await.unwrap();
+
+        let expected = b"DONE$FOUND$value$";
+        let mut buf = [0; 17];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(expected, &buf);
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    #[ntest::timeout(1000)]
+    async fn tls_connection_processes_requests() {
+        use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+        use tokio_rustls::rustls::pki_types::ServerName;
+
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/tls");
+        let tls = stream::tls_acceptor(&dir.join("cert.pem"), &dir.join("key.pem")).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = Arc::new(Config { tls: Some(tls), ..Config::default() });
+        tokio::spawn(serve(listener, Arc::new(Database::new()), config, std::future::pending()));
+
+        // Plaintext requests are not answered, the server only sends
+        // a TLS alert and closes the connection.
+        let mut socket = TcpStream::connect(address).await.unwrap();
+        socket.write_all(b"STORE$key$value$").await.unwrap();
+        let mut response = Vec::new();
+        let _ = socket.read_to_end(&mut response).await;
+        assert!(!response.starts_with(b"DONE$"));
+
+        let mut roots = RootCertStore::empty();
+        let cert = std::fs::read(dir.join("cert.pem")).unwrap();
+        for cert in rustls_pemfile::certs(&mut cert.as_slice()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(
+            ClientConfig::builder().with_root_certificates(roots).with_no_client_auth()
+        ));
+        let socket = TcpStream::connect(address).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut client = connector.connect(server_name, socket).await.unwrap();
+        client.write_all(b"STORE$key$value$LOAD$key$").await.unwrap();
+
+        let mut buf = [0; 17];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(b"DONE$FOUND$value$", &buf);
     }
 }
@@ -1,24 +1,172 @@
 // author - Patryk Jędrzejczak
 
 use tokio::net::TcpListener;
-use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
+use tokio::signal;
+use tokio::time::{self, Duration};
+use std::env;
+use std::future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use tracing_subscriber::EnvFilter;
 
-use key_value_store::{Db, TaskData};
+use key_value_store::{AuditLog, Config, Database, Db};
+
+const HELP: &str = "\
+Usage: key-value-store [--help]
+
+Key-value store server. It is configured with environment variables:
+  KVS_ADDR               address to listen on (default 0.0.0.0:5555)
+  KVS_RESP_ADDR          address to listen on for Redis clients (RESP)
+  KVS_HTTP_ADDR          address to listen on for HTTP clients
+  KVS_METRICS_ADDR       address to serve Prometheus metrics on
+  KVS_INIT_SENTINEL      value that INIT requests treat as absent
+  KVS_AUDIT_LOG          path of the audit log of modifying operations
+  KVS_WAL                path of the write-ahead log restoring pairs after restarts
+  KVS_WAL_COMPRESSED=1   compress values in the write-ahead log (compression feature)
+  KVS_WAL_KEY            64 hexadecimal digits of the key encrypting the write-ahead log (encryption feature)
+  KVS_MAX_TOTAL_BYTES    maximum total length of all keys and values
+  KVS_MAX_VALUE_BYTES    maximum length of a value stored with STORE, STOREB or STOREX
+  KVS_MAX_VERSIONS       number of previous values kept by PUSHVER (default 5)
+  KVS_WRITE_TIMEOUT_MS   time after which writing a response fails
+  KVS_IDLE_TIMEOUT_MS    time after which idle connections are closed (default 300000, 0 disables)
+  KVS_SNAPSHOT_DIR       directory in which SNAPSHOT requests create snapshots
+  KVS_MAX_MESSAGE_BYTES  maximum length of an unfinished request (default 65536)
+  KVS_MAX_CONNECTIONS    maximum number of connections handled at once on each address
+  KVS_MAX_RESPONSE_BYTES maximum length of an answer to DUMP, KEYS, SCAN or MGET
+  KVS_READ_BUFFER_BYTES  number of bytes read from a connection at once (default 8192)
+  KVS_FRAMED=1           precede every request with its length
+  KVS_DIAGNOSTICS=1      enable diagnostic requests (PEEK)
+  KVS_ADMIN=1            enable administrative requests (SETCONFIG)
+  KVS_COMPRESSED=1       compress connections with zlib (compression feature)
+  KVS_TLS_CERT           PEM file with the certificate chain encrypting connections (tls feature)
+  KVS_TLS_KEY            PEM file with the private key of KVS_TLS_CERT (tls feature)
+  RUST_LOG               filter of logs written to stderr (default info)";
 
 #[tokio::main]
 async fn main() {
-    let listener = TcpListener::bind("0.0.0.0:5555").await.unwrap();
+    if env::args().skip(1).any(|arg| arg == "--help" || arg == "-h") {
+        println!("{HELP}");
+        return;
+    }
 
-    let db: Db = Arc::new(Mutex::new(HashMap::new()));
+    // Keys are logged at debug level and values are never logged.
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr).init();
 
-    loop {
-        let (socket, _) = listener.accept().await.unwrap();
+    let config = Arc::new(Config::from_env());
+
+    let listener = bind(config.address).await;
+    let resp_listener = match config.resp_address {
+        Some(resp_address) => Some(bind(resp_address).await),
+        None => None
+    };
+    let http_listener = match config.http_address {
+        Some(http_address) => Some(bind(http_address).await),
+        None => None
+    };
+    let metrics_listener = match config.metrics_address {
+        Some(metrics_address) => Some(bind(metrics_address).await),
+        None => None
+    };
+
+    let audit_log = config.audit_log.as_deref().map(|path| Arc::new(open_audit_log(path)));
+    let mut db = match &audit_log {
+        Some(audit_log) => {
+            tokio::spawn(flush_periodically(audit_log.clone()));
+            Database::with_audit_log(audit_log.clone())
+        },
+        None => Database::new()
+    };
+    if let Some(wal) = &config.wal {
+        #[cfg(feature = "encryption")]
+        let opened = match &config.wal_key {
+            Some(key) => db.open_encrypted_wal(wal, config.wal_compressed, key),
+            None => db.open_wal(wal, config.wal_compressed)
+        };
+        #[cfg(not(feature = "encryption"))]
+        let opened = db.open_wal(wal, config.wal_compressed);
+        if let Err(error) = opened {
+            eprintln!("Cannot restore pairs from {}: {error}", wal.display());
+            process::exit(1);
+        }
+    }
+    let db: Db = Arc::new(db);
+
+    if let Some(idle_timeout) = config.idle_timeout {
+        tokio::spawn(key_value_store::reap_idle_connections(db.clone(), idle_timeout));
+    }
+
+    let resp_server = async {
+        if let Some(resp_listener) = resp_listener {
+            key_value_store::serve_resp(resp_listener, db.clone(), config.clone(), shutdown()).await;
+        }
+    };
+    let http_server = async {
+        if let Some(http_listener) = http_listener {
+            key_value_store::serve_http(http_listener, db.clone(), config.clone(), shutdown()).await;
+        }
+    };
+    let metrics_server = async {
+        if let Some(metrics_listener) = metrics_listener {
+            key_value_store::serve_metrics(metrics_listener, db.clone(), shutdown()).await;
+        }
+    };
+    tokio::join!(
+        key_value_store::serve(listener, db.clone(), config.clone(), shutdown()),
+        resp_server,
+        http_server,
+        metrics_server
+    );
+
+    if let Some(audit_log) = audit_log {
+        if let Err(error) = audit_log.flush() {
+            tracing::error!(%error, "flushing the audit log failed, its last entries are lost");
+        }
+    }
+}
 
-        let db = db.clone();
+// Exits the process if the audit log cannot be opened, like bind.
+fn open_audit_log(path: &Path) -> AuditLog {
+    match AuditLog::open(path) {
+        Ok(audit_log) => audit_log,
+        Err(error) => {
+            eprintln!("Cannot open the audit log {}: {error}", path.display());
+            process::exit(1);
+        }
+    }
+}
 
-        tokio::spawn(async move {
-            key_value_store::handle_connection(TaskData::new(socket, db)).await;
-        });
+// Exits the process if the server cannot listen on address.
+async fn bind(address: SocketAddr) -> TcpListener {
+    match TcpListener::bind(address).await {
+        Ok(listener) => {
+            tracing::info!(%address, "listening");
+            listener
+        },
+        Err(error) => {
+            eprintln!("Cannot listen on {address}: {error}");
+            process::exit(1);
+        }
+    }
+}
+
+async fn shutdown() {
+    // If Ctrl-C cannot be listened for, the server runs until it is killed.
+    if signal::ctrl_c().await.is_err() {
+        future::pending::<()>().await;
+    }
+}
+
+// A failed flush, e.g. on a full disk, does not stop the server. Entries
+// that were not written stay buffered and are written by the next flush.
+async fn flush_periodically(audit_log: Arc<AuditLog>) {
+    let mut interval = time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        if let Err(error) = audit_log.flush() {
+            tracing::warn!(%error, "flushing the audit log failed");
+        }
     }
 }
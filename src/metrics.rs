@@ -0,0 +1,289 @@
+// author - Patryk Jędrzejczak
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+use tracing::warn;
+
+use super::{Db, accept_retrying};
+
+// Maximum length of the head of a request for metrics. Clients send
+// only a request line and a few headers.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+// Counters of the server's activity since it started.
+pub(crate) struct Metrics {
+    started: Instant,
+    stores: AtomicU64,
+    loads: AtomicU64,
+    // LOAD requests that found the key.
+    hits: AtomicU64,
+    // LOAD requests that did not find the key.
+    misses: AtomicU64,
+    // Connections closed because of an incorrect message or a failed write.
+    errors: AtomicU64,
+    // All connections accepted so far, including closed ones.
+    connections: AtomicU64,
+    // Connections that are currently open.
+    active_connections: AtomicUsize,
+    // Connections closed because they were idle for too long.
+    reaped_connections: AtomicU64,
+    // Answers cut at the maximum length of a response.
+    truncated_responses: AtomicU64
+}
+
+// Values of all counters at some moment.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct MetricsSnapshot {
+    pub(crate) stores: u64,
+    pub(crate) loads: u64,
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    pub(crate) errors: u64,
+    pub(crate) connections: u64,
+    pub(crate) active_connections: usize,
+    pub(crate) reaped_connections: u64,
+    pub(crate) truncated_responses: u64,
+    pub(crate) uptime: u64 // In seconds.
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Metrics {
+            started: Instant::now(),
+            stores: AtomicU64::new(0),
+            loads: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            connections: AtomicU64::new(0),
+            active_connections: AtomicUsize::new(0),
+            reaped_connections: AtomicU64::new(0),
+            truncated_responses: AtomicU64::new(0)
+        }
+    }
+
+    pub(crate) fn record_store(&self) {
+        self.stores.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_load(&self, found: bool) {
+        self.loads.fetch_add(1, Ordering::Relaxed);
+        let counter = if found { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_connection_closed(&self) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_connection_reaped(&self) {
+        self.reaped_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_response_truncated(&self) {
+        self.truncated_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn active_connections(&self) -> usize {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    // Counters are read one by one, so the snapshot may mix values
+    // from before and after a concurrent request. Reported by METRICS
+    // request, which is available only with the json feature, and by
+    // the Prometheus endpoint.
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            stores: self.stores.load(Ordering::Relaxed),
+            loads: self.loads.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            reaped_connections: self.reaped_connections.load(Ordering::Relaxed),
+            truncated_responses: self.truncated_responses.load(Ordering::Relaxed),
+            uptime: self.started.elapsed().as_secs()
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    // Encodes the snapshot in the text format of Prometheus.
+    fn to_prometheus(&self) -> String {
+        let metrics = [
+            ("kvs_store_total", "counter", "STORE requests processed.", self.stores),
+            ("kvs_load_total", "counter", "LOAD requests processed.", self.loads),
+            ("kvs_load_hits_total", "counter", "LOAD requests that found the key.", self.hits),
+            ("kvs_load_misses_total", "counter", "LOAD requests that did not find the key.", self.misses),
+            ("kvs_errors_total", "counter", "Connections closed because of an error.", self.errors),
+            ("kvs_connections_total", "counter", "Connections accepted.", self.connections),
+            ("kvs_reaped_connections_total", "counter", "Connections closed for being idle.", self.reaped_connections),
+            ("kvs_truncated_responses_total", "counter", "Responses cut at the maximum length.", self.truncated_responses),
+            ("kvs_active_connections", "gauge", "Connections currently open.", self.active_connections as u64),
+            ("kvs_uptime_seconds", "gauge", "Seconds since the server started.", self.uptime)
+        ];
+        metrics.iter()
+            .map(|(name, kind, help, value)| format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"))
+            .collect()
+    }
+}
+
+// Answers GET /metrics requests on listener with metrics of db in the text
+// format of Prometheus, until shutdown completes. Every connection gets
+// a single answer and is closed, so connections are not tracked.
+pub async fn serve_metrics(listener: TcpListener, db: Db, shutdown: impl Future<Output = ()>) {
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            (socket, peer) = accept_retrying(|| listener.accept()) => {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = answer_metrics_request(socket, &db).await {
+                        warn!(%peer, %error, "answering metrics request failed");
+                    }
+                });
+            },
+            _ = &mut shutdown => return
+        }
+    }
+}
+
+async fn answer_metrics_request(mut socket: TcpStream, db: &Db) -> io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_BYTES {
+            return socket.write_all(&http_response("400 Bad Request", "")).await;
+        }
+        match socket.read(&mut buf).await? {
+            0 => return Ok(()),
+            read_num => head.extend_from_slice(&buf[..read_num])
+        }
+    }
+
+    let response = if head.starts_with(b"GET /metrics HTTP/1.") {
+        http_response("200 OK", &db.metrics.snapshot().to_prometheus())
+    } else {
+        http_response("404 Not Found", "")
+    };
+    socket.write_all(&response).await?;
+    socket.shutdown().await
+}
+
+fn http_response(status: &str, body: &str) -> Vec<u8> {
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    ).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_contains_recorded_events() {
+        let metrics = Metrics::new();
+
+        metrics.record_connection_opened();
+        metrics.record_store();
+        metrics.record_load(true);
+        metrics.record_load(false);
+        metrics.record_load(false);
+        metrics.record_error();
+        metrics.record_connection_reaped();
+        metrics.record_response_truncated();
+
+        let expected = MetricsSnapshot {
+            stores: 1,
+            loads: 3,
+            hits: 1,
+            misses: 2,
+            errors: 1,
+            connections: 1,
+            active_connections: 1,
+            reaped_connections: 1,
+            truncated_responses: 1,
+            uptime: 0
+        };
+        assert_eq!(expected, metrics.snapshot());
+    }
+
+    #[test]
+    fn prometheus_encoding_contains_all_metrics() {
+        let metrics = Metrics::new();
+        metrics.record_connection_opened();
+        metrics.record_store();
+        metrics.record_load(false);
+
+        let encoded = metrics.snapshot().to_prometheus();
+        let samples: Vec<&str> = encoded.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(
+            vec![
+                "kvs_store_total 1",
+                "kvs_load_total 1",
+                "kvs_load_hits_total 0",
+                "kvs_load_misses_total 1",
+                "kvs_errors_total 0",
+                "kvs_connections_total 1",
+                "kvs_reaped_connections_total 0",
+                "kvs_truncated_responses_total 0",
+                "kvs_active_connections 1",
+                "kvs_uptime_seconds 0"
+            ],
+            samples
+        );
+        assert!(encoded.contains("# TYPE kvs_active_connections gauge\n"));
+        assert!(encoded.contains("# TYPE kvs_load_hits_total counter\n"));
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(1000)]
+    async fn metrics_are_served_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let db = Db::default();
+        db.metrics.record_store();
+        tokio::spawn(serve_metrics(listener, db, std::future::pending()));
+
+        let request = |request: &'static str| async move {
+            let mut socket = TcpStream::connect(address).await.unwrap();
+            socket.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            socket.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = request("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP kvs_store_total "));
+        assert!(response.contains("\nkvs_store_total 1\n"));
+        let response = request("GET /kv/k HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn closed_connections_are_not_active() {
+        let metrics = Metrics::new();
+
+        metrics.record_connection_opened();
+        metrics.record_connection_opened();
+        metrics.record_connection_closed();
+
+        assert_eq!(1, metrics.active_connections());
+    }
+}
@@ -1,244 +1,2752 @@
 // author - Patryk Jędrzejczak
 
 use regex::Regex;
+use std::sync::LazyLock;
 
-use super::{TaskError, Request, StoreRequest, LoadRequest};
+use super::{
+    TaskError, Request, StoreRequest, StoreExpiringRequest, LoadRequest, LoadTransformedRequest, WaitRequest, InitRequest, CopyRequest, PrefixRequest,
+    DeleteRequest, ExistsRequest, MultiDeleteRequest, MultiExistsRequest, GetOrSetRequest, AppendIfNewRequest,
+    PushVersionRequest, GetVersionRequest, PatternSubscribeRequest, ExtremumRequest, IncrementRequest, DecrementFloorRequest,
+    DecrementDeleteRequest, MultiCompareAndSetRequest,
+    SnapshotRequest, SelectRequest, AckRequest, ChunkedRequest, CompareAndSwapRequest, MultiGetRequest, ScanRequest, DumpRequest,
+    SetConfigRequest, TracedRequest
+};
 
-fn match_regex(message: &str, pattern: &str) -> Result<bool, TaskError> {
-    match Regex::new(pattern) {
-        Ok(regex) => Ok(regex.is_match(message)),
-        Err(_) => Err(TaskError)
+// Pattern of a single character of a key. Keys may contain letters,
+// digits and underscores, while values consist only of lowercase letters.
+const KEY: &str = "[A-Za-z0-9_]";
+
+// Returns true if message matches the pattern, given like arguments of
+// format!. Every use has its own regex, compiled on the first call only,
+// so parsing takes no lock shared by connections and builds no patterns.
+// Patterns are constant, so compiling them never fails once tests pass.
+macro_rules! match_regex {
+    ($message:expr, $($pattern:tt)+) => {{
+        static REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(&format!($($pattern)+)).unwrap());
+        REGEX.is_match($message)
+    }};
+}
+
+// Patterns of whole arguments for could_become_request.
+static KEY_ARG: LazyLock<Regex> = LazyLock::new(|| Regex::new(&format!("^{KEY}*$")).unwrap());
+static VALUE_ARG: LazyLock<Regex> = LazyLock::new(|| Regex::new("^[a-z]*$").unwrap());
+
+static SETTING_ARG: LazyLock<Regex> = LazyLock::new(|| Regex::new("^[a-z0-9]*$").unwrap());
+
+// Prefix trace:id: of a request, e.g. trace:4bf92f35:LOAD$key$, where id
+// consists of letters, digits, underscores and hyphens.
+static TRACE_PREFIX: LazyLock<Regex> = LazyLock::new(|| Regex::new("^trace:([A-Za-z0-9_-]+):").unwrap());
+static NUMBER_ARG: LazyLock<Regex> = LazyLock::new(|| Regex::new("^[0-9]*$").unwrap());
+static SIGNED_ARG: LazyLock<Regex> = LazyLock::new(|| Regex::new("^-?[0-9]*$").unwrap());
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct STORE request.
+fn is_store_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^STORE\${KEY}*\$[a-z]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct STORECHECK request.
+fn is_store_check_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^STORECHECK\${KEY}*\$[a-z]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct STOREB request, i.e. STOREB$key$len$ followed by
+// len bytes of the value. Checks only that all bytes of the value came.
+fn is_store_binary_request(message: &str) -> Result<bool, TaskError> {
+    if !match_regex!(message, r"^STOREB\${KEY}*\$[0-9]+\$") {
+        return Ok(false);
+    }
+
+    let (args, value_and_rest) = split_request(message, 2);
+    let len: usize = args[1].parse().map_err(|_| TaskError::ParseError)?;
+    Ok(value_and_rest.len() >= len)
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct STOREX request.
+fn is_store_expiring_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^STOREX\${KEY}*\$[a-z]*\$[0-9]+\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct LOAD request.
+fn is_load_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^LOAD\${KEY}*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct LOADT request. Unknown transformations are correct.
+fn is_load_transformed_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^LOADT\${KEY}*\$[a-z]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct DELETE request.
+fn is_delete_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^DELETE\${KEY}*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct EXISTS request.
+fn is_exists_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^EXISTS\${KEY}*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct WAIT request.
+fn is_wait_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^WAIT\${KEY}*\$[0-9]+\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct INIT request.
+fn is_init_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^INIT\${KEY}*\$[a-z]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct COPY request.
+fn is_copy_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^COPY\${KEY}*\${KEY}*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct GETORSET request.
+fn is_get_or_set_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^GETORSET\${KEY}*\$[a-z]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct APPENDIFNEW request.
+fn is_append_if_new_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^APPENDIFNEW\${KEY}*\$[a-z]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct PUSHVER request.
+fn is_push_version_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^PUSHVER\${KEY}*\$[a-z]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct GETVER request.
+fn is_get_version_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^GETVER\${KEY}*\$[0-9]+\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct MAXSET request.
+fn is_max_set_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^MAXSET\${KEY}*\$-?[0-9]+\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct MINSET request.
+fn is_min_set_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^MINSET\${KEY}*\$-?[0-9]+\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct INCR request.
+fn is_increment_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^INCR\${KEY}*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct DECR request.
+fn is_decrement_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^DECR\${KEY}*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct DECRDEL request.
+fn is_decrement_delete_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^DECRDEL\${KEY}*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct DECRFLOOR request.
+fn is_decrement_floor_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^DECRFLOOR\${KEY}*\$-?[0-9]+\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct SELECT request. Name of a namespace is not empty
+// and consists of lowercase letters.
+fn is_select_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^SELECT\$[a-z]+\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct PREFIX request.
+fn is_prefix_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^PREFIX\${KEY}*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct PSUBSCRIBE request.
+fn is_pattern_subscribe_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^PSUBSCRIBE\${KEY}*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct MDEL request. The list of keys ends with an empty
+// argument, so listed keys cannot be empty.
+fn is_multi_delete_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^MDEL\$({KEY}+\$)*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct MEXISTS request. The list of keys has the same
+// form as in MDEL request.
+fn is_multi_exists_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^MEXISTS\$({KEY}+\$)*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct MGET request. The list of keys has the same
+// form as in MDEL request.
+fn is_multi_get_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^MGET\$({KEY}+\$)*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct CAS request.
+fn is_compare_and_swap_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^CAS\${KEY}*\$[a-z]*\$[a-z]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct MCAS request, e.g. MCAS$a$x$b$y$THEN$c$z$.
+fn is_multi_compare_and_set_request(message: &str) -> Result<bool, TaskError> {
+    Ok(check_multi_compare_and_set_request(message) == Some(true))
+}
+
+// Name of the argument of MCAS request that separates conditions from
+// the pair to store. It is reserved, so no condition can have it as a key.
+const THEN: &str = "THEN";
+
+// Checks MCAS$key1$value1$...$keyn$valuen$THEN$key$value$ at the beginning
+// of message. Returns Some(true) if message starts with a correct MCAS
+// request, Some(false) if it could still become one and None otherwise.
+fn check_multi_compare_and_set_request(message: &str) -> Option<bool> {
+    let fields: Vec<&str> = message.strip_prefix("MCAS$")?.split('$').collect();
+    let mut pos = 0;
+    loop {
+        // THEN that is not the last field, so it is complete.
+        let then = fields[pos] == THEN && pos + 1 < fields.len();
+        if then {
+            pos += 1;
+        }
+        for arg in [&KEY_ARG, &VALUE_ARG] {
+            if !arg.is_match(fields[pos]) {
+                return None;
+            }
+            // The last field is not followed by '$', so it is incomplete.
+            if pos + 1 == fields.len() {
+                return Some(false);
+            }
+            pos += 1;
+        }
+        if then {
+            return Some(true);
+        }
+    }
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct SNAPSHOT request. Name of a snapshot is a file
+// name, so it cannot be empty.
+fn is_snapshot_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^SNAPSHOT\$[a-z]+\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct KEYS request.
+fn is_keys_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^KEYS\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct SCAN request.
+fn is_scan_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^SCAN\${KEY}*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct USAGE request.
+fn is_usage_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^USAGE\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct COUNT request.
+fn is_count_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^COUNT\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct SETCONFIG request.
+fn is_set_config_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^SETCONFIG\$[a-z]*\$[a-z0-9]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct ACK request.
+fn is_ack_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^ACK\$[a-z]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct CHUNKED request.
+fn is_chunked_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^CHUNKED\$[0-9]+\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct DUMP request.
+fn is_dump_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^DUMP\$[a-z]*\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct LIMITS request.
+fn is_limits_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^LIMITS\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct CONNS request.
+fn is_conns_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^CONNS\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct TIME request.
+fn is_time_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^TIME\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct QUIT request.
+fn is_quit_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^QUIT\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct PING request.
+fn is_ping_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^PING\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct NOFLUSH request.
+fn is_no_flush_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^NOFLUSH\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct FLUSH request.
+fn is_flush_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^FLUSH\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct FLUSHALL request.
+fn is_flush_all_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^FLUSHALL\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct JSON request.
+#[cfg(feature = "json")]
+fn is_json_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^JSON\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct METRICS request.
+#[cfg(feature = "json")]
+fn is_metrics_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^METRICS\$"))
+}
+
+// Returns true if there exists a prefix of a message parameter
+// that is a correct PEEK request.
+fn is_peek_request(message: &str) -> Result<bool, TaskError> {
+    Ok(match_regex!(message, r"^PEEK\$"))
+}
+
+// Returns true if message could become a correct request called name
+// with arguments matching given patterns, each followed by '$'.
+// Every pattern has to match all prefixes of a correct argument.
+fn could_become_request(message: &str, name: &str, args: &[&Regex]) -> Result<bool, TaskError> {
+    let Some(rest) = message.strip_prefix(name).and_then(|rest| rest.strip_prefix('$')) else {
+        // The message is correct only while the name and '$' are still coming.
+        return Ok(message.len() <= name.len() && name.starts_with(message));
+    };
+
+    // Arguments before the last '$' are complete, the last one may not be.
+    let fields: Vec<&str> = rest.split('$').collect();
+    Ok(fields.len() <= args.len() && fields.iter().zip(args).all(|(field, arg)| arg.is_match(field)))
+}
+
+// Returns true if message could become a correct STORE request.
+fn could_become_store_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "STORE", &[&KEY_ARG, &VALUE_ARG])
+}
+
+// Returns true if message could become a correct STORECHECK request.
+fn could_become_store_check_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "STORECHECK", &[&KEY_ARG, &VALUE_ARG])
+}
+
+// Returns true if message could become a correct STOREB request,
+// including when only some bytes of the value came.
+fn could_become_store_binary_request(message: &str) -> Result<bool, TaskError> {
+    if !match_regex!(message, r"^STOREB\${KEY}*\$[0-9]+\$") {
+        return could_become_request(message, "STOREB", &[&KEY_ARG, &NUMBER_ARG]);
+    }
+
+    let (args, value_and_rest) = split_request(message, 2);
+    Ok(args[1].parse().is_ok_and(|len: usize| value_and_rest.len() < len))
+}
+
+// Returns true if message could become a correct STOREX request.
+fn could_become_store_expiring_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "STOREX", &[&KEY_ARG, &VALUE_ARG, &NUMBER_ARG])
+}
+
+// Returns true if message could become a correct LOAD request.
+fn could_become_load_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "LOAD", &[&KEY_ARG])
+}
+
+// Returns true if message could become a correct LOADT request.
+fn could_become_load_transformed_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "LOADT", &[&KEY_ARG, &VALUE_ARG])
+}
+
+// Returns true if message could become a correct DELETE request.
+fn could_become_delete_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "DELETE", &[&KEY_ARG])
+}
+
+// Returns true if message could become a correct EXISTS request.
+fn could_become_exists_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "EXISTS", &[&KEY_ARG])
+}
+
+// Returns true if message could become a correct WAIT request.
+fn could_become_wait_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "WAIT", &[&KEY_ARG, &NUMBER_ARG])
+}
+
+// Returns true if message could become a correct INIT request.
+fn could_become_init_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "INIT", &[&KEY_ARG, &VALUE_ARG])
+}
+
+// Returns true if message could become a correct COPY request.
+fn could_become_copy_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "COPY", &[&KEY_ARG, &KEY_ARG])
+}
+
+// Returns true if message could become a correct GETORSET request.
+fn could_become_get_or_set_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "GETORSET", &[&KEY_ARG, &VALUE_ARG])
+}
+
+// Returns true if message could become a correct APPENDIFNEW request.
+fn could_become_append_if_new_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "APPENDIFNEW", &[&KEY_ARG, &VALUE_ARG])
+}
+
+// Returns true if message could become a correct PUSHVER request.
+fn could_become_push_version_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "PUSHVER", &[&KEY_ARG, &VALUE_ARG])
+}
+
+// Returns true if message could become a correct GETVER request.
+fn could_become_get_version_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "GETVER", &[&KEY_ARG, &NUMBER_ARG])
+}
+
+// Returns true if message could become a correct MAXSET request.
+fn could_become_max_set_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "MAXSET", &[&KEY_ARG, &SIGNED_ARG])
+}
+
+// Returns true if message could become a correct MINSET request.
+fn could_become_min_set_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "MINSET", &[&KEY_ARG, &SIGNED_ARG])
+}
+
+// Returns true if message could become a correct INCR request.
+fn could_become_increment_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "INCR", &[&KEY_ARG])
+}
+
+// Returns true if message could become a correct DECR request.
+fn could_become_decrement_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "DECR", &[&KEY_ARG])
+}
+
+// Returns true if message could become a correct DECRDEL request.
+fn could_become_decrement_delete_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "DECRDEL", &[&KEY_ARG])
+}
+
+// Returns true if message could become a correct DECRFLOOR request.
+fn could_become_decrement_floor_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "DECRFLOOR", &[&KEY_ARG, &SIGNED_ARG])
+}
+
+// Returns true if message could become a correct SELECT request.
+fn could_become_select_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "SELECT", &[&VALUE_ARG])
+}
+
+// Returns true if message could become a correct PREFIX request.
+fn could_become_prefix_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "PREFIX", &[&KEY_ARG])
+}
+
+// Returns true if message could become a correct PSUBSCRIBE request.
+fn could_become_pattern_subscribe_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "PSUBSCRIBE", &[&KEY_ARG])
+}
+
+// Returns true if message could become a correct request called name
+// with a list of non-empty keys ended with an empty argument. Such
+// requests have a variable number of arguments, so they cannot be
+// checked with could_become_request.
+fn could_become_key_list_request(message: &str, name: &str) -> Result<bool, TaskError> {
+    let Some(rest) = message.strip_prefix(name).and_then(|rest| rest.strip_prefix('$')) else {
+        return Ok(message.len() <= name.len() && name.starts_with(message));
+    };
+
+    let fields: Vec<&str> = rest.split('$').collect();
+    let (last, keys) = fields.split_last().unwrap();
+    Ok(keys.iter().all(|key| !key.is_empty() && KEY_ARG.is_match(key)) && KEY_ARG.is_match(last))
+}
+
+// Returns true if message could become a correct MDEL request.
+fn could_become_multi_delete_request(message: &str) -> Result<bool, TaskError> {
+    could_become_key_list_request(message, "MDEL")
+}
+
+// Returns true if message could become a correct MEXISTS request.
+fn could_become_multi_exists_request(message: &str) -> Result<bool, TaskError> {
+    could_become_key_list_request(message, "MEXISTS")
+}
+
+// Returns true if message could become a correct MGET request.
+fn could_become_multi_get_request(message: &str) -> Result<bool, TaskError> {
+    could_become_key_list_request(message, "MGET")
+}
+
+// Returns true if message could become a correct CAS request.
+fn could_become_compare_and_swap_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "CAS", &[&KEY_ARG, &VALUE_ARG, &VALUE_ARG])
+}
+
+// Returns true if message could become a correct MCAS request.
+fn could_become_multi_compare_and_set_request(message: &str) -> Result<bool, TaskError> {
+    let header = "MCAS$";
+    if message.len() < header.len() {
+        return Ok(header.starts_with(message));
+    }
+
+    Ok(check_multi_compare_and_set_request(message) == Some(false))
+}
+
+// Returns true if message could become a correct SNAPSHOT request.
+fn could_become_snapshot_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "SNAPSHOT", &[&VALUE_ARG])
+}
+
+// Returns true if message could become a correct KEYS request.
+fn could_become_keys_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "KEYS", &[])
+}
+
+// Returns true if message could become a correct SCAN request.
+fn could_become_scan_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "SCAN", &[&KEY_ARG])
+}
+
+// Returns true if message could become a correct USAGE request.
+fn could_become_usage_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "USAGE", &[])
+}
+
+// Returns true if message could become a correct COUNT request.
+fn could_become_count_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "COUNT", &[])
+}
+
+// Returns true if message could become a correct SETCONFIG request.
+fn could_become_set_config_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "SETCONFIG", &[&VALUE_ARG, &SETTING_ARG])
+}
+
+// Returns true if message could become a correct ACK request.
+fn could_become_ack_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "ACK", &[&VALUE_ARG])
+}
+
+// Returns true if message could become a correct CHUNKED request.
+fn could_become_chunked_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "CHUNKED", &[&NUMBER_ARG])
+}
+
+// Returns true if message could become a correct DUMP request.
+fn could_become_dump_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "DUMP", &[&VALUE_ARG])
+}
+
+// Returns true if message could become a correct LIMITS request.
+fn could_become_limits_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "LIMITS", &[])
+}
+
+// Returns true if message could become a correct CONNS request.
+fn could_become_conns_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "CONNS", &[])
+}
+
+// Returns true if message could become a correct TIME request.
+fn could_become_time_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "TIME", &[])
+}
+
+// Returns true if message could become a correct QUIT request.
+fn could_become_quit_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "QUIT", &[])
+}
+
+// Returns true if message could become a correct PING request.
+fn could_become_ping_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "PING", &[])
+}
+
+// Returns true if message could become a correct NOFLUSH request.
+fn could_become_no_flush_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "NOFLUSH", &[])
+}
+
+// Returns true if message could become a correct FLUSH request.
+fn could_become_flush_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "FLUSH", &[])
+}
+
+// Returns true if message could become a correct FLUSHALL request.
+fn could_become_flush_all_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "FLUSHALL", &[])
+}
+
+// Returns true if message could become a correct JSON request.
+// JSON requests are correct only with the json feature enabled.
+fn could_become_json_request(message: &str) -> Result<bool, TaskError> {
+    Ok(cfg!(feature = "json") && could_become_request(message, "JSON", &[])?)
+}
+
+// Returns true if message could become a correct METRICS request.
+// METRICS requests are correct only with the json feature enabled.
+fn could_become_metrics_request(message: &str) -> Result<bool, TaskError> {
+    Ok(cfg!(feature = "json") && could_become_request(message, "METRICS", &[])?)
+}
+
+// Returns true if message could become a correct PEEK request.
+fn could_become_peek_request(message: &str) -> Result<bool, TaskError> {
+    could_become_request(message, "PEEK", &[])
+}
+
+type CouldBecomeFn = fn(&str) -> Result<bool, TaskError>;
+
+// Names of all requests together with functions checking if
+// a message could become them.
+static PARTIAL_REQUESTS: &[(&str, CouldBecomeFn)] = &[
+    ("STORE", could_become_store_request),
+    ("STOREB", could_become_store_binary_request),
+    ("STOREX", could_become_store_expiring_request),
+    ("STORECHECK", could_become_store_check_request),
+    ("LOAD", could_become_load_request),
+    ("LOADT", could_become_load_transformed_request),
+    ("DELETE", could_become_delete_request),
+    ("EXISTS", could_become_exists_request),
+    ("WAIT", could_become_wait_request),
+    ("INIT", could_become_init_request),
+    ("COPY", could_become_copy_request),
+    ("GETORSET", could_become_get_or_set_request),
+    ("APPENDIFNEW", could_become_append_if_new_request),
+    ("PUSHVER", could_become_push_version_request),
+    ("GETVER", could_become_get_version_request),
+    ("MAXSET", could_become_max_set_request),
+    ("MINSET", could_become_min_set_request),
+    ("INCR", could_become_increment_request),
+    ("DECR", could_become_decrement_request),
+    ("DECRFLOOR", could_become_decrement_floor_request),
+    ("DECRDEL", could_become_decrement_delete_request),
+    ("PREFIX", could_become_prefix_request),
+    ("SELECT", could_become_select_request),
+    ("PSUBSCRIBE", could_become_pattern_subscribe_request),
+    ("MDEL", could_become_multi_delete_request),
+    ("MEXISTS", could_become_multi_exists_request),
+    ("MGET", could_become_multi_get_request),
+    ("CAS", could_become_compare_and_swap_request),
+    ("MCAS", could_become_multi_compare_and_set_request),
+    ("SNAPSHOT", could_become_snapshot_request),
+    ("KEYS", could_become_keys_request),
+    ("SCAN", could_become_scan_request),
+    ("USAGE", could_become_usage_request),
+    ("COUNT", could_become_count_request),
+    ("DUMP", could_become_dump_request),
+    ("LIMITS", could_become_limits_request),
+    ("CONNS", could_become_conns_request),
+    ("TIME", could_become_time_request),
+    ("PING", could_become_ping_request),
+    ("NOFLUSH", could_become_no_flush_request),
+    ("FLUSH", could_become_flush_request),
+    ("FLUSHALL", could_become_flush_all_request),
+    ("QUIT", could_become_quit_request),
+    ("JSON", could_become_json_request),
+    ("METRICS", could_become_metrics_request),
+    ("SETCONFIG", could_become_set_config_request),
+    ("ACK", could_become_ack_request),
+    ("CHUNKED", could_become_chunked_request),
+    ("PEEK", could_become_peek_request)
+];
+
+// Returns names of all requests that message could become.
+pub fn could_become_requests(message: &str) -> Result<Vec<&'static str>, TaskError> {
+    let mut names = Vec::new();
+    for (name, could_become) in PARTIAL_REQUESTS {
+        if could_become(message)? {
+            names.push(*name);
+        }
+    }
+    Ok(names)
+}
+
+// Result of parsing a request at the beginning of a message: the request
+// and the rest of the message, borrowed so that it is not copied.
+pub type ParseResult<'a> = Result<Option<(Request, &'a str)>, TaskError>;
+
+// Splits a message with a prefix that is a correct request with
+// args_num arguments from NAME$arg1$...$argn$rest to ([arg1, ..., argn], rest).
+fn split_request(message: &str, args_num: usize) -> (Vec<String>, &str) {
+    let dollars: Vec<usize> = message.match_indices('$').map(|(pos, _)| pos).take(args_num + 1).collect();
+    let args = dollars.windows(2).map(|pair| message[pair[0] + 1..pair[1]].to_string()).collect();
+    (args, &message[dollars[args_num] + 1..])
+}
+
+// Splits a message with a prefix that is a correct STORE request
+// from STORE$key$value$rest to (key, value, rest).
+fn split_store_request(message: &str) -> (String, String, &str) {
+    let (mut args, rest) = split_request(message, 2);
+    let value = args.pop().unwrap();
+    let key = args.pop().unwrap();
+    (key, value, rest)
+}
+
+// Splits a message with a prefix that is a correct LOAD request
+// from LOAD$key$rest to (key, rest).
+fn split_load_request(message: &str) -> (String, &str) {
+    let (mut args, rest) = split_request(message, 1);
+    (args.pop().unwrap(), rest)
+}
+
+// Splits a message with a prefix that is a correct STOREB request
+// from STOREB$key$len$valuerest to (key, value, rest), where value has
+// len bytes. Returns TaskError if len bytes end in the middle of a character.
+fn split_store_binary_request(message: &str) -> Result<(String, String, &str), TaskError> {
+    let (mut args, value_and_rest) = split_request(message, 2);
+    let len: usize = args.pop().unwrap().parse().map_err(|_| TaskError::ParseError)?;
+    let key = args.pop().unwrap();
+    let value = value_and_rest.get(..len).ok_or(TaskError::ParseError)?;
+    Ok((key, value.to_string(), &value_and_rest[len..]))
+}
+
+// Splits a message with a prefix that is a correct STOREX request
+// from STOREX$key$value$ttl$rest to (key, value, ttl, rest).
+fn split_store_expiring_request(message: &str) -> (String, String, String, &str) {
+    let (mut args, rest) = split_request(message, 3);
+    let ttl = args.pop().unwrap();
+    let value = args.pop().unwrap();
+    let key = args.pop().unwrap();
+    (key, value, ttl, rest)
+}
+
+// Splits a message with a prefix that is a correct WAIT request
+// from WAIT$key$timeout$rest to (key, timeout, rest).
+fn split_wait_request(message: &str) -> (String, String, &str) {
+    let (mut args, rest) = split_request(message, 2);
+    let timeout = args.pop().unwrap();
+    let key = args.pop().unwrap();
+    (key, timeout, rest)
+}
+
+// Splits a message with a prefix that is a correct request with a list
+// of keys from NAME$key1$...$keyn$$rest to ([key1, ..., keyn], rest).
+fn split_key_list_request(message: &str) -> (Vec<String>, &str) {
+    let header_end = message.find('$').unwrap();
+    let list_end = header_end + message[header_end..].find("$$").unwrap();
+    let keys = message[header_end + 1..list_end + 1].split_terminator('$').map(String::from).collect();
+    (keys, &message[list_end + 2..])
+}
+
+// Splits a message with a prefix that is a correct CAS request
+// from CAS$key$expected$value$rest to (key, expected, value, rest).
+fn split_compare_and_swap_request(message: &str) -> (String, String, String, &str) {
+    let (mut args, rest) = split_request(message, 3);
+    let value = args.pop().unwrap();
+    let expected = args.pop().unwrap();
+    let key = args.pop().unwrap();
+    (key, expected, value, rest)
+}
+
+// Splits a message with a prefix that is a correct MCAS request from
+// MCAS$key1$value1$...$keyn$valuen$THEN$key$value$rest
+// to ([(key1, value1), ..., (keyn, valuen)], key, value, rest).
+fn split_multi_compare_and_set_request(message: &str) -> (Vec<(String, String)>, String, String, &str) {
+    // THEN is the first key that is THEN, because no condition can have it.
+    let conditions_num = message.split('$').skip(1).step_by(2).position(|key| key == THEN).unwrap();
+    let (mut args, rest) = split_request(message, 2 * conditions_num + 3);
+    let value = args.pop().unwrap();
+    let key = args.pop().unwrap();
+    args.pop();
+    let conditions = args.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+    (conditions, key, value, rest)
+}
+
+// If message contains a prefix that is a correct request, returns
+// Some(request). If message is incorrect, returns TaskError.
+// Otherwise, returns None. Removes request from message.
+pub fn try_parse_request(message: &mut String) -> Result<Option<Request>, TaskError> {
+    take_request(message, parse_request)
+}
+
+// Removes the request found by parse from the beginning of message.
+// Removing copies the rest of the message, so process_message skips
+// processed requests instead and removes them all at once.
+pub fn take_request(message: &mut String, parse: fn(&str) -> ParseResult<'_>) -> Result<Option<Request>, TaskError> {
+    let Some((request, rest)) = parse(message)? else {
+        return Ok(None);
+    };
+    message.drain(..message.len() - rest.len());
+    Ok(Some(request))
+}
+
+// If message contains a prefix that is a correct request, returns
+// Some((request, rest)), where rest is the part of message following the
+// request. If message is incorrect, returns TaskError. Otherwise, returns None.
+// A request may be preceded by a trace prefix, but only by one.
+pub fn parse_request(message: &str) -> ParseResult<'_> {
+    let Some(prefix) = TRACE_PREFIX.captures(message) else {
+        return parse_untraced_request(message);
+    };
+    let id = prefix[1].to_string();
+    let parsed = parse_untraced_request(&message[prefix[0].len()..])?;
+    Ok(parsed.map(|(request, rest)| (Request::Traced(TracedRequest::new(id, request)), rest)))
+}
+
+fn parse_untraced_request(message: &str) -> ParseResult<'_> {
+    #[cfg(feature = "json")]
+    if is_json_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        return Ok(Some((Request::Json, rest)));
+    }
+
+    #[cfg(feature = "json")]
+    if is_metrics_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        return Ok(Some((Request::Metrics, rest)));
+    }
+
+    if is_store_request(message)? {
+        let (key, value, rest) = split_store_request(message);
+        Ok(Some((Request::Store(StoreRequest::new(key, value)), rest)))
+    } else if is_store_check_request(message)? {
+        // STORECHECK request has the same form as STORE request.
+        let (key, value, rest) = split_store_request(message);
+        Ok(Some((Request::StoreCheck(StoreRequest::new(key, value)), rest)))
+    } else if is_store_binary_request(message)? {
+        // STOREB request differs from STORE request only in the form of the value.
+        let (key, value, rest) = split_store_binary_request(message)?;
+        Ok(Some((Request::Store(StoreRequest::new(key, value)), rest)))
+    } else if is_store_expiring_request(message)? {
+        let (key, value, ttl, rest) = split_store_expiring_request(message);
+        // TTL that does not fit in u32 makes the request incorrect.
+        let ttl = ttl.parse().map_err(|_| TaskError::ParseError)?;
+        Ok(Some((Request::StoreExpiring(StoreExpiringRequest::new(key, value, ttl)), rest)))
+    } else if is_load_request(message)? {
+        let (key, rest) = split_load_request(message);
+        Ok(Some((Request::Load(LoadRequest::new(key)), rest)))
+    } else if is_delete_request(message)? {
+        // DELETE request has the same form as LOAD request.
+        let (key, rest) = split_load_request(message);
+        Ok(Some((Request::Delete(DeleteRequest::new(key)), rest)))
+    } else if is_exists_request(message)? {
+        // EXISTS request has the same form as LOAD request.
+        let (key, rest) = split_load_request(message);
+        Ok(Some((Request::Exists(ExistsRequest::new(key)), rest)))
+    } else if is_load_transformed_request(message)? {
+        // LOADT request has the same form as STORE request.
+        let (key, transform, rest) = split_store_request(message);
+        Ok(Some((Request::LoadTransformed(LoadTransformedRequest::new(key, transform)), rest)))
+    } else if is_wait_request(message)? {
+        let (key, timeout, rest) = split_wait_request(message);
+        // Timeout that does not fit in u32 makes the request incorrect.
+        let timeout = timeout.parse().map_err(|_| TaskError::ParseError)?;
+        Ok(Some((Request::Wait(WaitRequest::new(key, timeout)), rest)))
+    } else if is_init_request(message)? {
+        // INIT request has the same form as STORE request.
+        let (key, value, rest) = split_store_request(message);
+        Ok(Some((Request::Init(InitRequest::new(key, value)), rest)))
+    } else if is_copy_request(message)? {
+        // COPY request has the same form as STORE request.
+        let (source, destination, rest) = split_store_request(message);
+        Ok(Some((Request::Copy(CopyRequest::new(source, destination)), rest)))
+    } else if is_get_or_set_request(message)? {
+        // GETORSET request has the same form as STORE request.
+        let (key, default, rest) = split_store_request(message);
+        Ok(Some((Request::GetOrSet(GetOrSetRequest::new(key, default)), rest)))
+    } else if is_append_if_new_request(message)? {
+        // APPENDIFNEW request has the same form as STORE request.
+        let (key, value, rest) = split_store_request(message);
+        Ok(Some((Request::AppendIfNew(AppendIfNewRequest::new(key, value)), rest)))
+    } else if is_push_version_request(message)? {
+        // PUSHVER request has the same form as STORE request.
+        let (key, value, rest) = split_store_request(message);
+        Ok(Some((Request::PushVersion(PushVersionRequest::new(key, value)), rest)))
+    } else if is_get_version_request(message)? {
+        // GETVER request has the same form as WAIT request.
+        let (key, index, rest) = split_wait_request(message);
+        // Index that does not fit in usize makes the request incorrect.
+        let index = index.parse().map_err(|_| TaskError::ParseError)?;
+        Ok(Some((Request::GetVersion(GetVersionRequest::new(key, index)), rest)))
+    } else if is_max_set_request(message)? || is_min_set_request(message)? {
+        // MAXSET and MINSET requests have the same form as WAIT request.
+        let (key, value, rest) = split_wait_request(message);
+        // Value that does not fit in i64 makes the request incorrect.
+        let request = ExtremumRequest::new(key, value.parse().map_err(|_| TaskError::ParseError)?);
+        let request = if message.starts_with("MAXSET") {
+            Request::MaxSet(request)
+        } else {
+            Request::MinSet(request)
+        };
+        Ok(Some((request, rest)))
+    } else if is_increment_request(message)? || is_decrement_request(message)? {
+        // INCR and DECR requests have the same form as LOAD request.
+        let (key, rest) = split_load_request(message);
+        let delta = if message.starts_with("INCR") { 1 } else { -1 };
+        Ok(Some((Request::Increment(IncrementRequest::new(key, delta)), rest)))
+    } else if is_decrement_delete_request(message)? {
+        // DECRDEL request has the same form as LOAD request.
+        let (key, rest) = split_load_request(message);
+        Ok(Some((Request::DecrementDelete(DecrementDeleteRequest::new(key)), rest)))
+    } else if is_decrement_floor_request(message)? {
+        // DECRFLOOR request has the same form as WAIT request.
+        let (key, floor, rest) = split_wait_request(message);
+        // Floor that does not fit in i64 makes the request incorrect.
+        let floor = floor.parse().map_err(|_| TaskError::ParseError)?;
+        Ok(Some((Request::DecrementFloor(DecrementFloorRequest::new(key, floor)), rest)))
+    } else if is_prefix_request(message)? {
+        // PREFIX request has the same form as LOAD request.
+        let (prefix, rest) = split_load_request(message);
+        Ok(Some((Request::Prefix(PrefixRequest::new(prefix)), rest)))
+    } else if is_select_request(message)? {
+        // SELECT request has the same form as LOAD request.
+        let (namespace, rest) = split_load_request(message);
+        Ok(Some((Request::Select(SelectRequest::new(namespace)), rest)))
+    } else if is_pattern_subscribe_request(message)? {
+        // PSUBSCRIBE request has the same form as LOAD request.
+        let (prefix, rest) = split_load_request(message);
+        Ok(Some((Request::PatternSubscribe(PatternSubscribeRequest::new(prefix)), rest)))
+    } else if is_multi_delete_request(message)? {
+        let (keys, rest) = split_key_list_request(message);
+        Ok(Some((Request::MultiDelete(MultiDeleteRequest::new(keys)), rest)))
+    } else if is_multi_exists_request(message)? {
+        // MEXISTS request has the same form as MDEL request.
+        let (keys, rest) = split_key_list_request(message);
+        Ok(Some((Request::MultiExists(MultiExistsRequest::new(keys)), rest)))
+    } else if is_multi_get_request(message)? {
+        // MGET request has the same form as MDEL request.
+        let (keys, rest) = split_key_list_request(message);
+        Ok(Some((Request::MultiGet(MultiGetRequest::new(keys)), rest)))
+    } else if is_compare_and_swap_request(message)? {
+        let (key, expected, value, rest) = split_compare_and_swap_request(message);
+        Ok(Some((Request::CompareAndSwap(CompareAndSwapRequest::new(key, expected, value)), rest)))
+    } else if is_multi_compare_and_set_request(message)? {
+        let (conditions, key, value, rest) = split_multi_compare_and_set_request(message);
+        Ok(Some((Request::MultiCompareAndSet(MultiCompareAndSetRequest::new(conditions, key, value)), rest)))
+    } else if is_snapshot_request(message)? {
+        // SNAPSHOT request has the same form as LOAD request.
+        let (name, rest) = split_load_request(message);
+        Ok(Some((Request::Snapshot(SnapshotRequest::new(name)), rest)))
+    } else if is_keys_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::Keys, rest)))
+    } else if is_scan_request(message)? {
+        // SCAN request has the same form as LOAD request.
+        let (prefix, rest) = split_load_request(message);
+        Ok(Some((Request::Scan(ScanRequest::new(prefix)), rest)))
+    } else if is_usage_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::Usage, rest)))
+    } else if is_count_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::Count, rest)))
+    } else if is_dump_request(message)? {
+        // DUMP request has the same form as LOAD request.
+        let (format, rest) = split_load_request(message);
+        Ok(Some((Request::Dump(DumpRequest::new(format)), rest)))
+    } else if is_ack_request(message)? {
+        // ACK request has the same form as LOAD request.
+        let (mode, rest) = split_load_request(message);
+        Ok(Some((Request::Ack(AckRequest::new(mode)), rest)))
+    } else if is_chunked_request(message)? {
+        let (chunk_bytes, rest) = split_load_request(message);
+        // Length that does not fit in usize makes the request incorrect.
+        let chunk_bytes = chunk_bytes.parse().map_err(|_| TaskError::ParseError)?;
+        Ok(Some((Request::Chunked(ChunkedRequest::new(chunk_bytes)), rest)))
+    } else if is_limits_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::Limits, rest)))
+    } else if is_conns_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::Conns, rest)))
+    } else if is_time_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::Time, rest)))
+    } else if is_ping_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::Ping, rest)))
+    } else if is_no_flush_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::NoFlush, rest)))
+    } else if is_flush_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::Flush, rest)))
+    } else if is_flush_all_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::FlushAll, rest)))
+    } else if is_quit_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::Quit, rest)))
+    } else if is_set_config_request(message)? {
+        // SETCONFIG request has the same form as STORE request, but its value may contain digits.
+        let (name, value, rest) = split_store_request(message);
+        Ok(Some((Request::SetConfig(SetConfigRequest::new(name, value)), rest)))
+    } else if is_peek_request(message)? {
+        let (_, rest) = split_request(message, 0);
+        Ok(Some((Request::Peek, rest)))
+    } else if could_become_trace_prefix(message) || !could_become_requests(message)?.is_empty() {
+        Ok(None)
+    } else {
+        Err(TaskError::ParseError)
+    }
+}
+
+fn could_become_trace_prefix(message: &str) -> bool {
+    "trace:".starts_with(message) || match_regex!(message, "^trace:[A-Za-z0-9_-]*$")
+}
+
+// In the framed protocol every request is preceded by its length in
+// bytes and '$', e.g. 16$STORE$key$value$. If message contains a prefix
+// that is a complete frame, returns Some((request, rest)), like
+// parse_request. If message is incorrect, returns TaskError. Otherwise,
+// returns None. A complete frame has to contain exactly one correct request.
+pub fn parse_framed_request(message: &str) -> ParseResult<'_> {
+    let header_end = match message.find('$') {
+        Some(header_end) => header_end,
+        None if message.bytes().all(|byte| byte.is_ascii_digit()) => return Ok(None),
+        None => return Err(TaskError::ParseError)
+    };
+
+    if !match_regex!(message, r"^[0-9]+\$") {
+        return Err(TaskError::ParseError);
+    }
+    let frame_len: usize = message[..header_end].parse().map_err(|_| TaskError::ParseError)?;
+    let frame_end = frame_len.checked_add(header_end + 1).ok_or(TaskError::ParseError)?;
+    if message.len() < frame_end {
+        return Ok(None);
+    }
+
+    let frame = message.get(header_end + 1..frame_end).ok_or(TaskError::ParseError)?;
+    match parse_request(frame)? {
+        Some((request, "")) => Ok(Some((request, &message[frame_end..]))),
+        _ => Err(TaskError::ParseError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_store_request_returns_true_when_given_exact_store_request() {
+        let correct_store_requests = vec![
+            "STORE$$$", "STORE$k$$", "STORE$key$$", "STORE$$v$", "STORE$$value$",
+            "STORE$k$v$", "STORE$key$value$", "STORE$qwertyuiopasdfghjklzxcvbnm$value$",
+            "STORE$key$qwertyuiopasdfghjklzxcvbnm$", "STORE$User42$value$", "STORE$a_1$v$"
+        ];
+
+        for request in correct_store_requests {
+            assert!(is_store_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_store_request_returns_true_when_given_prefix_of_store_request() {
+        let correct_prefixes_of_store_requests = vec![
+            "STORE$$$a", "STORE$k$$$$$$$$$$$$$", "STORE$key$$qqqqqqqqqqqqq", "STORE$$v$123123", "STORE$$value$*"
+        ];
+
+        for request in correct_prefixes_of_store_requests {
+            assert!(is_store_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_store_request_returns_false_when_given_incorrect_store_request() {
+        let incorrect_store_requests = vec![
+            "", "S", "STORE", "STORE$$", "STORE$k$", "STORE$k$v", "STORE$$v",
+            "STORE$-$v$", "STORE$k$1$", "STORE$k-$v$", "STORE$k$V$", "STORE$*$*$",
+            "STOR$k$v$", "LOAD$k$", "STORE$a$V$abc", "aSTORE$k$v$"
+        ];
+
+        for request in incorrect_store_requests {
+            assert!(!is_store_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_load_request_returns_true_when_given_exact_load_request() {
+        let correct_load_requests = vec![
+            "LOAD$$", "LOAD$k$", "LOAD$key$", "LOAD$qwertyuiopasdfghjklzxcvbnm$", "LOAD$User42$", "LOAD$A_1$"
+        ];
+
+        for request in correct_load_requests {
+            assert!(is_load_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_load_request_returns_true_when_given_prefix_of_load_request() {
+        let correct_prefixes_of_load_requests = vec![
+            "LOAD$$x", "LOAD$k$1*2*3*", "LOAD$key$$$$$", "LOAD$qwertyuiopasdfghjklzxcvbnm$abcd&4321"
+        ];
+
+        for request in correct_prefixes_of_load_requests {
+            assert!(is_load_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_load_request_returns_false_when_given_incorrect_load_request() {
+        let incorrect_load_requests = vec![
+            "", "L", "LOAD", "LOAD$", "LOAD$k", "LOAD$-$", "LOAD$k.$",
+            "LOAD$*$", "LOA$k$", "STORE$k$v$", "LOAD$k-$a", "aLOAD$k$"
+        ];
+
+        for request in incorrect_load_requests {
+            assert!(!is_load_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_store_request_returns_true_when_should() {
+        let correct_store_prefixes = vec![
+            "", "S", "ST", "STO", "STOR", "STORE", "STORE$",
+            "STORE$key", "STORE$qwertyuiopasdfghjklzxcvbnm",
+            "STORE$key$", "STORE$key$value", "STORE$$",
+            "STORE$key$qwertyuiopasdfghjklzxcvbnm", "STORE$User42", "STORE$User42$v"
+        ];
+
+        for request in correct_store_prefixes {
+            assert!(could_become_store_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_store_request_returns_false_when_should() {
+        let incorrect_store_prefixes = vec![
+            "T", "a", "STOE$", "STOREa", "STORE$-", "STORE$*", "STORE$a$1",
+            "STORE$$$", "STORE$$$a", "STORE$key$value$", "STRE$key$value"
+        ];
+
+        for request in incorrect_store_prefixes {
+            assert!(!could_become_store_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_load_request_returns_true_when_should() {
+        let correct_load_prefixes = vec![
+            "", "L", "LO", "LOA", "LOAD", "LOAD$", "LOAD$a",
+            "LOAD$key", "LOAD$qwertyuiopasdfghjklzxcvbnm", "LOAD$User_42"
+        ];
+
+        for request in correct_load_prefixes {
+            assert!(could_become_load_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_load_request_returns_false_when_should() {
+        let incorrect_load_prefixes = vec![
+            "O", "a", "LOD$", "LOADa", "LOAD$-", "LOAD$*", "LOAD$$",
+            "LOAD$$a", "LAD$key"
+        ];
+
+        for request in incorrect_load_prefixes {
+            assert!(!could_become_load_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn split_store_request_splits_correctly() {
+        let test_cases = vec![
+            ("STORE$$$", ("", "", "")),
+            ("STORE$k$$", ("k", "", "")),
+            ("STORE$$v$", ("", "v", "")),
+            ("STORE$k$v$", ("k", "v", "")),
+            ("STORE$k$v$r", ("k", "v", "r")),
+            ("STORE$$$r", ("", "", "r")),
+            ("STORE$key$value$rest", ("key", "value", "rest")),
+            ("STORE$k$v$STORE$k$v$", ("k", "v", "STORE$k$v$"))
+        ];
+
+        for (input, (k, v, r)) in test_cases {
+            assert_eq!((k.to_string(), v.to_string(), r), split_store_request(input));
+        }
+    }
+
+    #[test]
+    fn split_load_request_splits_correctly() {
+        let test_cases = vec![
+            ("LOAD$$", ("", "")),
+            ("LOAD$k$", ("k", "")),
+            ("LOAD$$r", ("", "r")),
+            ("LOAD$k$r", ("k", "r")),
+            ("LOAD$key$rest", ("key", "rest")),
+            ("LOAD$k$LOAD$k$", ("k", "LOAD$k$"))
+        ];
+
+        for (input, (k, r)) in test_cases {
+            assert_eq!((k.to_string(), r), split_load_request(input));
+        }
+    }
+
+    #[test]
+    fn is_load_transformed_request_returns_true_only_when_should() {
+        let correct_requests = vec!["LOADT$$$", "LOADT$k$len$", "LOADT$k$unknown$LOAD$k$"];
+        let incorrect_requests = vec!["", "LOADT$k$", "LOADT$k$LEN$", "LOADT$k-$len$", "LOAD$k$len$"];
+
+        for request in correct_requests {
+            assert!(is_load_transformed_request(request).unwrap());
+        }
+        for request in incorrect_requests {
+            assert!(!is_load_transformed_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_load_transformed_request_returns_true_only_when_should() {
+        let correct_prefixes = vec!["", "L", "LOAD", "LOADT", "LOADT$k", "LOADT$k$up"];
+        let incorrect_prefixes = vec!["LOADa", "LOAD$", "LOADT$k$len$", "LOADT$k$1"];
+
+        for request in correct_prefixes {
+            assert!(could_become_load_transformed_request(request).unwrap());
+        }
+        for request in incorrect_prefixes {
+            assert!(!could_become_load_transformed_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_wait_request_returns_true_when_given_wait_request() {
+        let correct_wait_requests = vec![
+            "WAIT$$0$", "WAIT$k$1$", "WAIT$key$1000$", "WAIT$key$100$rest", "WAIT$k$5$WAIT$k$5$"
+        ];
+
+        for request in correct_wait_requests {
+            assert!(is_wait_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_wait_request_returns_false_when_given_incorrect_wait_request() {
+        let incorrect_wait_requests = vec![
+            "", "W", "WAIT", "WAIT$", "WAIT$k$", "WAIT$k$$", "WAIT$k$10", "WAIT$k-$10$",
+            "WAIT$k$a$", "WAIT$k$-1$", "WAT$k$1$", "aWAIT$k$1$", "LOAD$k$"
+        ];
+
+        for request in incorrect_wait_requests {
+            assert!(!is_wait_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_wait_request_returns_true_when_should() {
+        let correct_wait_prefixes = vec![
+            "", "W", "WA", "WAI", "WAIT", "WAIT$", "WAIT$key", "WAIT$key$", "WAIT$key$12", "WAIT$$"
+        ];
+
+        for request in correct_wait_prefixes {
+            assert!(could_become_wait_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_wait_request_returns_false_when_should() {
+        let incorrect_wait_prefixes = vec![
+            "A", "WAT$", "WAITa", "WAIT$-", "WAIT$k$a", "WAIT$k$1$", "WAIT$k$$", "WAIT$k-"
+        ];
+
+        for request in incorrect_wait_prefixes {
+            assert!(!could_become_wait_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_store_binary_request_returns_true_only_when_whole_value_came() {
+        let correct_store_binary_requests = vec!["STOREB$k$0$", "STOREB$k$3$a$b", "STOREB$K_1$2$12LOAD$k$"];
+        let incorrect_store_binary_requests = vec!["", "STOREB$k$", "STOREB$k$3$ab", "STOREB$k$a$", "STORE$k$v$"];
+
+        for request in correct_store_binary_requests {
+            assert!(is_store_binary_request(request).unwrap());
+        }
+        for request in incorrect_store_binary_requests {
+            assert!(!is_store_binary_request(request).unwrap());
+        }
+        assert!(is_store_binary_request(&format!("STOREB$k${}0$", usize::MAX)).is_err());
+    }
+
+    #[test]
+    fn could_become_store_binary_request_returns_true_only_when_should() {
+        let correct_store_binary_prefixes = vec![
+            "", "S", "STOREB", "STOREB$k$", "STOREB$k$12", "STOREB$k$3$", "STOREB$k$3$$a"
+        ];
+        let incorrect_store_binary_prefixes = vec!["T", "STOREBa", "STOREB$k$a", "STOREB$k$2$ab", "STOREB$k$1$abc"];
+
+        for request in correct_store_binary_prefixes {
+            assert!(could_become_store_binary_request(request).unwrap());
+        }
+        for request in incorrect_store_binary_prefixes {
+            assert!(!could_become_store_binary_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn try_parse_request_parses_store_binary_request_with_any_value() {
+        let mut message = "STOREB$key$9${\"a\": $1}LOAD$key$".to_string();
+
+        match try_parse_request(&mut message) {
+            Ok(Some(Request::Store(request))) => {
+                assert_eq!(("key", "{\"a\": $1}"), (request.key.as_str(), request.value.as_str()));
+            },
+            _ => panic!("STOREB request was not parsed")
+        }
+        assert_eq!("LOAD$key$", message);
+
+        let mut message = "STOREB$key$3$\u{e9}$".to_string();
+        assert!(matches!(try_parse_request(&mut message), Ok(Some(Request::Store(request))) if request.value == "\u{e9}$"));
+
+        // Length ends in the middle of a character.
+        let mut message = "STOREB$key$1$\u{e9}".to_string();
+        assert!(try_parse_request(&mut message).is_err());
+    }
+
+    #[test]
+    fn is_store_expiring_request_returns_true_only_when_should() {
+        let correct_store_expiring_requests = vec!["STOREX$$$0$", "STOREX$K_1$v$10$", "STOREX$k$v$1$LOAD$k$"];
+        let incorrect_store_expiring_requests = vec![
+            "", "STOREX$k$v$", "STOREX$k$v$$", "STOREX$k$V$1$", "STOREX$k$v$-1$", "STOREX$k$v$1", "STORE$k$v$"
+        ];
+
+        for request in correct_store_expiring_requests {
+            assert!(is_store_expiring_request(request).unwrap());
+        }
+        for request in incorrect_store_expiring_requests {
+            assert!(!is_store_expiring_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_store_expiring_request_returns_true_only_when_should() {
+        let correct_store_expiring_prefixes = vec!["", "STORE", "STOREX$", "STOREX$k$v", "STOREX$k$v$", "STOREX$k$v$12"];
+        let incorrect_store_expiring_prefixes = vec!["T", "STOREXa", "STOREX$k$V", "STOREX$k$v$a", "STOREX$k$v$1$"];
+
+        for request in correct_store_expiring_prefixes {
+            assert!(could_become_store_expiring_request(request).unwrap());
+        }
+        for request in incorrect_store_expiring_prefixes {
+            assert!(!could_become_store_expiring_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn split_store_expiring_request_splits_correctly() {
+        let test_cases = vec![
+            ("STOREX$$$0$", ("", "", "0", "")),
+            ("STOREX$key$value$60$rest", ("key", "value", "60", "rest")),
+            ("STOREX$k$v$1$STOREX$k$v$1$", ("k", "v", "1", "STOREX$k$v$1$"))
+        ];
+
+        for (input, (k, v, t, r)) in test_cases {
+            let expected = (k.to_string(), v.to_string(), t.to_string(), r);
+            assert_eq!(expected, split_store_expiring_request(input));
+        }
+    }
+
+    #[test]
+    fn try_parse_request_rejects_store_expiring_request_with_too_long_ttl() {
+        let mut message = "STOREX$key$value$99999999999$".to_string();
+        assert!(try_parse_request(&mut message).is_err());
+    }
+
+    #[test]
+    fn split_wait_request_splits_correctly() {
+        let test_cases = vec![
+            ("WAIT$$0$", ("", "0", "")),
+            ("WAIT$k$10$", ("k", "10", "")),
+            ("WAIT$key$100$rest", ("key", "100", "rest")),
+            ("WAIT$k$1$WAIT$k$1$", ("k", "1", "WAIT$k$1$"))
+        ];
+
+        for (input, (k, t, r)) in test_cases {
+            assert_eq!((k.to_string(), t.to_string(), r), split_wait_request(input));
+        }
+    }
+
+    #[test]
+    fn is_delete_request_returns_true_only_when_should() {
+        let correct_delete_requests = vec!["DELETE$$", "DELETE$k$", "DELETE$key$LOAD$k$"];
+        let incorrect_delete_requests = vec![
+            "", "DELETE$", "DELETE$k", "DELETE$k-$", "DELETE$-$", "DEL$k$", "aDELETE$k$"
+        ];
+
+        for request in correct_delete_requests {
+            assert!(is_delete_request(request).unwrap());
+        }
+        for request in incorrect_delete_requests {
+            assert!(!is_delete_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_delete_request_returns_true_only_when_should() {
+        let correct_delete_prefixes = vec!["", "D", "DEL", "DELETE", "DELETE$", "DELETE$key"];
+        let incorrect_delete_prefixes = vec!["E", "DELETEa", "DELETE$k$", "DELETE$k-"];
+
+        for request in correct_delete_prefixes {
+            assert!(could_become_delete_request(request).unwrap());
+        }
+        for request in incorrect_delete_prefixes {
+            assert!(!could_become_delete_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn try_parse_request_parses_delete_request() {
+        let mut message = "DELETE$key$LOAD$key$".to_string();
+
+        match try_parse_request(&mut message) {
+            Ok(Some(Request::Delete(request))) => assert_eq!("key", request.key),
+            _ => panic!("DELETE request was not parsed")
+        }
+        assert_eq!("LOAD$key$", message);
+    }
+
+    #[test]
+    fn is_exists_request_returns_true_when_given_exact_exists_request() {
+        let correct_exists_requests = vec!["EXISTS$$", "EXISTS$k$", "EXISTS$key$", "EXISTS$qwertyuiopasdfghjklzxcvbnm$"];
+
+        for request in correct_exists_requests {
+            assert!(is_exists_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_exists_request_returns_true_when_given_prefix_of_exists_request() {
+        let correct_prefixes_of_exists_requests = vec!["EXISTS$$x", "EXISTS$k$1*2*3*", "EXISTS$key$$$$$"];
+
+        for request in correct_prefixes_of_exists_requests {
+            assert!(is_exists_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_exists_request_returns_false_when_given_incorrect_exists_request() {
+        let incorrect_exists_requests = vec![
+            "", "E", "EXISTS", "EXISTS$", "EXISTS$k", "EXISTS$-$", "EXISTS$k-$",
+            "EXISTS$*$", "EXIST$k$", "MEXISTS$k$$", "aEXISTS$k$"
+        ];
+
+        for request in incorrect_exists_requests {
+            assert!(!is_exists_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_exists_request_returns_true_when_should() {
+        let correct_exists_prefixes = vec!["", "E", "EX", "EXISTS", "EXISTS$", "EXISTS$a", "EXISTS$key"];
+
+        for request in correct_exists_prefixes {
+            assert!(could_become_exists_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_exists_request_returns_false_when_should() {
+        let incorrect_exists_prefixes = vec!["X", "a", "EXIT$", "EXISTSa", "EXISTS$-", "EXISTS$$", "EXISTS$$a"];
+
+        for request in incorrect_exists_prefixes {
+            assert!(!could_become_exists_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn try_parse_request_accepts_keys_with_uppercase_letters_digits_and_underscores() {
+        let mut message = "STORE$User_42$value$LOAD$User_42$".to_string();
+
+        match try_parse_request(&mut message) {
+            Ok(Some(Request::Store(request))) => {
+                assert_eq!(("User_42", "value"), (request.key.as_str(), request.value.as_str()))
+            },
+            _ => panic!("STORE request was not parsed")
+        }
+        match try_parse_request(&mut message) {
+            Ok(Some(Request::Load(request))) => assert_eq!("User_42", request.key),
+            _ => panic!("LOAD request was not parsed")
+        }
+        assert!(message.is_empty());
+    }
+
+    #[test]
+    fn try_parse_request_rejects_wait_request_with_too_long_timeout() {
+        let mut message = "WAIT$key$99999999999$".to_string();
+        assert!(try_parse_request(&mut message).is_err());
+    }
+
+    #[test]
+    fn is_no_flush_request_returns_true_only_when_should() {
+        let correct_no_flush_requests = vec!["NOFLUSH$", "NOFLUSH$LOAD$k$"];
+        let incorrect_no_flush_requests = vec!["", "NOFLUSH", "NOFLUS$", "aNOFLUSH$", "FLUSH$"];
+
+        for request in correct_no_flush_requests {
+            assert!(is_no_flush_request(request).unwrap());
+        }
+        for request in incorrect_no_flush_requests {
+            assert!(!is_no_flush_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_flush_request_returns_true_only_when_should() {
+        let correct_flush_requests = vec!["FLUSH$", "FLUSH$LOAD$k$"];
+        let incorrect_flush_requests = vec!["", "FLUSH", "FLUS$", "aFLUSH$", "NOFLUSH$"];
+
+        for request in correct_flush_requests {
+            assert!(is_flush_request(request).unwrap());
+        }
+        for request in incorrect_flush_requests {
+            assert!(!is_flush_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_no_flush_request_returns_true_only_when_should() {
+        let correct_no_flush_prefixes = vec!["", "N", "NOF", "NOFLUSH"];
+        let incorrect_no_flush_prefixes = vec!["F", "FLU", "NOFLUSHa", "NOFLUSH$"];
+
+        for request in correct_no_flush_prefixes {
+            assert!(could_become_no_flush_request(request).unwrap());
+        }
+        for request in incorrect_no_flush_prefixes {
+            assert!(!could_become_no_flush_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_flush_request_returns_true_only_when_should() {
+        let correct_flush_prefixes = vec!["", "F", "FLU", "FLUSH"];
+        let incorrect_flush_prefixes = vec!["N", "NOFLUSH", "FLUSHa", "FLUSH$"];
+
+        for request in correct_flush_prefixes {
+            assert!(could_become_flush_request(request).unwrap());
+        }
+        for request in incorrect_flush_prefixes {
+            assert!(!could_become_flush_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_flush_all_request_returns_true_only_when_should() {
+        let correct_flush_all_requests = vec!["FLUSHALL$", "FLUSHALL$LOAD$k$"];
+        let incorrect_flush_all_requests = vec!["", "FLUSHALL", "FLUSH$", "aFLUSHALL$", "flushall$"];
+
+        for request in correct_flush_all_requests {
+            assert!(is_flush_all_request(request).unwrap());
+        }
+        for request in incorrect_flush_all_requests {
+            assert!(!is_flush_all_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_flush_all_request_returns_true_only_when_should() {
+        let correct_flush_all_prefixes = vec!["", "F", "FLUSH", "FLUSHALL"];
+        let incorrect_flush_all_prefixes = vec!["A", "FLUSH$", "FLUSHALLa", "FLUSHALL$"];
+
+        for request in correct_flush_all_prefixes {
+            assert!(could_become_flush_all_request(request).unwrap());
+        }
+        for request in incorrect_flush_all_prefixes {
+            assert!(!could_become_flush_all_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_init_request_returns_true_only_when_should() {
+        let correct_init_requests = vec!["INIT$$$", "INIT$k$v$", "INIT$key$value$rest"];
+        let incorrect_init_requests = vec![
+            "", "INIT", "INIT$k$", "INIT$k$v", "INIT$k-$v$", "INIT$k$1$", "INI$k$v$", "STORE$k$v$"
+        ];
+
+        for request in correct_init_requests {
+            assert!(is_init_request(request).unwrap());
+        }
+        for request in incorrect_init_requests {
+            assert!(!is_init_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_init_request_returns_true_only_when_should() {
+        let correct_init_prefixes = vec!["", "I", "INIT", "INIT$", "INIT$key", "INIT$key$", "INIT$key$val"];
+        let incorrect_init_prefixes = vec!["N", "INITa", "INIT$-", "INIT$k$V", "INIT$k$v$"];
+
+        for request in correct_init_prefixes {
+            assert!(could_become_init_request(request).unwrap());
+        }
+        for request in incorrect_init_prefixes {
+            assert!(!could_become_init_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_copy_request_returns_true_only_when_should() {
+        let correct_copy_requests = vec!["COPY$$$", "COPY$src$dst$", "COPY$src$dst$rest"];
+        let incorrect_copy_requests = vec![
+            "", "COPY", "COPY$src$", "COPY$src$dst", "COPY$src-$dst$", "COPY$src$-$", "COP$src$dst$"
+        ];
+
+        for request in correct_copy_requests {
+            assert!(is_copy_request(request).unwrap());
+        }
+        for request in incorrect_copy_requests {
+            assert!(!is_copy_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_copy_request_returns_true_only_when_should() {
+        let correct_copy_prefixes = vec!["", "C", "COPY", "COPY$", "COPY$src", "COPY$src$", "COPY$src$ds"];
+        let incorrect_copy_prefixes = vec!["O", "COPYa", "COPY$-", "COPY$src$d-", "COPY$src$dst$"];
+
+        for request in correct_copy_prefixes {
+            assert!(could_become_copy_request(request).unwrap());
+        }
+        for request in incorrect_copy_prefixes {
+            assert!(!could_become_copy_request(request).unwrap());
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn is_json_request_returns_true_only_when_should() {
+        let correct_json_requests = vec!["JSON$", "JSON$LOAD$k$"];
+        let incorrect_json_requests = vec!["", "JSON", "JSO$", "aJSON$", "json$"];
+
+        for request in correct_json_requests {
+            assert!(is_json_request(request).unwrap());
+        }
+        for request in incorrect_json_requests {
+            assert!(!is_json_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_json_request_returns_true_only_when_should() {
+        let correct_json_prefixes = vec!["J", "JS", "JSON"];
+        let incorrect_json_prefixes = vec!["S", "JSONa", "JSON$"];
+
+        for request in correct_json_prefixes {
+            assert_eq!(cfg!(feature = "json"), could_become_json_request(request).unwrap());
+        }
+        for request in incorrect_json_prefixes {
+            assert!(!could_become_json_request(request).unwrap());
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn is_metrics_request_returns_true_only_when_should() {
+        let correct_metrics_requests = vec!["METRICS$", "METRICS$LOAD$k$"];
+        let incorrect_metrics_requests = vec!["", "METRICS", "METRIC$", "metrics$"];
+
+        for request in correct_metrics_requests {
+            assert!(is_metrics_request(request).unwrap());
+        }
+        for request in incorrect_metrics_requests {
+            assert!(!is_metrics_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_metrics_request_returns_true_only_when_should() {
+        let correct_metrics_prefixes = vec!["ME", "METR", "METRICS"];
+        let incorrect_metrics_prefixes = vec!["MD", "METRICSa", "METRICS$"];
+
+        for request in correct_metrics_prefixes {
+            assert_eq!(cfg!(feature = "json"), could_become_metrics_request(request).unwrap());
+        }
+        for request in incorrect_metrics_prefixes {
+            assert!(!could_become_metrics_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_get_or_set_request_returns_true_only_when_should() {
+        let correct_get_or_set_requests = vec!["GETORSET$$$", "GETORSET$k$v$", "GETORSET$k$v$LOAD$k$"];
+        let incorrect_get_or_set_requests = vec!["", "GETORSET$k$", "GETORSET$k-$v$", "GETORSET$k$1$", "GETSET$k$v$"];
+
+        for request in correct_get_or_set_requests {
+            assert!(is_get_or_set_request(request).unwrap());
+        }
+        for request in incorrect_get_or_set_requests {
+            assert!(!is_get_or_set_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_get_or_set_request_returns_true_only_when_should() {
+        let correct_get_or_set_prefixes = vec!["", "G", "GETOR", "GETORSET$", "GETORSET$k$", "GETORSET$k$v"];
+        let incorrect_get_or_set_prefixes = vec!["S", "GETORSETa", "GETORSET$k$v$", "GETORSET$k-"];
+
+        for request in correct_get_or_set_prefixes {
+            assert!(could_become_get_or_set_request(request).unwrap());
+        }
+        for request in incorrect_get_or_set_prefixes {
+            assert!(!could_become_get_or_set_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_append_if_new_request_returns_true_only_when_should() {
+        let correct_append_if_new_requests = vec!["APPENDIFNEW$$$", "APPENDIFNEW$k$v$", "APPENDIFNEW$k$v$LOAD$k$"];
+        let incorrect_append_if_new_requests = vec![
+            "", "APPENDIFNEW$k$", "APPENDIFNEW$k-$v$", "APPENDIFNEW$k$1$", "APPEND$k$v$"
+        ];
+
+        for request in correct_append_if_new_requests {
+            assert!(is_append_if_new_request(request).unwrap());
+        }
+        for request in incorrect_append_if_new_requests {
+            assert!(!is_append_if_new_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_append_if_new_request_returns_true_only_when_should() {
+        let correct_append_if_new_prefixes = vec!["", "A", "APPEND", "APPENDIFNEW$", "APPENDIFNEW$k$", "APPENDIFNEW$k$v"];
+        let incorrect_append_if_new_prefixes = vec!["P", "APPENDIFNEWa", "APPENDIFNEW$k$v$", "APPENDIFNEW$k$1"];
+
+        for request in correct_append_if_new_prefixes {
+            assert!(could_become_append_if_new_request(request).unwrap());
+        }
+        for request in incorrect_append_if_new_prefixes {
+            assert!(!could_become_append_if_new_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_push_version_request_returns_true_only_when_should() {
+        let correct_push_version_requests = vec!["PUSHVER$$$", "PUSHVER$k$v$", "PUSHVER$k$v$LOAD$k$"];
+        let incorrect_push_version_requests = vec!["", "PUSHVER$k$", "PUSHVER$k-$v$", "PUSHVER$k$1$", "PUSH$k$v$"];
+
+        for request in correct_push_version_requests {
+            assert!(is_push_version_request(request).unwrap());
+        }
+        for request in incorrect_push_version_requests {
+            assert!(!is_push_version_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_push_version_request_returns_true_only_when_should() {
+        let correct_push_version_prefixes = vec!["", "P", "PUSH", "PUSHVER$", "PUSHVER$k$", "PUSHVER$k$v"];
+        let incorrect_push_version_prefixes = vec!["U", "PUSHVERa", "PUSHVER$k$v$", "PUSHVER$k$1"];
+
+        for request in correct_push_version_prefixes {
+            assert!(could_become_push_version_request(request).unwrap());
+        }
+        for request in incorrect_push_version_prefixes {
+            assert!(!could_become_push_version_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_get_version_request_returns_true_only_when_should() {
+        let correct_get_version_requests = vec!["GETVER$$0$", "GETVER$k$12$", "GETVER$k$1$LOAD$k$"];
+        let incorrect_get_version_requests = vec!["", "GETVER$k$$", "GETVER$k$a$", "GETVER$k-$1$", "GETVER$k$1"];
+
+        for request in correct_get_version_requests {
+            assert!(is_get_version_request(request).unwrap());
+        }
+        for request in incorrect_get_version_requests {
+            assert!(!is_get_version_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_get_version_request_returns_true_only_when_should() {
+        let correct_get_version_prefixes = vec!["", "G", "GETV", "GETVER$", "GETVER$k$", "GETVER$k$12"];
+        let incorrect_get_version_prefixes = vec!["V", "GETVERa", "GETVER$k$1$", "GETVER$k$a"];
+
+        for request in correct_get_version_prefixes {
+            assert!(could_become_get_version_request(request).unwrap());
+        }
+        for request in incorrect_get_version_prefixes {
+            assert!(!could_become_get_version_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn try_parse_request_rejects_get_version_request_with_too_large_index() {
+        let mut message = format!("GETVER$k${}0$", usize::MAX);
+        assert!(try_parse_request(&mut message).is_err());
+    }
+
+    #[test]
+    fn is_max_set_and_min_set_request_return_true_only_when_should() {
+        let correct_requests = vec!["$$0$", "$k$12$", "$k$-3$", "$k$1$LOAD$k$"];
+        let incorrect_requests = vec!["", "$k$$", "$k$a$", "$k$-$", "$k$1-$", "$k-$1$", "$k$1"];
+
+        for (name, is_request) in [("MAXSET", is_max_set_request as CouldBecomeFn), ("MINSET", is_min_set_request)] {
+            for request in &correct_requests {
+                assert!(is_request(&format!("{name}{request}")).unwrap());
+            }
+            for request in &incorrect_requests {
+                assert!(!is_request(&format!("{name}{request}")).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn could_become_max_set_and_min_set_request_return_true_only_when_should() {
+        let correct_prefixes = vec!["", "$", "$k$", "$k$12", "$k$-", "$k$-5"];
+        let incorrect_prefixes = vec!["a", "$k$1$", "$k$a", "$k$1-", "$k$--"];
+
+        for (name, could_become) in [
+            ("MAXSET", could_become_max_set_request as CouldBecomeFn), ("MINSET", could_become_min_set_request)
+        ] {
+            for request in &correct_prefixes {
+                assert!(could_become(&format!("{name}{request}")).unwrap());
+            }
+            for request in &incorrect_prefixes {
+                assert!(!could_become(&format!("{name}{request}")).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn try_parse_request_distinguishes_max_set_and_min_set_requests() {
+        let mut message = "MAXSET$k$1$MINSET$k$2$".to_string();
+        assert!(matches!(try_parse_request(&mut message), Ok(Some(Request::MaxSet(_)))));
+        assert!(matches!(try_parse_request(&mut message), Ok(Some(Request::MinSet(_)))));
+        assert!(message.is_empty());
+
+        let mut message = format!("MAXSET$k${}0$", i64::MIN);
+        assert!(try_parse_request(&mut message).is_err());
+    }
+
+    #[test]
+    fn is_increment_and_decrement_request_return_true_only_when_should() {
+        let correct_requests = vec!["$$", "$k$", "$K_1$LOAD$k$"];
+        let incorrect_requests = vec!["", "$", "$k", "$k-$"];
+
+        for (name, is_request) in [("INCR", is_increment_request as CouldBecomeFn), ("DECR", is_decrement_request)] {
+            for request in &correct_requests {
+                assert!(is_request(&format!("{name}{request}")).unwrap());
+            }
+            for request in &incorrect_requests {
+                assert!(!is_request(&format!("{name}{request}")).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn could_become_increment_and_decrement_request_return_true_only_when_should() {
+        let correct_prefixes = vec!["", "$", "$k"];
+        let incorrect_prefixes = vec!["a", "$k$", "$k-"];
+
+        for (name, could_become) in [
+            ("INCR", could_become_increment_request as CouldBecomeFn), ("DECR", could_become_decrement_request)
+        ] {
+            for request in &correct_prefixes {
+                assert!(could_become(&format!("{name}{request}")).unwrap());
+            }
+            for request in &incorrect_prefixes {
+                assert!(!could_become(&format!("{name}{request}")).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn try_parse_request_distinguishes_increment_and_decrement_requests() {
+        let mut message = "INCR$k$DECR$k$".to_string();
+        assert!(matches!(try_parse_request(&mut message), Ok(Some(Request::Increment(request))) if request.delta == 1));
+        assert!(matches!(try_parse_request(&mut message), Ok(Some(Request::Increment(request))) if request.delta == -1));
+        assert!(message.is_empty());
+    }
+
+    #[test]
+    fn is_decrement_delete_request_returns_true_only_when_should() {
+        let correct_requests = vec!["DECRDEL$k$", "DECRDEL$K_1$LOAD$k$", "DECRDEL$$"];
+        let incorrect_requests = vec!["", "DECRDEL$", "DECRDEL$k", "DECRDEL$k-$", "DECR$k$"];
+
+        for request in correct_requests {
+            assert!(is_decrement_delete_request(request).unwrap());
+        }
+        for request in incorrect_requests {
+            assert!(!is_decrement_delete_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_decrement_delete_request_returns_true_only_when_should() {
+        let correct_prefixes = vec!["", "DECR", "DECRDEL$", "DECRDEL$k"];
+        let incorrect_prefixes = vec!["DECR$", "DECRDEL$k$", "DECRDEL$k-", "DECRDELa"];
+
+        for request in correct_prefixes {
+            assert!(could_become_decrement_delete_request(request).unwrap());
+        }
+        for request in incorrect_prefixes {
+            assert!(!could_become_decrement_delete_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_decrement_floor_request_returns_true_only_when_should() {
+        let correct_requests = vec!["DECRFLOOR$k$0$", "DECRFLOOR$K_1$-5$", "DECRFLOOR$$3$LOAD$k$"];
+        let incorrect_requests = vec!["", "DECRFLOOR$k$", "DECRFLOOR$k$$", "DECRFLOOR$k$-$", "DECRFLOOR$k$1", "DECR$k$1$"];
+
+        for request in correct_requests {
+            assert!(is_decrement_floor_request(request).unwrap());
+        }
+        for request in incorrect_requests {
+            assert!(!is_decrement_floor_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_decrement_floor_request_returns_true_only_when_should() {
+        let correct_prefixes = vec!["", "DECR", "DECRFLOOR$", "DECRFLOOR$k$", "DECRFLOOR$k$-1"];
+        let incorrect_prefixes = vec!["DECR$", "DECRFLOOR$k$1$", "DECRFLOOR$k$a", "DECRFLOOR$k-"];
+
+        for request in correct_prefixes {
+            assert!(could_become_decrement_floor_request(request).unwrap());
+        }
+        for request in incorrect_prefixes {
+            assert!(!could_become_decrement_floor_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_prefix_request_returns_true_only_when_should() {
+        let correct_prefix_requests = vec!["PREFIX$$", "PREFIX$p$", "PREFIX$prefix$rest"];
+        let incorrect_prefix_requests = vec!["", "PREFIX", "PREFIX$", "PREFIX$p", "PREFIX$p-$", "PREFIX$-$", "PREFI$p$"];
+
+        for request in correct_prefix_requests {
+            assert!(is_prefix_request(request).unwrap());
+        }
+        for request in incorrect_prefix_requests {
+            assert!(!is_prefix_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_prefix_request_returns_true_only_when_should() {
+        let correct_prefix_prefixes = vec!["", "P", "PREFIX", "PREFIX$", "PREFIX$pre"];
+        let incorrect_prefix_prefixes = vec!["R", "PREFIXa", "PREFIX$-", "PREFIX$p$", "PREFIX$$"];
+
+        for request in correct_prefix_prefixes {
+            assert!(could_become_prefix_request(request).unwrap());
+        }
+        for request in incorrect_prefix_prefixes {
+            assert!(!could_become_prefix_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_select_request_returns_true_only_when_should() {
+        let correct_select_requests = vec!["SELECT$ns$", "SELECT$default$LOAD$k$"];
+        let incorrect_select_requests = vec!["", "SELECT$$", "SELECT$ns", "SELECT$Ns$", "SELECT$n1$", "SELECT$a/b$"];
+
+        for request in correct_select_requests {
+            assert!(is_select_request(request).unwrap());
+        }
+        for request in incorrect_select_requests {
+            assert!(!is_select_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_select_request_returns_true_only_when_should() {
+        let correct_select_prefixes = vec!["", "S", "SEL", "SELECT$", "SELECT$app"];
+        let incorrect_select_prefixes = vec!["E", "SELECTa", "SELECT$$", "SELECT$ns$", "SELECT$_"];
+
+        for request in correct_select_prefixes {
+            assert!(could_become_select_request(request).unwrap());
+        }
+        for request in incorrect_select_prefixes {
+            assert!(!could_become_select_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_pattern_subscribe_request_returns_true_only_when_should() {
+        let correct_pattern_subscribe_requests = vec!["PSUBSCRIBE$$", "PSUBSCRIBE$p$", "PSUBSCRIBE$p$LOAD$k$"];
+        let incorrect_pattern_subscribe_requests = vec!["", "PSUBSCRIBE$", "PSUBSCRIBE$p", "PSUBSCRIBE$p-$", "SUBSCRIBE$p$"];
+
+        for request in correct_pattern_subscribe_requests {
+            assert!(is_pattern_subscribe_request(request).unwrap());
+        }
+        for request in incorrect_pattern_subscribe_requests {
+            assert!(!is_pattern_subscribe_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_pattern_subscribe_request_returns_true_only_when_should() {
+        let correct_pattern_subscribe_prefixes = vec!["", "P", "PSUB", "PSUBSCRIBE$", "PSUBSCRIBE$pre"];
+        let incorrect_pattern_subscribe_prefixes = vec!["S", "PSUBSCRIBEa", "PSUBSCRIBE$p$", "PSUBSCRIBE$p-"];
+
+        for request in correct_pattern_subscribe_prefixes {
+            assert!(could_become_pattern_subscribe_request(request).unwrap());
+        }
+        for request in incorrect_pattern_subscribe_prefixes {
+            assert!(!could_become_pattern_subscribe_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_multi_delete_request_returns_true_only_when_should() {
+        let correct_multi_delete_requests = vec!["MDEL$$", "MDEL$k$$", "MDEL$a$bc$d$$", "MDEL$k$$LOAD$k$"];
+        let incorrect_multi_delete_requests = vec![
+            "", "MDEL$", "MDEL$k$", "MDEL$k-$$", "MDEL$-$$", "aMDEL$$"
+        ];
+
+        for request in correct_multi_delete_requests {
+            assert!(is_multi_delete_request(request).unwrap());
+        }
+        for request in incorrect_multi_delete_requests {
+            assert!(!is_multi_delete_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_multi_delete_request_returns_true_only_when_should() {
+        let correct_multi_delete_prefixes = vec!["", "M", "MDEL", "MDEL$", "MDEL$key", "MDEL$a$bc$", "MDEL$a$bc"];
+        let incorrect_multi_delete_prefixes = vec!["D", "MDELa", "MDEL$$", "MDEL$a$$", "MDEL$a-", "MDEL$a$-"];
+
+        for request in correct_multi_delete_prefixes {
+            assert!(could_become_multi_delete_request(request).unwrap());
+        }
+        for request in incorrect_multi_delete_prefixes {
+            assert!(!could_become_multi_delete_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_multi_exists_request_returns_true_only_when_should() {
+        let correct_multi_exists_requests = vec!["MEXISTS$$", "MEXISTS$k$$", "MEXISTS$a$bc$$LOAD$k$"];
+        let incorrect_multi_exists_requests = vec!["", "MEXISTS$", "MEXISTS$k$", "MEXISTS$k-$$", "MDEL$k$$"];
+
+        for request in correct_multi_exists_requests {
+            assert!(is_multi_exists_request(request).unwrap());
+        }
+        for request in incorrect_multi_exists_requests {
+            assert!(!is_multi_exists_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_multi_exists_request_returns_true_only_when_should() {
+        let correct_multi_exists_prefixes = vec!["", "M", "MEX", "MEXISTS$", "MEXISTS$a$bc"];
+        let incorrect_multi_exists_prefixes = vec!["MD", "MEXISTSa", "MEXISTS$$", "MEXISTS$a-"];
+
+        for request in correct_multi_exists_prefixes {
+            assert!(could_become_multi_exists_request(request).unwrap());
+        }
+        for request in incorrect_multi_exists_prefixes {
+            assert!(!could_become_multi_exists_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_multi_get_request_returns_true_only_when_should() {
+        let correct_multi_get_requests = vec!["MGET$$", "MGET$k$$", "MGET$a$bc$$LOAD$k$"];
+        let incorrect_multi_get_requests = vec!["", "MGET$", "MGET$k$", "MGET$k-$$", "MDEL$k$$", "GET$k$$"];
+
+        for request in correct_multi_get_requests {
+            assert!(is_multi_get_request(request).unwrap());
+        }
+        for request in incorrect_multi_get_requests {
+            assert!(!is_multi_get_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_multi_get_request_returns_true_only_when_should() {
+        let correct_multi_get_prefixes = vec!["", "M", "MG", "MGET$", "MGET$a$bc"];
+        let incorrect_multi_get_prefixes = vec!["MD", "MGETa", "MGET$$", "MGET$a-"];
+
+        for request in correct_multi_get_prefixes {
+            assert!(could_become_multi_get_request(request).unwrap());
+        }
+        for request in incorrect_multi_get_prefixes {
+            assert!(!could_become_multi_get_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_compare_and_swap_request_returns_true_only_when_should() {
+        let correct_cas_requests = vec!["CAS$$$$", "CAS$k$old$new$", "CAS$Key_1$$v$rest"];
+        let incorrect_cas_requests = vec![
+            "", "CAS", "CAS$k$old$", "CAS$k$old$new", "CAS$k-$a$b$", "CAS$k$A$b$", "CAS$k$a$1$", "MCAS$k$a$b$"
+        ];
+
+        for request in correct_cas_requests {
+            assert!(is_compare_and_swap_request(request).unwrap());
+        }
+        for request in incorrect_cas_requests {
+            assert!(!is_compare_and_swap_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_compare_and_swap_request_returns_true_only_when_should() {
+        let correct_cas_prefixes = vec!["", "C", "CAS", "CAS$k", "CAS$k$", "CAS$k$old", "CAS$k$old$new"];
+        let incorrect_cas_prefixes = vec!["A", "CASa", "CAS$-", "CAS$k$O", "CAS$k$old$1", "CAS$k$old$new$"];
+
+        for request in correct_cas_prefixes {
+            assert!(could_become_compare_and_swap_request(request).unwrap());
+        }
+        for request in incorrect_cas_prefixes {
+            assert!(!could_become_compare_and_swap_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn split_compare_and_swap_request_splits_correctly() {
+        let test_cases = vec![
+            ("CAS$$$$", ("", "", "", "")),
+            ("CAS$key$old$new$rest", ("key", "old", "new", "rest")),
+            ("CAS$k$$v$CAS$k$v$$", ("k", "", "v", "CAS$k$v$$"))
+        ];
+
+        for (input, (k, e, v, r)) in test_cases {
+            let expected = (k.to_string(), e.to_string(), v.to_string(), r);
+            assert_eq!(expected, split_compare_and_swap_request(input));
+        }
+    }
+
+    #[test]
+    fn is_multi_compare_and_set_request_returns_true_only_when_should() {
+        let correct_requests = vec![
+            "MCAS$THEN$k$v$", "MCAS$a$x$THEN$k$v$", "MCAS$a$x$b$$THEN$$$", "MCAS$a$x$THEN$k$v$LOAD$k$",
+            "MCAS$A_1$x$THEN$K_2$v$", "MCAS$THENX$x$THEN$THEN$v$"
+        ];
+        let incorrect_requests = vec![
+            "", "MCAS$", "MCAS$a$THEN$k$v$", "MCAS$a$x$THEN$k$", "MCAS$a$x$then$k$v$", "MCAS$a-$x$THEN$k$v$",
+            "MCAS$THEN$x$THEN$k$v$"
+        ];
+
+        for request in correct_requests {
+            assert!(is_multi_compare_and_set_request(request).unwrap());
+        }
+        for request in incorrect_requests {
+            assert!(!is_multi_compare_and_set_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_multi_compare_and_set_request_returns_true_only_when_should() {
+        let correct_prefixes = vec![
+            "", "MC", "MCAS$", "MCAS$a", "MCAS$a$", "MCAS$a$x$", "MCAS$a$x$T", "MCAS$a$x$THEN",
+            "MCAS$a$x$THEN$", "MCAS$a$x$THEN$k$v", "MCAS$THE", "MCAS$A", "MCAS$a$x$THENk", "MCAS$a$x$THENk$x$"
+        ];
+        let incorrect_prefixes = vec![
+            "MD", "MCASa", "MCAS$a$T", "MCAS$a-", "MCAS$a$x$THEN$k$v$", "MCAS$THEN$x$THEN"
+        ];
+
+        for request in correct_prefixes {
+            assert!(could_become_multi_compare_and_set_request(request).unwrap());
+        }
+        for request in incorrect_prefixes {
+            assert!(!could_become_multi_compare_and_set_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn split_multi_compare_and_set_request_splits_correctly() {
+        let (conditions, key, value, rest) = split_multi_compare_and_set_request("MCAS$a$x$b$$THEN$k$v$LOAD$k$");
+        assert_eq!(vec![("a".to_string(), "x".to_string()), ("b".to_string(), String::new())], conditions);
+        assert_eq!(("k", "v", "LOAD$k$"), (key.as_str(), value.as_str(), rest));
+
+        let (conditions, key, value, rest) = split_multi_compare_and_set_request("MCAS$THEN$$$");
+        assert!(conditions.is_empty());
+        assert_eq!(("", "", ""), (key.as_str(), value.as_str(), rest));
+
+        let (conditions, key, value, rest) = split_multi_compare_and_set_request("MCAS$THENX$x$THEN$THEN$v$");
+        assert_eq!(vec![("THENX".to_string(), "x".to_string())], conditions);
+        assert_eq!(("THEN", "v", ""), (key.as_str(), value.as_str(), rest));
+    }
+
+    #[test]
+    fn split_key_list_request_splits_correctly() {
+        let test_cases = [
+            ("MDEL$$", vec![], ""),
+            ("MDEL$k$$", vec!["k"], ""),
+            ("MDEL$a$bc$d$$LOAD$k$", vec!["a", "bc", "d"], "LOAD$k$"),
+            ("MEXISTS$a$$$", vec!["a"], "$")
+        ];
+
+        for (request, keys, rest) in test_cases {
+            let keys = keys.into_iter().map(String::from).collect();
+            assert_eq!((keys, rest), split_key_list_request(request));
+        }
+    }
+
+    #[test]
+    fn is_snapshot_request_returns_true_only_when_should() {
+        let correct_snapshot_requests = vec!["SNAPSHOT$s$", "SNAPSHOT$backup$LOAD$k$"];
+        let incorrect_snapshot_requests = vec!["", "SNAPSHOT$$", "SNAPSHOT$s", "SNAPSHOT$S$", "SNAPSHOT$a/b$"];
+
+        for request in correct_snapshot_requests {
+            assert!(is_snapshot_request(request).unwrap());
+        }
+        for request in incorrect_snapshot_requests {
+            assert!(!is_snapshot_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_snapshot_request_returns_true_only_when_should() {
+        let correct_snapshot_prefixes = vec!["", "S", "SNAP", "SNAPSHOT$", "SNAPSHOT$back"];
+        let incorrect_snapshot_prefixes = vec!["N", "SNAPSHOTa", "SNAPSHOT$$", "SNAPSHOT$s$", "SNAPSHOT$."];
+
+        for request in correct_snapshot_prefixes {
+            assert!(could_become_snapshot_request(request).unwrap());
+        }
+        for request in incorrect_snapshot_prefixes {
+            assert!(!could_become_snapshot_request(request).unwrap());
+        }
     }
-}
 
-// Returns true if there exists a prefix of a message parameter
-// that is a correct STORE request.
-fn is_store_request(message: &str) -> Result<bool, TaskError> {
-    match_regex(message, r"^STORE\$[a-z]*\$[a-z]*\$")
-}
+    #[test]
+    fn is_keys_request_returns_true_only_when_should() {
+        let correct_keys_requests = vec!["KEYS$", "KEYS$LOAD$k$"];
+        let incorrect_keys_requests = vec!["", "KEYS", "KEY$", "aKEYS$", "keys$"];
 
-// Returns true if there exists a prefix of a message parameter
-// that is a correct LOAD request.
-fn is_load_request(message: &str) -> Result<bool, TaskError> {
-    match_regex(message, r"^LOAD\$[a-z]*\$")
-}
+        for request in correct_keys_requests {
+            assert!(is_keys_request(request).unwrap());
+        }
+        for request in incorrect_keys_requests {
+            assert!(!is_keys_request(request).unwrap());
+        }
+    }
 
-// Returns true if message could become a correct STORE request.
-fn could_become_store_request(message: &str) -> Result<bool, TaskError> {
-    static STORE: &str = "STORE$";
-    if message.len() <= STORE.len() {
-        return Ok(message == &STORE[..message.len()]);
+    #[test]
+    fn could_become_keys_request_returns_true_only_when_should() {
+        let correct_keys_prefixes = vec!["", "K", "KEY", "KEYS"];
+        let incorrect_keys_prefixes = vec!["E", "KEYSa", "KEYS$"];
+
+        for request in correct_keys_prefixes {
+            assert!(could_become_keys_request(request).unwrap());
+        }
+        for request in incorrect_keys_prefixes {
+            assert!(!could_become_keys_request(request).unwrap());
+        }
     }
 
-    Ok(match_regex(message, r"^STORE\$[a-z]*$")?
-        || match_regex(message, r"^STORE\$[a-z]*\$[a-z]*$")?)
-}
+    #[test]
+    fn is_scan_request_returns_true_only_when_should() {
+        let correct_scan_requests = vec!["SCAN$$", "SCAN$p$", "SCAN$p$KEYS$"];
+        let incorrect_scan_requests = vec!["", "SCAN$", "SCAN$p", "SCAN$p-$", "scan$p$"];
 
-// Returns true if message could become a correct LOAD request.
-fn could_become_load_request(message: &str) -> Result<bool, TaskError> {
-    static LOAD: &str = "LOAD$";
-    if message.len() <= LOAD.len() {
-        return Ok(message == &LOAD[..message.len()]);
+        for request in correct_scan_requests {
+            assert!(is_scan_request(request).unwrap());
+        }
+        for request in incorrect_scan_requests {
+            assert!(!is_scan_request(request).unwrap());
+        }
     }
 
-    match_regex(message,  r"^LOAD\$[a-z]*$")
-}
+    #[test]
+    fn could_become_scan_request_returns_true_only_when_should() {
+        let correct_scan_prefixes = vec!["", "S", "SCA", "SCAN$", "SCAN$pre"];
+        let incorrect_scan_prefixes = vec!["C", "SCANa", "SCAN$p$", "SCAN$p-"];
 
-// Splits a message with a prefix that is a correct STORE request
-// from STORE$key$value$rest to (key, value, rest).
-fn split_store_request(message: &str) -> (String, String, String) {
-    let dollars: Vec<usize> = message.match_indices('$').map(|(pos, _)| pos).collect();
-    let key = message[dollars[0] + 1..dollars[1]].to_string();
-    let value = message[dollars[1] + 1..dollars[2]].to_string();
-    let rest = message[dollars[2] + 1..].to_string();
-    (key, value, rest)
-}
+        for request in correct_scan_prefixes {
+            assert!(could_become_scan_request(request).unwrap());
+        }
+        for request in incorrect_scan_prefixes {
+            assert!(!could_become_scan_request(request).unwrap());
+        }
+    }
 
-// Splits a message with a prefix that is a correct LOAD request
-// from LOAD$key$rest to (key, rest).
-fn split_load_request(message: &str) -> (String, String) {
-    let dollars: Vec<usize> = message.match_indices('$').map(|(pos, _)| pos).collect();
-    let key = message[dollars[0] + 1..dollars[1]].to_string();
-    let rest = message[dollars[1] + 1..].to_string();
-    (key, rest)
-}
+    #[test]
+    fn is_usage_request_returns_true_only_when_should() {
+        let correct_usage_requests = vec!["USAGE$", "USAGE$LOAD$k$"];
+        let incorrect_usage_requests = vec!["", "USAGE", "USAG$", "aUSAGE$", "usage$"];
 
-// If message contains a prefix that is a correct request, returns
-// Some(request). If message is incorrect, returns TaskError.
-// Otherwise, returns None. Removes request from message.
-pub fn try_parse_request(message: &mut String) -> Result<Option<Request>, TaskError> {
-    if is_store_request(message)? {
-        let (key, value, rest) = split_store_request(message);
-        *message = rest;
-        Ok(Some(Request::Store(StoreRequest::new(key, value))))
-    } else if is_load_request(message)? {
-        let (key, rest) = split_load_request(message);
-        *message = rest;
-        Ok(Some(Request::Load(LoadRequest::new(key))))
-    } else if could_become_store_request(message)?
-        || could_become_load_request(message)? {
-        Ok(None)
-    } else {
-        Err(TaskError)
+        for request in correct_usage_requests {
+            assert!(is_usage_request(request).unwrap());
+        }
+        for request in incorrect_usage_requests {
+            assert!(!is_usage_request(request).unwrap());
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn could_become_usage_request_returns_true_only_when_should() {
+        let correct_usage_prefixes = vec!["", "U", "USA", "USAGE"];
+        let incorrect_usage_prefixes = vec!["S", "USAGEa", "USAGE$"];
+
+        for request in correct_usage_prefixes {
+            assert!(could_become_usage_request(request).unwrap());
+        }
+        for request in incorrect_usage_prefixes {
+            assert!(!could_become_usage_request(request).unwrap());
+        }
+    }
 
     #[test]
-    fn is_store_request_returns_true_when_given_exact_store_request() {
-        let correct_store_requests = vec![
-            "STORE$$$", "STORE$k$$", "STORE$key$$", "STORE$$v$", "STORE$$value$",
-            "STORE$k$v$", "STORE$key$value$", "STORE$qwertyuiopasdfghjklzxcvbnm$value$",
-            "STORE$key$qwertyuiopasdfghjklzxcvbnm$"
-        ];
+    fn is_limits_request_returns_true_only_when_should() {
+        let correct_limits_requests = vec!["LIMITS$", "LIMITS$LOAD$k$"];
+        let incorrect_limits_requests = vec!["", "LIMITS", "LIMIT$", "aLIMITS$", "limits$"];
 
-        for request in correct_store_requests {
-            assert!(is_store_request(request).unwrap());
+        for request in correct_limits_requests {
+            assert!(is_limits_request(request).unwrap());
+        }
+        for request in incorrect_limits_requests {
+            assert!(!is_limits_request(request).unwrap());
         }
     }
 
     #[test]
-    fn is_store_request_returns_true_when_given_prefix_of_store_request() {
-        let correct_prefixes_of_store_requests = vec![
-            "STORE$$$a", "STORE$k$$$$$$$$$$$$$", "STORE$key$$qqqqqqqqqqqqq", "STORE$$v$123123", "STORE$$value$*"
-        ];
+    fn could_become_limits_request_returns_true_only_when_should() {
+        let correct_limits_prefixes = vec!["", "L", "LIM", "LIMITS"];
+        let incorrect_limits_prefixes = vec!["I", "LIMITSa", "LIMITS$"];
 
-        for request in correct_prefixes_of_store_requests {
-            assert!(is_store_request(request).unwrap());
+        for request in correct_limits_prefixes {
+            assert!(could_become_limits_request(request).unwrap());
+        }
+        for request in incorrect_limits_prefixes {
+            assert!(!could_become_limits_request(request).unwrap());
         }
     }
 
     #[test]
-    fn is_store_request_returns_false_when_given_incorrect_store_request() {
-        let incorrect_store_requests = vec![
-            "", "S", "STORE", "STORE$$", "STORE$k$", "STORE$k$v", "STORE$$v",
-            "STORE$1$v$", "STORE$k$1$", "STORE$K$v$", "STORE$k$V$", "STORE$*$*$",
-            "STOR$k$v$", "LOAD$k$", "STORE$A$v$abc", "aSTORE$k$v$"
-        ];
+    fn is_count_request_returns_true_only_when_should() {
+        let correct_count_requests = vec!["COUNT$", "COUNT$LOAD$k$"];
+        let incorrect_count_requests = vec!["", "COUNT", "COUN$", "aCOUNT$", "count$"];
 
-        for request in incorrect_store_requests {
-            assert!(!is_store_request(request).unwrap());
+        for request in correct_count_requests {
+            assert!(is_count_request(request).unwrap());
+        }
+        for request in incorrect_count_requests {
+            assert!(!is_count_request(request).unwrap());
         }
     }
 
     #[test]
-    fn is_load_request_returns_true_when_given_exact_load_request() {
-        let correct_load_requests = vec![
-            "LOAD$$", "LOAD$k$", "LOAD$key$", "LOAD$qwertyuiopasdfghjklzxcvbnm$"
-        ];
+    fn could_become_count_request_returns_true_only_when_should() {
+        let correct_count_prefixes = vec!["", "C", "COU", "COUNT"];
+        let incorrect_count_prefixes = vec!["U", "COUNTa", "COUNT$"];
 
-        for request in correct_load_requests {
-            assert!(is_load_request(request).unwrap());
+        for request in correct_count_prefixes {
+            assert!(could_become_count_request(request).unwrap());
+        }
+        for request in incorrect_count_prefixes {
+            assert!(!could_become_count_request(request).unwrap());
         }
     }
 
     #[test]
-    fn is_load_request_returns_true_when_given_prefix_of_load_request() {
-        let correct_prefixes_of_load_requests = vec![
-            "LOAD$$x", "LOAD$k$1*2*3*", "LOAD$key$$$$$", "LOAD$qwertyuiopasdfghjklzxcvbnm$abcd&4321"
-        ];
+    fn is_set_config_request_returns_true_only_when_should() {
+        let correct_requests = vec!["SETCONFIG$maxval$10$", "SETCONFIG$maxval$none$LOAD$k$", "SETCONFIG$$$"];
+        let incorrect_requests = vec!["", "SETCONFIG$maxval$", "SETCONFIG$MAXVAL$1$", "SETCONFIG$maxval$-1$", "SETCONFI$a$1$"];
 
-        for request in correct_prefixes_of_load_requests {
-            assert!(is_load_request(request).unwrap());
+        for request in correct_requests {
+            assert!(is_set_config_request(request).unwrap());
+        }
+        for request in incorrect_requests {
+            assert!(!is_set_config_request(request).unwrap());
         }
     }
 
     #[test]
-    fn is_load_request_returns_false_when_given_incorrect_load_request() {
-        let incorrect_load_requests = vec![
-            "", "L", "LOAD", "LOAD$", "LOAD$k", "LOAD$1$", "LOAD$K$",
-            "LOAD$*$", "LOA$k$", "STORE$k$v$", "LOAD$K$a", "aLOAD$k$"
-        ];
+    fn could_become_set_config_request_returns_true_only_when_should() {
+        let correct_prefixes = vec!["", "SET", "SETCONFIG", "SETCONFIG$max", "SETCONFIG$maxval$1"];
+        let incorrect_prefixes = vec!["SETCONFIGa", "SETCONFIG$max1", "SETCONFIG$maxval$1$", "SETCONFIG$maxval$-"];
 
-        for request in incorrect_load_requests {
-            assert!(!is_load_request(request).unwrap());
+        for request in correct_prefixes {
+            assert!(could_become_set_config_request(request).unwrap());
+        }
+        for request in incorrect_prefixes {
+            assert!(!could_become_set_config_request(request).unwrap());
         }
     }
 
     #[test]
-    fn could_become_store_request_returns_true_when_should() {
-        let correct_store_prefixes = vec![
-            "", "S", "ST", "STO", "STOR", "STORE", "STORE$",
-            "STORE$key", "STORE$qwertyuiopasdfghjklzxcvbnm",
-            "STORE$key$", "STORE$key$value", "STORE$$",
-            "STORE$key$qwertyuiopasdfghjklzxcvbnm"
-        ];
+    fn is_store_check_request_returns_true_only_when_should() {
+        let correct_requests = vec!["STORECHECK$k$v$", "STORECHECK$$$", "STORECHECK$User_1$value$LOAD$k$"];
+        let incorrect_requests = vec!["", "STORECHECK$k$", "STORECHECK$k$v", "STORECHECK$k$V$", "STORECHECK$k-$v$", "STORE$k$v$"];
 
-        for request in correct_store_prefixes {
-            assert!(could_become_store_request(request).unwrap());
+        for request in correct_requests {
+            assert!(is_store_check_request(request).unwrap());
+        }
+        for request in incorrect_requests {
+            assert!(!is_store_check_request(request).unwrap());
         }
     }
 
     #[test]
-    fn could_become_store_request_returns_false_when_should() {
-        let incorrect_store_prefixes = vec![
-            "T", "a", "STOE$", "STOREa", "STORE$1", "STORE$*", "STORE$a$1",
-            "STORE$$$", "STORE$$$a", "STORE$key$value$", "STRE$key$value"
-        ];
+    fn could_become_store_check_request_returns_true_only_when_should() {
+        let correct_prefixes = vec!["", "STORE", "STOREC", "STORECHECK$", "STORECHECK$key", "STORECHECK$key$val"];
+        let incorrect_prefixes = vec!["STORE$", "STORECHECKa", "STORECHECK$-", "STORECHECK$k$V", "STORECHECK$k$v$"];
 
-        for request in incorrect_store_prefixes {
-            assert!(!could_become_store_request(request).unwrap());
+        for request in correct_prefixes {
+            assert!(could_become_store_check_request(request).unwrap());
+        }
+        for request in incorrect_prefixes {
+            assert!(!could_become_store_check_request(request).unwrap());
         }
     }
 
     #[test]
-    fn could_become_load_request_returns_true_when_should() {
-        let correct_load_prefixes = vec![
-            "", "L", "LO", "LOA", "LOAD", "LOAD$", "LOAD$a",
-            "LOAD$key", "LOAD$qwertyuiopasdfghjklzxcvbnm"
-        ];
+    fn is_ack_request_returns_true_only_when_should() {
+        let correct_ack_requests = vec!["ACK$queued$", "ACK$persisted$LOAD$k$", "ACK$unknown$", "ACK$$"];
+        let incorrect_ack_requests = vec!["", "ACK$", "ACK$queued", "ACK$QUEUED$", "AC$queued$", "ack$queued$"];
 
-        for request in correct_load_prefixes {
-            assert!(could_become_load_request(request).unwrap());
+        for request in correct_ack_requests {
+            assert!(is_ack_request(request).unwrap());
+        }
+        for request in incorrect_ack_requests {
+            assert!(!is_ack_request(request).unwrap());
         }
     }
 
     #[test]
-    fn could_become_load_request_returns_false_when_should() {
-        let incorrect_load_prefixes = vec![
-            "O", "a", "LOD$", "LOADa", "LOAD$1", "LOAD$*", "LOAD$$",
-            "LOAD$$a", "LAD$key"
-        ];
+    fn could_become_ack_request_returns_true_only_when_should() {
+        let correct_ack_prefixes = vec!["", "A", "ACK", "ACK$", "ACK$que"];
+        let incorrect_ack_prefixes = vec!["C", "ACKa", "ACK$queued$", "ACK$1"];
 
-        for request in incorrect_load_prefixes {
-            assert!(!could_become_load_request(request).unwrap());
+        for request in correct_ack_prefixes {
+            assert!(could_become_ack_request(request).unwrap());
+        }
+        for request in incorrect_ack_prefixes {
+            assert!(!could_become_ack_request(request).unwrap());
         }
     }
 
     #[test]
-    fn split_store_request_splits_correctly() {
+    fn is_chunked_request_returns_true_only_when_should() {
+        let correct_chunked_requests = vec!["CHUNKED$0$", "CHUNKED$1024$LOAD$k$"];
+        let incorrect_chunked_requests = vec!["", "CHUNKED$", "CHUNKED$$", "CHUNKED$4", "CHUNKED$-1$", "CHUNKED$a$", "CHUNK$4$"];
+
+        for request in correct_chunked_requests {
+            assert!(is_chunked_request(request).unwrap());
+        }
+        for request in incorrect_chunked_requests {
+            assert!(!is_chunked_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_chunked_request_returns_true_only_when_should() {
+        let correct_chunked_prefixes = vec!["", "C", "CHUNK", "CHUNKED$", "CHUNKED$10"];
+        let incorrect_chunked_prefixes = vec!["H", "CHUNKEDa", "CHUNKED$10$", "CHUNKED$a"];
+
+        for request in correct_chunked_prefixes {
+            assert!(could_become_chunked_request(request).unwrap());
+        }
+        for request in incorrect_chunked_prefixes {
+            assert!(!could_become_chunked_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn try_parse_request_rejects_too_long_chunks() {
+        let mut message = format!("CHUNKED${}0$", usize::MAX);
+        assert!(matches!(try_parse_request(&mut message), Err(TaskError::ParseError)));
+    }
+
+    #[test]
+    fn is_dump_request_returns_true_only_when_should() {
+        let correct_dump_requests = vec!["DUMP$binary$", "DUMP$json$LOAD$k$", "DUMP$unknown$", "DUMP$$"];
+        let incorrect_dump_requests = vec!["", "DUMP$", "DUMP$json", "DUMP$JSON$", "DUM$json$", "aDUMP$json$", "dump$json$"];
+
+        for request in correct_dump_requests {
+            assert!(is_dump_request(request).unwrap());
+        }
+        for request in incorrect_dump_requests {
+            assert!(!is_dump_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_dump_request_returns_true_only_when_should() {
+        let correct_dump_prefixes = vec!["", "D", "DU", "DUMP", "DUMP$", "DUMP$js"];
+        let incorrect_dump_prefixes = vec!["U", "DUMPa", "DUMP$json$", "DUMP$1"];
+
+        for request in correct_dump_prefixes {
+            assert!(could_become_dump_request(request).unwrap());
+        }
+        for request in incorrect_dump_prefixes {
+            assert!(!could_become_dump_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_conns_request_returns_true_only_when_should() {
+        let correct_conns_requests = vec!["CONNS$", "CONNS$LOAD$k$"];
+        let incorrect_conns_requests = vec!["", "CONNS", "CONN$", "aCONNS$", "conns$"];
+
+        for request in correct_conns_requests {
+            assert!(is_conns_request(request).unwrap());
+        }
+        for request in incorrect_conns_requests {
+            assert!(!is_conns_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_conns_request_returns_true_only_when_should() {
+        let correct_conns_prefixes = vec!["", "C", "CON", "CONNS"];
+        let incorrect_conns_prefixes = vec!["N", "CONNSa", "CONNS$"];
+
+        for request in correct_conns_prefixes {
+            assert!(could_become_conns_request(request).unwrap());
+        }
+        for request in incorrect_conns_prefixes {
+            assert!(!could_become_conns_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_time_request_returns_true_only_when_should() {
+        let correct_time_requests = vec!["TIME$", "TIME$LOAD$k$"];
+        let incorrect_time_requests = vec!["", "TIME", "TIM$", "aTIME$", "time$"];
+
+        for request in correct_time_requests {
+            assert!(is_time_request(request).unwrap());
+        }
+        for request in incorrect_time_requests {
+            assert!(!is_time_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_time_request_returns_true_only_when_should() {
+        let correct_time_prefixes = vec!["", "T", "TIM", "TIME"];
+        let incorrect_time_prefixes = vec!["I", "TIMEa", "TIME$"];
+
+        for request in correct_time_prefixes {
+            assert!(could_become_time_request(request).unwrap());
+        }
+        for request in incorrect_time_prefixes {
+            assert!(!could_become_time_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_ping_request_returns_true_only_when_should() {
+        let correct_ping_requests = vec!["PING$", "PING$LOAD$k$"];
+        let incorrect_ping_requests = vec!["", "PING", "PIN$", "aPING$", "ping$", "PONG$"];
+
+        for request in correct_ping_requests {
+            assert!(is_ping_request(request).unwrap());
+        }
+        for request in incorrect_ping_requests {
+            assert!(!is_ping_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_quit_request_returns_true_only_when_should() {
+        let correct_quit_requests = vec!["QUIT$", "QUIT$LOAD$k$"];
+        let incorrect_quit_requests = vec!["", "QUIT", "QUI$", "aQUIT$", "quit$"];
+
+        for request in correct_quit_requests {
+            assert!(is_quit_request(request).unwrap());
+        }
+        for request in incorrect_quit_requests {
+            assert!(!is_quit_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_quit_request_returns_true_only_when_should() {
+        let correct_quit_prefixes = vec!["", "Q", "QU", "QUIT"];
+        let incorrect_quit_prefixes = vec!["U", "QUITa", "QUIT$"];
+
+        for request in correct_quit_prefixes {
+            assert!(could_become_quit_request(request).unwrap());
+        }
+        for request in incorrect_quit_prefixes {
+            assert!(!could_become_quit_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn could_become_ping_request_returns_true_only_when_should() {
+        let correct_ping_prefixes = vec!["", "P", "PI", "PING"];
+        let incorrect_ping_prefixes = vec!["I", "PINGa", "PING$", "PONG"];
+
+        for request in correct_ping_prefixes {
+            assert!(could_become_ping_request(request).unwrap());
+        }
+        for request in incorrect_ping_prefixes {
+            assert!(!could_become_ping_request(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn try_parse_request_parses_ping_request() {
+        let mut message = "PING$PING$".to_string();
+        assert!(matches!(try_parse_request(&mut message), Ok(Some(Request::Ping))));
+        assert_eq!("PING$", message);
+    }
+
+    #[test]
+    fn could_become_requests_returns_names_of_matching_requests() {
         let test_cases = vec![
-            ("STORE$$$", ("", "", "")),
-            ("STORE$k$$", ("k", "", "")),
-            ("STORE$$v$", ("", "v", "")),
-            ("STORE$k$v$", ("k", "v", "")),
-            ("STORE$k$v$r", ("k", "v", "r")),
-            ("STORE$$$r", ("", "", "r")),
-            ("STORE$key$value$rest", ("key", "value", "rest")),
-            ("STORE$k$v$STORE$k$v$", ("k", "v", "STORE$k$v$"))
+            ("S", vec!["STORE", "STOREB", "STOREX", "STORECHECK", "SELECT", "SNAPSHOT", "SCAN", "SETCONFIG"]),
+            ("STORE$key", vec!["STORE"]),
+            ("LO", vec!["LOAD", "LOADT"]),
+            ("LOAD$", vec!["LOAD"]),
+            ("L", vec!["LOAD", "LOADT", "LIMITS"]),
+            ("P", vec!["PUSHVER", "PREFIX", "PSUBSCRIBE", "PING", "PEEK"]),
+            ("FLUSH", vec!["FLUSH", "FLUSHALL"]),
+            ("FLUSHA", vec!["FLUSHALL"]),
+            ("DECR", vec!["DECR", "DECRFLOOR", "DECRDEL"]),
+            ("A", vec!["APPENDIFNEW", "ACK"]),
+            ("STORE$key$value$", vec![]),
+            ("X", vec![])
         ];
 
-        for (input, (k, v, r)) in test_cases {
-            assert_eq!((k.to_string(), v.to_string(), r.to_string()), split_store_request(input));
+        for (message, names) in test_cases {
+            assert_eq!(names, could_become_requests(message).unwrap());
         }
+        // JSON and METRICS requests need the json feature.
+        let json_requests = 2 * usize::from(!cfg!(feature = "json"));
+        assert_eq!(PARTIAL_REQUESTS.len() - json_requests, could_become_requests("").unwrap().len());
     }
 
     #[test]
-    fn split_load_request_splits_correctly() {
-        let test_cases = vec![
-            ("LOAD$$", ("", "")),
-            ("LOAD$k$", ("k", "")),
-            ("LOAD$$r", ("", "r")),
-            ("LOAD$k$r", ("k", "r")),
-            ("LOAD$key$rest", ("key", "rest")),
-            ("LOAD$k$LOAD$k$", ("k", "LOAD$k$"))
+    fn parse_request_returns_rest_of_message() {
+        let message = "STORE$k$v$LOAD$k$LO";
+        let Ok(Some((Request::Store(_), rest))) = parse_request(message) else {
+            panic!("expected STORE request")
+        };
+        assert_eq!("LOAD$k$LO", rest);
+        let Ok(Some((Request::Load(_), rest))) = parse_request(rest) else {
+            panic!("expected LOAD request")
+        };
+        assert_eq!("LO", rest);
+        assert!(matches!(parse_request(rest), Ok(None)));
+    }
+
+    #[test]
+    fn try_parse_request_parses_peek_request() {
+        let mut message = "PEEK$STO".to_string();
+        assert!(matches!(try_parse_request(&mut message), Ok(Some(Request::Peek))));
+        assert_eq!("STO", message);
+    }
+
+    #[test]
+    fn parse_request_parses_traced_requests() {
+        match parse_request("trace:4bf9-2_a:LOAD$k$LO") {
+            Ok(Some((Request::Traced(traced), rest))) => {
+                assert_eq!("4bf9-2_a", traced.id);
+                assert!(matches!(*traced.request, Request::Load(request) if request.key == "k"));
+                assert_eq!("LO", rest);
+            },
+            _ => panic!("expected traced LOAD request")
+        }
+
+        for message in ["t", "trace", "trace:", "trace:4b", "trace:4b:", "trace:4b:LOAD$k"] {
+            assert!(matches!(parse_request(message), Ok(None)), "{message}");
+        }
+        for message in ["trace::LOAD$k$", "trace:4b!:LOAD$k$", "trace:a:trace:b:LOAD$k$", "TRACE:a:LOAD$k$"] {
+            assert!(parse_request(message).is_err(), "{message}");
+        }
+    }
+
+    #[test]
+    fn parse_framed_request_parses_complete_frames() {
+        let mut message = "16$STORE$key$value$9$LOAD$key$3$".to_string();
+
+        match take_request(&mut message, parse_framed_request) {
+            Ok(Some(Request::Store(request))) => {
+                assert_eq!(("key", "value"), (request.key.as_str(), request.value.as_str()));
+            },
+            _ => panic!("expected STORE request")
+        }
+        match take_request(&mut message, parse_framed_request) {
+            Ok(Some(Request::Load(request))) => assert_eq!("key", request.key),
+            _ => panic!("expected LOAD request")
+        }
+        assert!(matches!(take_request(&mut message, parse_framed_request), Ok(None)));
+        assert_eq!("3$", message);
+    }
+
+    #[test]
+    fn parse_framed_request_reassembles_frame_split_across_reads() {
+        let fragments = ["1", "6", "$STO", "RE$key$va", "lue", "$"];
+        let mut message = String::new();
+
+        for fragment in &fragments[..fragments.len() - 1] {
+            message.push_str(fragment);
+            assert!(matches!(take_request(&mut message, parse_framed_request), Ok(None)));
+        }
+
+        message.push_str(fragments[fragments.len() - 1]);
+        assert!(matches!(take_request(&mut message, parse_framed_request), Ok(Some(Request::Store(_)))));
+        assert!(message.is_empty());
+    }
+
+    #[test]
+    fn parse_framed_request_rejects_incorrect_frames() {
+        let incorrect_messages = vec![
+            "a", "$LOAD$k$", "x5$LOAD$k$", "6$LOAD$k$", "8$LOAD$k$$", "12$LOAD$k$LOAD$", "7$LOAD$-$",
+            "99999999999999999999999$"
         ];
 
-        for (input, (k, r)) in test_cases {
-            assert_eq!((k.to_string(), r.to_string()), split_load_request(input));
+        for message in incorrect_messages {
+            assert!(take_request(&mut message.to_string(), parse_framed_request).is_err());
         }
     }
 }
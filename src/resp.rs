@@ -0,0 +1,155 @@
+// author - Patryk Jędrzejczak
+
+// Subset of RESP, the protocol of Redis, so Redis clients can talk to the
+// server. Every request is an array of bulk strings, e.g.
+// *2\r\n$3\r\nGET\r\n$3\r\nkey\r\n, and is translated into a request of
+// the default protocol: GET into LOAD, SET into STORE, DEL into MDEL and
+// PING into PING. Names of commands are case-insensitive.
+
+use super::{TaskError, Request, StoreRequest, LoadRequest, MultiDeleteRequest, ParseResult};
+
+// If message contains a prefix that is a complete RESP array, returns
+// Some((request, rest)), like parse_request. If message is incorrect or
+// the array is not a supported command, returns TaskError. Otherwise,
+// returns None.
+pub fn parse_resp_request(message: &str) -> ParseResult<'_> {
+    let Some((mut args, len)) = split_array(message)? else {
+        return Ok(None);
+    };
+    let rest = &message[len..];
+
+    let name = args.first().ok_or(TaskError::ParseError)?.to_ascii_uppercase();
+    // Arguments of supported commands are keys, except the value of SET.
+    let keys = if name == "SET" { args.get(1..2) } else { args.get(1..) };
+    if !keys.unwrap_or_default().iter().all(|key| is_key(key)) {
+        return Err(TaskError::ParseError);
+    }
+
+    let request = match (name.as_str(), args.len()) {
+        ("GET", 2) => Request::Load(LoadRequest::new(args.pop().unwrap())),
+        ("SET", 3) => {
+            let value = args.pop().unwrap();
+            let key = args.pop().unwrap();
+            Request::Store(StoreRequest::new(key, value))
+        },
+        ("DEL", 2..) => Request::MultiDelete(MultiDeleteRequest::new(args.split_off(1))),
+        ("PING", 1) => Request::Ping,
+        _ => return Err(TaskError::ParseError)
+    };
+    Ok(Some((request, rest)))
+}
+
+// Keys have the same form as in the default protocol.
+fn is_key(arg: &str) -> bool {
+    arg.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+// If message starts with a complete array of bulk strings, returns them
+// and the length of the array in bytes. Returns None if the array is not
+// complete yet and TaskError if message is not an array of bulk strings.
+fn split_array(message: &str) -> Result<Option<(Vec<String>, usize)>, TaskError> {
+    let Some((len, mut pos)) = split_header(message, 0, '*')? else {
+        return Ok(None);
+    };
+
+    let mut args = Vec::new();
+    for _ in 0..len {
+        let Some((len, start)) = split_header(message, pos, '$')? else {
+            return Ok(None);
+        };
+        let end = start.checked_add(len).ok_or(TaskError::ParseError)?;
+        if message.len() < end.saturating_add(2) {
+            return Ok(None);
+        }
+        if message.as_bytes()[end..end + 2] != *b"\r\n" {
+            return Err(TaskError::ParseError);
+        }
+        // Fails if len bytes end in the middle of a character.
+        args.push(message.get(start..end).ok_or(TaskError::ParseError)?.to_string());
+        pos = end + 2;
+    }
+    Ok(Some((args, pos)))
+}
+
+// Parses a header like *2\r\n or $3\r\n starting at pos, where kind is
+// the first character. Returns the number from the header and the position
+// right after it, or None if the header is not complete yet.
+fn split_header(message: &str, pos: usize, kind: char) -> Result<Option<(usize, usize)>, TaskError> {
+    let rest = &message[pos..];
+    if rest.is_empty() {
+        return Ok(None);
+    }
+    if !rest.starts_with(kind) {
+        return Err(TaskError::ParseError);
+    }
+
+    match rest.find("\r\n") {
+        Some(end) => {
+            let number = rest[1..end].parse().map_err(|_| TaskError::ParseError)?;
+            Ok(Some((number, pos + end + 2)))
+        },
+        // Header cannot become correct if it has anything else than digits.
+        None if rest[1..].trim_end_matches('\r').bytes().all(|byte| byte.is_ascii_digit()) => Ok(None),
+        None => Err(TaskError::ParseError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_parsing::take_request;
+
+    #[test]
+    fn split_array_waits_for_whole_array() {
+        let message = "*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n";
+        for len in 0..message.len() {
+            assert!(split_array(&message[..len]).unwrap().is_none());
+        }
+
+        let expected = (vec!["GET".to_string(), "key".to_string()], message.len());
+        assert_eq!(Some(expected), split_array(&format!("{message}*1")).unwrap());
+    }
+
+    #[test]
+    fn split_array_rejects_incorrect_arrays() {
+        let incorrect_messages = vec![
+            "GET key\r\n", "*a\r\n", "*1\r\n:1\r\n", "*1\r\n$3\r\nGETX\r\n", "*1\r\n$-1\r\n", "*1x", "*1\r\n$1x"
+        ];
+
+        for message in incorrect_messages {
+            assert!(split_array(message).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_resp_request_translates_commands() {
+        let mut message = "*3\r\n$3\r\nset\r\n$1\r\nk\r\n$5\r\na$\r\nb\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n".to_string();
+        assert!(matches!(
+            take_request(&mut message, parse_resp_request),
+            Ok(Some(Request::Store(request))) if request.key == "k" && request.value == "a$\r\nb"
+        ));
+        assert!(matches!(take_request(&mut message, parse_resp_request), Ok(Some(Request::Load(request))) if request.key == "k"));
+        assert!(message.is_empty());
+
+        let mut message = "*3\r\n$3\r\nDEL\r\n$1\r\na\r\n$1\r\nb\r\n*1\r\n$4\r\nPING\r\n*1\r\n".to_string();
+        assert!(matches!(
+            take_request(&mut message, parse_resp_request),
+            Ok(Some(Request::MultiDelete(request))) if request.keys == ["a", "b"]
+        ));
+        assert!(matches!(take_request(&mut message, parse_resp_request), Ok(Some(Request::Ping))));
+        assert!(matches!(take_request(&mut message, parse_resp_request), Ok(None)));
+        assert_eq!("*1\r\n", message);
+    }
+
+    #[test]
+    fn parse_resp_request_rejects_unsupported_commands() {
+        let incorrect_messages = vec![
+            "*0\r\n", "*1\r\n$3\r\nGET\r\n", "*2\r\n$4\r\nINCR\r\n$1\r\nk\r\n",
+            "*2\r\n$3\r\nGET\r\n$2\r\nk-\r\n", "*3\r\n$3\r\nSET\r\n$2\r\nk$\r\n$1\r\nv\r\n"
+        ];
+
+        for message in incorrect_messages {
+            assert!(take_request(&mut message.to_string(), parse_resp_request).is_err());
+        }
+    }
+}
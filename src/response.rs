@@ -0,0 +1,507 @@
+// author - Patryk Jędrzejczak
+
+use super::dump::{encode_dump, DumpFormat};
+#[cfg(feature = "json")]
+use super::dump::encode_json_dump;
+#[cfg(feature = "json")]
+use super::metrics::MetricsSnapshot;
+
+// Response sent to the client after processing a request.
+pub enum Response {
+    Done,
+    Found(String),
+    NotFound,
+    Exists,
+    // Presence of a single key. Answer to EXISTS request.
+    Present(bool),
+    // Presence of keys in the order they were listed in MEXISTS request.
+    ExistsList(Vec<bool>),
+    // Values of keys in the order they were listed in MGET request.
+    // None means the key is absent.
+    Values(Vec<Option<String>>),
+    Full,
+    Mismatch,
+    // Value was stored by CAS request, because the key had the expected value.
+    Swapped,
+    // Item was not appended by APPENDIFNEW request, because it was already there.
+    Skipped,
+    Timeout,
+    // Setting cannot be changed by SETCONFIG request while the server runs.
+    Immutable,
+    // STORE request with the same key and value would store the pair.
+    // Answer to STORECHECK request.
+    Ok,
+    // Request was correct, but could not be processed for the given reason.
+    Error(&'static str),
+    // Value of a key after MAXSET, MINSET, INCR or DECR request, which may be negative.
+    Counter(i64),
+    // Value of a key that DECRFLOOR request left unchanged, because
+    // decrementing it would go below the floor.
+    Clamped(i64),
+    // Value stored as the value of a key. Pushed to PSUBSCRIBE subscribers.
+    Changed { key: String, value: String },
+    // Number of removed keys.
+    Deleted(usize),
+    // Key was removed by DELETE request.
+    Removed,
+    // All keys were removed by FLUSHALL request.
+    Flushed,
+    // All keys in the database. Answer to KEYS request.
+    Keys(Vec<String>),
+    // Length of the write-ahead log, total length of keys and values in
+    // memory and the number of keys. Answer to USAGE request.
+    Usage { disk: u64, cache: usize, keys: usize },
+    // Number of open connections.
+    Conns(usize),
+    // Number of keys. Answer to COUNT request.
+    Count(usize),
+    // All pairs sorted by keys. Answer to DUMP request. Always encoded
+    // in the selected format of the dump module, even with JSON request.
+    Dump(Vec<(String, String)>, DumpFormat),
+    // Time since the Unix epoch in whole seconds and the remaining nanoseconds.
+    Time { secs: u64, nanos: u32 },
+    // Answer to PING request.
+    Pong,
+    // Answer to QUIT request, after which the connection is closed.
+    Bye,
+    // Limits of lengths of a key, a value and all keys and values.
+    // None means there is no limit.
+    Limits { max_key: Option<usize>, max_value: Option<usize>, max_total: Option<usize> },
+    // State of the buffered part of the message. Answer to PEEK request.
+    Peek { len: usize, could_become: Vec<&'static str> },
+    // Counters of the server's activity. Always encoded as JSON.
+    #[cfg(feature = "json")]
+    Metrics(MetricsSnapshot)
+}
+
+// Format in which responses are written to a connection.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResponseFormat {
+    // Default format, e.g. FOUND$value$.
+    Text,
+    // Every response is a JSON object, e.g. {"status":"FOUND","value":"value"}.
+    #[cfg(feature = "json")]
+    Json,
+    // Responses of Redis to the commands of the resp module, e.g. +OK\r\n.
+    Resp,
+    // HTTP responses to the requests of the http module, e.g. 404 Not Found.
+    Http
+}
+
+impl Response {
+    pub fn encode(&self, format: ResponseFormat) -> Vec<u8> {
+        match self {
+            Response::Dump(pairs, DumpFormat::Binary) => return encode_dump(pairs),
+            #[cfg(feature = "json")]
+            Response::Dump(pairs, DumpFormat::Json) => return encode_json_dump(pairs),
+            _ => {}
+        }
+        match format {
+            ResponseFormat::Text => self.to_text().into_bytes(),
+            ResponseFormat::Resp => self.to_resp().into_bytes(),
+            ResponseFormat::Http => self.to_http().into_bytes(),
+            #[cfg(feature = "json")]
+            ResponseFormat::Json => self.to_json().into_bytes()
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        match self {
+            Response::Done => "DONE",
+            Response::Found(_) => "FOUND",
+            Response::NotFound => "NOTFOUND",
+            Response::Exists | Response::ExistsList(_) => "EXISTS",
+            Response::Present(true) => "YES",
+            Response::Present(false) => "NO",
+            Response::Full => "FULL",
+            Response::Mismatch => "MISMATCH",
+            Response::Swapped => "SWAPPED",
+            Response::Skipped => "SKIPPED",
+            Response::Timeout => "TIMEOUT",
+            Response::Immutable => "IMMUTABLE",
+            Response::Ok => "OK",
+            Response::Error(_) => "ERROR",
+            Response::Counter(_) | Response::Clamped(_) => "VALUE",
+            Response::Changed { .. } => "CHANGED",
+            Response::Deleted(_) | Response::Removed => "DELETED",
+            Response::Flushed => "FLUSHED",
+            Response::Keys(_) => "KEYS",
+            Response::Values(_) => "VALUES",
+            Response::Usage { .. } => "USAGE",
+            Response::Limits { .. } => "LIMITS",
+            Response::Conns(_) => "CONNS",
+            Response::Count(_) => "COUNT",
+            Response::Dump(..) => "DUMP",
+            Response::Time { .. } => "TIME",
+            Response::Pong => "PONG",
+            Response::Bye => "BYE",
+            Response::Peek { .. } => "PEEK",
+            #[cfg(feature = "json")]
+            Response::Metrics(_) => "METRICS"
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            #[cfg(feature = "json")]
+            Response::Metrics(_) => self.to_json(),
+            Response::Found(value) => format!("FOUND${value}$"),
+            Response::Changed { key, value } => format!("CHANGED${key}${value}$"),
+            Response::Counter(value) => format!("VALUE${value}$"),
+            Response::Clamped(value) => format!("VALUE${value}$clamped$"),
+            Response::Error(reason) => format!("ERROR${reason}$"),
+            Response::Deleted(count) => format!("DELETED${count}$"),
+            Response::Conns(count) => format!("CONNS${count}$"),
+            Response::Count(count) => format!("COUNT${count}$"),
+            Response::Time { secs, nanos } => format!("TIME${secs}${nanos}$"),
+            Response::ExistsList(present) => {
+                let mask: String = present.iter().map(|present| if *present { '1' } else { '0' }).collect();
+                format!("EXISTS${mask}$")
+            },
+            Response::Keys(keys) => {
+                let keys: String = keys.iter().map(|key| format!("{key}$")).collect();
+                format!("KEYS${keys}")
+            },
+            // The number of values comes first, so the client knows where the response ends.
+            Response::Values(values) => {
+                let answers: String = values.iter().map(|value| match value {
+                    Some(value) => format!("FOUND${value}$"),
+                    None => "NOTFOUND$".to_string()
+                }).collect();
+                format!("VALUES${}${answers}", values.len())
+            },
+            Response::Usage { disk, cache, keys } => format!("USAGE$disk={disk}$cache={cache}$keys={keys}$"),
+            Response::Limits { max_key, max_value, max_total } => format!(
+                "LIMITS$maxkey={}$maxval={}$maxtotal={}$",
+                limit_to_text(max_key), limit_to_text(max_value), limit_to_text(max_total)
+            ),
+            Response::Peek { len, could_become } if could_become.is_empty() => {
+                format!("PEEK$len={len}$couldbecome=none$")
+            },
+            Response::Peek { len, could_become } => {
+                format!("PEEK$len={len}$couldbecome={}$", could_become.join(","))
+            },
+            _ => format!("{}$", self.status())
+        }
+    }
+
+    // Only responses to requests that RESP commands are translated into
+    // have their Redis counterparts. Other responses are encoded as errors.
+    fn to_resp(&self) -> String {
+        match self {
+            Response::Done => "+OK\r\n".to_string(),
+            Response::Pong => "+PONG\r\n".to_string(),
+            Response::Found(value) => format!("${}\r\n{value}\r\n", value.len()),
+            Response::NotFound => "$-1\r\n".to_string(),
+            Response::Deleted(count) => format!(":{count}\r\n"),
+            Response::Full => "-OOM command not allowed when used memory > 'maxmemory'.\r\n".to_string(),
+            Response::Error(reason) => format!("-ERR {reason}\r\n"),
+            _ => format!("-ERR unexpected response {}\r\n", self.status())
+        }
+    }
+
+    // Only responses to LOAD and STORE requests have their HTTP counterparts.
+    // Errors close the connection, so their responses say so.
+    fn to_http(&self) -> String {
+        let (status, body) = match self {
+            Response::Done => ("200 OK", ""),
+            Response::Found(value) => ("200 OK", value.as_str()),
+            Response::NotFound => ("404 Not Found", ""),
+            Response::Full => ("507 Insufficient Storage", ""),
+            Response::Error(reason) => {
+                let status = match *reason {
+                    "bad_request" => "400 Bad Request",
+                    "too_large" | "value_too_large" => "413 Content Too Large",
+                    _ => "500 Internal Server Error"
+                };
+                let len = reason.len();
+                return format!("HTTP/1.1 {status}\r\nContent-Length: {len}\r\nConnection: close\r\n\r\n{reason}");
+            },
+            _ => ("500 Internal Server Error", self.status())
+        };
+        format!("HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    // Plain-text values are stored in JSON as they are. Values with control
+    // characters, e.g. stored with STOREB, are base64-encoded and marked with
+    // "encoding":"base64", see is_plain_text. VALUES encodes all values if
+    // any of them is not plain text.
+    #[cfg(feature = "json")]
+    fn to_json(&self) -> String {
+        let json = match self {
+            Response::Found(value) if !is_plain_text(value) => {
+                serde_json::json!({ "status": self.status(), "value": to_base64(value), "encoding": "base64" })
+            },
+            Response::Found(value) => serde_json::json!({ "status": self.status(), "value": value }),
+            Response::Changed { key, value } if !is_plain_text(value) => serde_json::json!({
+                "status": self.status(), "key": key, "value": to_base64(value), "encoding": "base64"
+            }),
+            Response::Changed { key, value } => {
+                serde_json::json!({ "status": self.status(), "key": key, "value": value })
+            },
+            Response::Counter(value) => serde_json::json!({ "status": self.status(), "value": value }),
+            Response::Clamped(value) => serde_json::json!({ "status": self.status(), "value": value, "clamped": true }),
+            Response::Error(reason) => serde_json::json!({ "status": self.status(), "reason": reason }),
+            Response::Deleted(count) | Response::Conns(count) | Response::Count(count) => {
+                serde_json::json!({ "status": self.status(), "count": count })
+            },
+            Response::Time { secs, nanos } => {
+                serde_json::json!({ "status": self.status(), "secs": secs, "nanos": nanos })
+            },
+            Response::ExistsList(present) => serde_json::json!({ "status": self.status(), "exists": present }),
+            Response::Keys(keys) => serde_json::json!({ "status": self.status(), "keys": keys }),
+            Response::Values(values) if !values.iter().flatten().all(|value| is_plain_text(value)) => {
+                let values: Vec<_> = values.iter().map(|value| value.as_deref().map(to_base64)).collect();
+                serde_json::json!({ "status": self.status(), "values": values, "encoding": "base64" })
+            },
+            Response::Values(values) => serde_json::json!({ "status": self.status(), "values": values }),
+            Response::Usage { disk, cache, keys } => {
+                serde_json::json!({ "status": self.status(), "disk": disk, "cache": cache, "keys": keys })
+            },
+            Response::Limits { max_key, max_value, max_total } => serde_json::json!({
+                "status": self.status(), "maxkey": max_key, "maxval": max_value, "maxtotal": max_total
+            }),
+            Response::Peek { len, could_become } => {
+                serde_json::json!({ "status": self.status(), "len": len, "couldbecome": could_become })
+            },
+            Response::Metrics(metrics) => serde_json::json!({
+                "status": self.status(),
+                "stores": metrics.stores,
+                "loads": metrics.loads,
+                "hits": metrics.hits,
+                "misses": metrics.misses,
+                "errors": metrics.errors,
+                "connections": metrics.connections,
+                "reapedconns": metrics.reaped_connections,
+                "truncated": metrics.truncated_responses,
+                "uptime": metrics.uptime
+            }),
+            _ => serde_json::json!({ "status": self.status() })
+        };
+        json.to_string()
+    }
+}
+
+// Values with control characters, e.g. stored with STOREB, are not plain
+// text and are sent base64-encoded in JSON responses.
+#[cfg(feature = "json")]
+pub(crate) fn is_plain_text(value: &str) -> bool {
+    !value.chars().any(char::is_control)
+}
+
+#[cfg(feature = "json")]
+pub(crate) fn to_base64(value: &str) -> String {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD.encode(value)
+}
+
+// Encodes the value as chunks CHUNK$len$bytes of at most chunk_bytes bytes
+// each, followed by ENDCHUNK$, so clients can tell where the value ends
+// even if it contains $. Lengths are numbers of bytes, so a chunk may end
+// in the middle of a character.
+pub(crate) fn encode_chunks(value: &str, chunk_bytes: usize) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for chunk in value.as_bytes().chunks(chunk_bytes) {
+        encoded.extend_from_slice(format!("CHUNK${}$", chunk.len()).as_bytes());
+        encoded.extend_from_slice(chunk);
+    }
+    encoded.extend_from_slice(b"ENDCHUNK$");
+    encoded
+}
+
+fn limit_to_text(limit: &Option<usize>) -> String {
+    limit.map_or("none".to_string(), |limit| limit.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_encoding_is_correct() {
+        let test_cases = vec![
+            (Response::Done, "DONE$"),
+            (Response::Found("value".to_string()), "FOUND$value$"),
+            (Response::Found(String::new()), "FOUND$$"),
+            (Response::NotFound, "NOTFOUND$"),
+            (Response::Exists, "EXISTS$"),
+            (Response::Full, "FULL$"),
+            (Response::Mismatch, "MISMATCH$"),
+            (Response::Swapped, "SWAPPED$"),
+            (Response::Skipped, "SKIPPED$"),
+            (Response::Timeout, "TIMEOUT$"),
+            (Response::Immutable, "IMMUTABLE$"),
+            (Response::Ok, "OK$"),
+            (Response::Changed { key: "k".to_string(), value: "v".to_string() }, "CHANGED$k$v$"),
+            (Response::Counter(-3), "VALUE$-3$"),
+            (Response::Clamped(0), "VALUE$0$clamped$"),
+            (Response::Error("reason"), "ERROR$reason$"),
+            (Response::Deleted(2), "DELETED$2$"),
+            (Response::Removed, "DELETED$"),
+            (Response::Flushed, "FLUSHED$"),
+            (Response::Conns(4), "CONNS$4$"),
+            (Response::Count(3), "COUNT$3$"),
+            (Response::Time { secs: 1700000000, nanos: 5 }, "TIME$1700000000$5$"),
+            (Response::Pong, "PONG$"),
+            (Response::Bye, "BYE$"),
+            (Response::Present(true), "YES$"),
+            (Response::Present(false), "NO$"),
+            (Response::ExistsList(vec![true, false, true]), "EXISTS$101$"),
+            (Response::ExistsList(vec![]), "EXISTS$$"),
+            (Response::Values(vec![Some("a".to_string()), None, Some(String::new())]), "VALUES$3$FOUND$a$NOTFOUND$FOUND$$"),
+            (Response::Values(vec![]), "VALUES$0$"),
+            (Response::Keys(vec!["a".to_string(), "bc".to_string()]), "KEYS$a$bc$"),
+            (Response::Keys(vec![]), "KEYS$"),
+            (Response::Usage { disk: 40, cache: 12, keys: 3 }, "USAGE$disk=40$cache=12$keys=3$"),
+            (
+                Response::Limits { max_key: None, max_value: Some(10), max_total: Some(100) },
+                "LIMITS$maxkey=none$maxval=10$maxtotal=100$"
+            ),
+            (Response::Peek { len: 5, could_become: vec!["STORE"] }, "PEEK$len=5$couldbecome=STORE$"),
+            (Response::Peek { len: 1, could_become: vec!["PREFIX", "PEEK"] }, "PEEK$len=1$couldbecome=PREFIX,PEEK$"),
+            (Response::Peek { len: 1, could_become: vec![] }, "PEEK$len=1$couldbecome=none$")
+        ];
+
+        for (response, encoded) in test_cases {
+            assert_eq!(encoded.as_bytes(), response.encode(ResponseFormat::Text));
+        }
+    }
+
+    #[test]
+    fn values_are_split_into_chunks() {
+        let test_cases = [
+            ("", 3, "ENDCHUNK$"),
+            ("abc", 3, "CHUNK$3$abcENDCHUNK$"),
+            ("a$b$c", 2, "CHUNK$2$a$CHUNK$2$b$CHUNK$1$cENDCHUNK$")
+        ];
+        for (value, chunk_bytes, encoded) in test_cases {
+            assert_eq!(encoded.as_bytes(), encode_chunks(value, chunk_bytes));
+        }
+        // Chunks are counted in bytes, not characters.
+        assert_eq!(b"CHUNK$1$\xc5CHUNK$1$\xbcENDCHUNK$".as_slice(), encode_chunks("ż", 1));
+    }
+
+    #[test]
+    fn resp_encoding_is_correct() {
+        let test_cases = vec![
+            (Response::Done, "+OK\r\n"),
+            (Response::Pong, "+PONG\r\n"),
+            (Response::Found("zażółć".to_string()), "$10\r\nzażółć\r\n"),
+            (Response::Found(String::new()), "$0\r\n\r\n"),
+            (Response::NotFound, "$-1\r\n"),
+            (Response::Deleted(2), ":2\r\n"),
+            (Response::Full, "-OOM command not allowed when used memory > 'maxmemory'.\r\n"),
+            (Response::Error("bad_request"), "-ERR bad_request\r\n"),
+            (Response::Conns(1), "-ERR unexpected response CONNS\r\n")
+        ];
+
+        for (response, encoded) in test_cases {
+            assert_eq!(encoded.as_bytes(), response.encode(ResponseFormat::Resp));
+        }
+    }
+
+    #[test]
+    fn http_encoding_is_correct() {
+        let test_cases = vec![
+            (Response::Done, "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"),
+            (Response::Found("zażółć".to_string()), "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nzażółć"),
+            (Response::NotFound, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"),
+            (Response::Full, "HTTP/1.1 507 Insufficient Storage\r\nContent-Length: 0\r\n\r\n"),
+            (
+                Response::Error("bad_request"),
+                "HTTP/1.1 400 Bad Request\r\nContent-Length: 11\r\nConnection: close\r\n\r\nbad_request"
+            ),
+            (
+                Response::Error("too_large"),
+                "HTTP/1.1 413 Content Too Large\r\nContent-Length: 9\r\nConnection: close\r\n\r\ntoo_large"
+            )
+        ];
+
+        for (response, encoded) in test_cases {
+            assert_eq!(encoded.as_bytes(), response.encode(ResponseFormat::Http));
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_encoding_is_correct() {
+        let test_cases = vec![
+            (Response::Done, r#"{"status":"DONE"}"#),
+            (Response::Found("value".to_string()), r#"{"status":"FOUND","value":"value"}"#),
+            (Response::Found("zażółć".to_string()), r#"{"status":"FOUND","value":"zażółć"}"#),
+            (Response::Found("a\r\nb\0".to_string()), r#"{"encoding":"base64","status":"FOUND","value":"YQ0KYgA="}"#),
+            (Response::NotFound, r#"{"status":"NOTFOUND"}"#),
+            (Response::Exists, r#"{"status":"EXISTS"}"#),
+            (Response::Full, r#"{"status":"FULL"}"#),
+            (Response::Mismatch, r#"{"status":"MISMATCH"}"#),
+            (Response::Swapped, r#"{"status":"SWAPPED"}"#),
+            (Response::Skipped, r#"{"status":"SKIPPED"}"#),
+            (Response::Timeout, r#"{"status":"TIMEOUT"}"#),
+            (Response::Immutable, r#"{"status":"IMMUTABLE"}"#),
+            (Response::Ok, r#"{"status":"OK"}"#),
+            (
+                Response::Changed { key: "k".to_string(), value: "v".to_string() },
+                r#"{"key":"k","status":"CHANGED","value":"v"}"#
+            ),
+            (
+                Response::Changed { key: "k".to_string(), value: "\n".to_string() },
+                r#"{"encoding":"base64","key":"k","status":"CHANGED","value":"Cg=="}"#
+            ),
+            (Response::Counter(-3), r#"{"status":"VALUE","value":-3}"#),
+            (Response::Clamped(0), r#"{"clamped":true,"status":"VALUE","value":0}"#),
+            (Response::Error("reason"), r#"{"reason":"reason","status":"ERROR"}"#),
+            (Response::Deleted(2), r#"{"count":2,"status":"DELETED"}"#),
+            (Response::Removed, r#"{"status":"DELETED"}"#),
+            (Response::Flushed, r#"{"status":"FLUSHED"}"#),
+            (Response::Conns(4), r#"{"count":4,"status":"CONNS"}"#),
+            (Response::Count(3), r#"{"count":3,"status":"COUNT"}"#),
+            (Response::Time { secs: 1700000000, nanos: 5 }, r#"{"nanos":5,"secs":1700000000,"status":"TIME"}"#),
+            (Response::Pong, r#"{"status":"PONG"}"#),
+            (Response::Bye, r#"{"status":"BYE"}"#),
+            (Response::Present(true), r#"{"status":"YES"}"#),
+            (Response::Present(false), r#"{"status":"NO"}"#),
+            (Response::ExistsList(vec![true, false]), r#"{"exists":[true,false],"status":"EXISTS"}"#),
+            (Response::Values(vec![Some("a".to_string()), None]), r#"{"status":"VALUES","values":["a",null]}"#),
+            (
+                Response::Values(vec![Some("a".to_string()), None, Some("\t".to_string())]),
+                r#"{"encoding":"base64","status":"VALUES","values":["YQ==",null,"CQ=="]}"#
+            ),
+            (Response::Keys(vec!["a".to_string()]), r#"{"keys":["a"],"status":"KEYS"}"#),
+            (Response::Usage { disk: 40, cache: 12, keys: 3 }, r#"{"cache":12,"disk":40,"keys":3,"status":"USAGE"}"#),
+            (
+                Response::Limits { max_key: None, max_value: Some(10), max_total: None },
+                r#"{"maxkey":null,"maxtotal":null,"maxval":10,"status":"LIMITS"}"#
+            ),
+            (Response::Peek { len: 5, could_become: vec!["STORE"] }, r#"{"couldbecome":["STORE"],"len":5,"status":"PEEK"}"#)
+        ];
+
+        for (response, encoded) in test_cases {
+            assert_eq!(encoded.as_bytes(), response.encode(ResponseFormat::Json));
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn metrics_are_encoded_as_json_in_both_formats() {
+        let metrics = MetricsSnapshot {
+            stores: 1,
+            loads: 2,
+            hits: 1,
+            misses: 1,
+            errors: 0,
+            connections: 3,
+            active_connections: 2,
+            reaped_connections: 1,
+            truncated_responses: 0,
+            uptime: 7
+        };
+        let encoded = concat!(
+            r#"{"connections":3,"errors":0,"hits":1,"loads":2,"misses":1,"reapedconns":1,"#,
+            r#""status":"METRICS","stores":1,"truncated":0,"uptime":7}"#
+        );
+
+        let response = Response::Metrics(metrics);
+        assert_eq!(encoded.as_bytes(), response.encode(ResponseFormat::Text));
+        assert_eq!(encoded.as_bytes(), response.encode(ResponseFormat::Json));
+    }
+}
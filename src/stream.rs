@@ -0,0 +1,76 @@
+// author - Patryk Jędrzejczak
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use std::io;
+
+use super::Config;
+
+// Byte stream over which requests are received and responses are sent.
+// Request processing does not depend on what the stream really is.
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+// Wraps socket of a new connection with TLS if the configuration says so.
+// The handshake is limited by the idle timeout, so clients that never
+// finish it do not hold the connection forever. Applies to connections of
+// all protocols, unlike compression, see connection_stream.
+#[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+pub(crate) async fn secure_stream(socket: TcpStream, config: &Config) -> io::Result<Box<dyn Stream>> {
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &config.tls {
+        let handshake = tls.accept(socket);
+        let stream = match config.idle_timeout {
+            Some(idle_timeout) => tokio::time::timeout(idle_timeout, handshake).await
+                .unwrap_or(Err(io::ErrorKind::TimedOut.into()))?,
+            None => handshake.await?
+        };
+        return Ok(Box::new(stream));
+    }
+
+    Ok(Box::new(socket))
+}
+
+// Wraps stream of a new connection of the default protocol according to
+// the configuration.
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+pub(crate) fn connection_stream(stream: Box<dyn Stream>, config: &Config) -> Box<dyn Stream> {
+    #[cfg(feature = "compression")]
+    if config.compressed {
+        return compressed(stream);
+    }
+
+    stream
+}
+
+// Creates an acceptor encrypting connections with TLS, with the
+// certificate chain and the private key read from PEM files.
+#[cfg(feature = "tls")]
+pub(crate) fn tls_acceptor(cert: &std::path::Path, key: &std::path::Path) -> io::Result<tokio_rustls::TlsAcceptor> {
+    use std::fs::File;
+    use std::io::BufReader;
+    use tokio_rustls::rustls::ServerConfig;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?)).collect::<io::Result<Vec<_>>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no private key found"))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    Ok(tokio_rustls::TlsAcceptor::from(std::sync::Arc::new(config)))
+}
+
+// Compresses both directions of stream with zlib. Every flush of the
+// written side ends a compressed block, so the other side can decode
+// everything written so far.
+#[cfg(feature = "compression")]
+pub(crate) fn compressed<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S) -> Box<dyn Stream> {
+    use async_compression::tokio::bufread::ZlibDecoder;
+    use async_compression::tokio::write::ZlibEncoder;
+    use tokio::io::BufReader;
+
+    let (reader, writer) = tokio::io::split(stream);
+    Box::new(tokio::io::join(ZlibDecoder::new(BufReader::new(reader)), ZlibEncoder::new(writer)))
+}
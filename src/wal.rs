@@ -0,0 +1,681 @@
+// author - Patryk Jędrzejczak
+
+use tokio::time::Instant;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "encryption")]
+pub(crate) use super::cipher::Cipher;
+
+// Without the encryption feature there are no ciphers, so no batch is
+// encrypted and logs with encrypted batches cannot be opened.
+#[cfg(not(feature = "encryption"))]
+pub(crate) enum Cipher {}
+
+// The log is compacted once it is larger than this and more than twice
+// as large as right after the previous compaction.
+const COMPACTION_MIN_BYTES: u64 = 1 << 20;
+
+// Every log starts with the header KVSWAL1$, where 1 is the version of
+// its format, so a log written by a newer server with another format is
+// rejected instead of misread. Logs written before the header was added
+// are version 0, which has the same records. They are upgraded to the
+// current version when they are opened.
+const MAGIC: &str = "KVSWAL";
+const VERSION: u32 = 1;
+
+// Name of records holding batches of encrypted records.
+const ENCRYPTED_BATCH: &[u8] = b"ENC$";
+
+// First byte of an encoded value, telling how the rest is encoded.
+const RAW: u8 = b'r';
+#[cfg(feature = "compression")]
+const GZIP: u8 = b'g';
+
+// Change of the database. Values are prefixed with their length and
+// a header byte, so they may contain any characters and may be compressed,
+// e.g. SET$key$6$rva$ue or DEL$key$. If the log is encrypted, records
+// appended at once are encrypted together and written as ENC$len$ followed
+// by len bytes of the encrypted batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Record {
+    // Value stored as the value of a key, forgetting its previous versions.
+    Insert { key: String, value: String },
+    // Value stored as the value of a key, keeping up to max_versions
+    // previous values.
+    PushVersion { key: String, value: String, max_versions: usize },
+    // Expiry time of the pair stored just before. Written as milliseconds
+    // since the Unix epoch, so it survives restarts.
+    Expire { key: String, expires_at: Instant },
+    Remove { key: String },
+    Clear
+}
+
+impl Record {
+    fn encode(&self, compressed: bool) -> Vec<u8> {
+        match self {
+            Record::Insert { key, value } => [format!("SET${key}$").into_bytes(), encode_value(value, compressed)].concat(),
+            Record::PushVersion { key, value, max_versions } => {
+                [format!("PUSHVER${key}${max_versions}$").into_bytes(), encode_value(value, compressed)].concat()
+            },
+            Record::Expire { key, expires_at } => format!("EXPIRE${key}${}$", to_unix_millis(*expires_at)).into_bytes(),
+            Record::Remove { key } => format!("DEL${key}$").into_bytes(),
+            Record::Clear => b"CLEAR$".to_vec()
+        }
+    }
+}
+
+// File the log is appended to. Implemented by files on the disk and, in
+// tests, by files failing on demand.
+trait LogFile: Write + Send {
+    fn sync_data(&self) -> io::Result<()>;
+    fn set_len(&self, len: u64) -> io::Result<()>;
+}
+
+impl LogFile for File {
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+}
+
+// Append-only log of all changes of the database. Every change is written
+// to the disk before the client is answered, so after a crash the database
+// is reconstructed by applying the logged changes in order.
+pub(crate) struct Wal {
+    path: PathBuf,
+    file: Box<dyn LogFile>,
+    len: u64,
+    // Set if a failed write could not be removed from the end of the log.
+    // Records appended after it could not be read, so nothing is appended.
+    broken: bool,
+    // Length of the log right after the last compaction or opening.
+    compacted_len: u64,
+    // Whether values are written compressed. Logs may contain both kinds
+    // of values, so the setting can be changed between restarts.
+    compressed: bool,
+    // Cipher encrypting appended records. Like compression, encryption can
+    // be enabled between restarts, as unencrypted records are still read.
+    cipher: Option<Cipher>
+}
+
+impl Wal {
+    // Opens the log at path, creating it if it does not exist, and returns
+    // it together with the records it contains. A record cut off by a crash
+    // in the middle of writing it is dropped. If the log contains a malformed
+    // record, has an unknown version or has encrypted records that cipher
+    // cannot decrypt, opening fails and the log is left untouched.
+    pub(crate) fn open(path: &Path, compressed: bool, cipher: Option<Cipher>) -> io::Result<(Self, Vec<Record>)> {
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let (records, len) = match split_header(&contents)? {
+            Some((VERSION, log)) => {
+                let (records, len) = decode(log, cipher.as_ref())?;
+                let len = contents.len() - log.len() + len;
+                file.set_len(len as u64)?;
+                (records, len)
+            },
+            Some((0, log)) => {
+                let (records, len) = decode(log, cipher.as_ref())?;
+                let upgraded = [header(), log[..len].to_vec()].concat();
+                replace(path, &upgraded)?;
+                file = OpenOptions::new().append(true).open(path)?;
+                (records, upgraded.len())
+            },
+            Some((version, _)) => {
+                let message = format!("log has format version {version}, but only versions up to {VERSION} are supported");
+                return Err(io::Error::new(ErrorKind::InvalidData, message));
+            },
+            // A new log, or one whose header was cut off by a crash.
+            None => {
+                replace(path, &header())?;
+                file = OpenOptions::new().append(true).open(path)?;
+                (Vec::new(), header().len())
+            }
+        };
+        let wal = Wal {
+            path: path.to_path_buf(),
+            file: Box::new(file),
+            len: len as u64,
+            broken: false,
+            compacted_len: len as u64,
+            compressed,
+            cipher
+        };
+        Ok((wal, records))
+    }
+
+    // Writes the records at the end of the log and flushes them to the disk.
+    // If it fails, e.g. because the disk is full, the part of the records
+    // that was written is removed, so the records can be appended again.
+    pub(crate) fn append(&mut self, records: &[Record]) -> io::Result<()> {
+        if self.broken {
+            return Err(io::Error::other("log is broken by a failed write"));
+        }
+        let contents = self.encode(records)?;
+        if let Err(error) = self.file.write_all(&contents).and_then(|()| self.file.sync_data()) {
+            self.broken = self.file.set_len(self.len).is_err();
+            return Err(error);
+        }
+        self.len += contents.len() as u64;
+        Ok(())
+    }
+
+    // Length of the log on the disk in bytes.
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn should_compact(&self) -> bool {
+        self.len > COMPACTION_MIN_BYTES.max(2 * self.compacted_len)
+    }
+
+    // Replaces the log with records describing the current state of the
+    // database.
+    pub(crate) fn compact(&mut self, records: &[Record]) -> io::Result<()> {
+        let contents = [header(), self.encode(records)?].concat();
+        replace(&self.path, &contents)?;
+
+        self.file = Box::new(OpenOptions::new().append(true).open(&self.path)?);
+        self.len = contents.len() as u64;
+        // The failed write was in the replaced log.
+        self.broken = false;
+        self.compacted_len = self.len;
+        Ok(())
+    }
+
+    fn encode(&self, records: &[Record]) -> io::Result<Vec<u8>> {
+        let encoded: Vec<u8> = records.iter().flat_map(|record| record.encode(self.compressed)).collect();
+        match &self.cipher {
+            Some(cipher) if !encoded.is_empty() => encrypt_batch(cipher, &encoded),
+            _ => Ok(encoded)
+        }
+    }
+}
+
+// Replaces the file at path with contents. The new file is written under
+// a temporary name and then renamed, so a crash in the meantime leaves
+// the old file intact. The directory is flushed after renaming, otherwise
+// after a power loss it could still point to the old file.
+fn replace(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // The suffix is appended, not swapped with the extension, so it
+    // differs from the path of the log even if it ends with .tmp.
+    let mut temp_path = path.to_path_buf().into_os_string();
+    temp_path.push(".tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()
+}
+
+fn header() -> Vec<u8> {
+    format!("{MAGIC}{VERSION}$").into_bytes()
+}
+
+// Splits the header from log and returns the version of the log and its
+// records. Returns None if log has no complete header and no records.
+fn split_header(log: &[u8]) -> io::Result<Option<(u32, &[u8])>> {
+    let Some(rest) = log.strip_prefix(MAGIC.as_bytes()) else {
+        // No record starts with a prefix of the magic.
+        return Ok((!MAGIC.as_bytes().starts_with(log)).then_some((0, log)));
+    };
+    split_number(rest)
+}
+
+// Encodes the value as len$ followed by len bytes: the header byte and
+// the value, compressed with gzip if it is requested and makes it shorter.
+// Without the compression feature, values are never compressed.
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+fn encode_value(value: &str, compressed: bool) -> Vec<u8> {
+    let encoded = [&[RAW], value.as_bytes()].concat();
+    #[cfg(feature = "compression")]
+    let encoded = match compressed.then(|| [&[GZIP], gzip(value).as_slice()].concat()) {
+        Some(gzipped) if gzipped.len() < encoded.len() => gzipped,
+        _ => encoded
+    };
+    [format!("{}$", encoded.len()).into_bytes(), encoded].concat()
+}
+
+// Returns all complete records at the beginning of contents and their
+// total length in bytes. Decoding stops at the first incomplete record,
+// which can only be the last one, cut off by a crash. Fails if a complete
+// record is malformed, so that records after it are not dropped.
+fn decode(contents: &[u8], cipher: Option<&Cipher>) -> io::Result<(Vec<Record>, usize)> {
+    let mut records = Vec::new();
+    let mut rest = contents;
+    loop {
+        if let Some(batch) = rest.strip_prefix(ENCRYPTED_BATCH) {
+            let Some((batch, batch_rest)) = split_value(batch)? else {
+                break;
+            };
+            records.extend(decrypt_batch(cipher, batch)?);
+            rest = batch_rest;
+        } else if let Some((record, record_rest)) = decode_record(rest)? {
+            records.push(record);
+            rest = record_rest;
+        } else {
+            break;
+        }
+    }
+    Ok((records, contents.len() - rest.len()))
+}
+
+#[cfg(feature = "encryption")]
+fn encrypt_batch(cipher: &Cipher, encoded: &[u8]) -> io::Result<Vec<u8>> {
+    let encrypted = cipher.encrypt(encoded)?;
+    Ok([ENCRYPTED_BATCH, format!("{}$", encrypted.len()).as_bytes(), &encrypted].concat())
+}
+
+#[cfg(not(feature = "encryption"))]
+fn encrypt_batch(cipher: &Cipher, _: &[u8]) -> io::Result<Vec<u8>> {
+    match *cipher {}
+}
+
+// A batch is written at once, so only the whole batch may be cut off.
+#[cfg(feature = "encryption")]
+fn decrypt_batch(cipher: Option<&Cipher>, batch: &[u8]) -> io::Result<Vec<Record>> {
+    let Some(cipher) = cipher else {
+        return Err(io::Error::new(ErrorKind::InvalidData, "log is encrypted, but no key was given"));
+    };
+    let encoded = cipher.decrypt(batch)?;
+    match decode(&encoded, None)? {
+        (records, len) if len == encoded.len() => Ok(records),
+        _ => Err(invalid_data("encrypted batch with incomplete record"))
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt_batch(_: Option<&Cipher>, _: &[u8]) -> io::Result<Vec<Record>> {
+    Err(io::Error::new(ErrorKind::InvalidData, "log is encrypted, but the encryption feature is disabled"))
+}
+
+// Splits the first record from log. Returns None if it is not complete
+// and InvalidData if it is complete, but cannot be decoded.
+fn decode_record(log: &[u8]) -> io::Result<Option<(Record, &[u8])>> {
+    let Some((name, rest)) = split_field(log)? else {
+        return Ok(None);
+    };
+    if name == "CLEAR" {
+        return Ok(Some((Record::Clear, rest)));
+    }
+    if !matches!(name, "SET" | "PUSHVER" | "EXPIRE" | "DEL") {
+        return Err(invalid_data("record with unknown name"));
+    }
+    let Some((key, rest)) = split_field(rest)? else {
+        return Ok(None);
+    };
+    let key = key.to_string();
+
+    match name {
+        "SET" => {
+            let Some((value, rest)) = split_value(rest)? else {
+                return Ok(None);
+            };
+            Ok(Some((Record::Insert { key, value: decode_value(value)? }, rest)))
+        },
+        "PUSHVER" => {
+            let Some((max_versions, rest)) = split_number(rest)? else {
+                return Ok(None);
+            };
+            let Some((value, rest)) = split_value(rest)? else {
+                return Ok(None);
+            };
+            Ok(Some((Record::PushVersion { key, value: decode_value(value)?, max_versions }, rest)))
+        },
+        "EXPIRE" => {
+            let Some((millis, rest)) = split_number(rest)? else {
+                return Ok(None);
+            };
+            Ok(Some((Record::Expire { key, expires_at: from_unix_millis(millis) }, rest)))
+        },
+        _ => Ok(Some((Record::Remove { key }, rest)))
+    }
+}
+
+// Splits a field ended with '$'. Fields other than values are ASCII.
+fn split_field(log: &[u8]) -> io::Result<Option<(&str, &[u8])>> {
+    let Some(end) = log.iter().position(|byte| *byte == b'$') else {
+        return Ok(None);
+    };
+    let field = std::str::from_utf8(&log[..end]).map_err(|_| invalid_data("field that is not UTF-8"))?;
+    Ok(Some((field, &log[end + 1..])))
+}
+
+// Splits a field that is a number.
+fn split_number<T: std::str::FromStr>(log: &[u8]) -> io::Result<Option<(T, &[u8])>> {
+    let Some((number, rest)) = split_field(log)? else {
+        return Ok(None);
+    };
+    let number = number.parse().map_err(|_| invalid_data("field that is not a number"))?;
+    Ok(Some((number, rest)))
+}
+
+// Splits len$value, where value has len bytes, into value and the rest.
+fn split_value(log: &[u8]) -> io::Result<Option<(&[u8], &[u8])>> {
+    let Some((len, rest)) = split_number::<usize>(log)? else {
+        return Ok(None);
+    };
+    Ok(rest.get(..len).map(|value| (value, &rest[len..])))
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("malformed log: {reason}"))
+}
+
+fn decode_value(encoded: &[u8]) -> io::Result<String> {
+    let value = match encoded.split_first() {
+        Some((&RAW, value)) => value.to_vec(),
+        #[cfg(feature = "compression")]
+        Some((&GZIP, value)) => gunzip(value)?,
+        _ => return Err(invalid_data("value with unsupported encoding"))
+    };
+    String::from_utf8(value).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+}
+
+#[cfg(feature = "compression")]
+fn gzip(value: &str) -> Vec<u8> {
+    use flate2::{write::GzEncoder, Compression};
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing to a vector never fails.
+    encoder.write_all(value.as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[cfg(feature = "compression")]
+fn gunzip(gzipped: &[u8]) -> io::Result<Vec<u8>> {
+    let mut value = Vec::new();
+    flate2::read::GzDecoder::new(gzipped).read_to_end(&mut value)?;
+    Ok(value)
+}
+
+fn to_unix_millis(instant: Instant) -> u64 {
+    let remaining = instant.saturating_duration_since(Instant::now());
+    let millis = (SystemTime::now() + remaining).duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis());
+    u64::try_from(millis).unwrap_or(u64::MAX)
+}
+
+// Times that have already passed are mapped to now.
+fn from_unix_millis(millis: u64) -> Instant {
+    let time = UNIX_EPOCH + Duration::from_millis(millis);
+    Instant::now() + time.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("kvs-wal-{name}-{}.log", std::process::id()))
+    }
+
+    fn encode(records: &[Record], compressed: bool) -> Vec<u8> {
+        records.iter().flat_map(|record| record.encode(compressed)).collect()
+    }
+
+    #[test]
+    fn decode_stops_at_incomplete_record() {
+        let records = vec![
+            Record::Insert { key: "a".to_string(), value: "b$c".to_string() },
+            Record::PushVersion { key: "a".to_string(), value: "zażółć".to_string(), max_versions: 3 },
+            Record::Remove { key: "a".to_string() },
+            Record::Insert { key: String::new(), value: String::new() },
+            Record::Clear
+        ];
+        let log = encode(&records, false);
+        assert_eq!((records.clone(), log.len()), decode(&log, None).unwrap());
+
+        // Cut in the middle of the second record, including in the middle of a character.
+        let first_len = records[0].encode(false).len();
+        for len in first_len..first_len + records[1].encode(false).len() {
+            assert_eq!((records[..1].to_vec(), first_len), decode(&log[..len], None).unwrap());
+        }
+    }
+
+    #[test]
+    fn values_survive_encoding_with_and_without_compression() {
+        let value = r#"{"key":"value"}"#.repeat(100);
+        let records = vec![
+            Record::Insert { key: "a".to_string(), value: value.clone() },
+            Record::PushVersion { key: "a".to_string(), value: "short".to_string(), max_versions: 1 }
+        ];
+
+        for compressed in [false, true] {
+            let log = encode(&records, compressed);
+            assert_eq!((records.clone(), log.len()), decode(&log, None).unwrap());
+        }
+        // Logs written with and without compression can be mixed.
+        let log = [encode(&records, false), encode(&records, true)].concat();
+        assert_eq!(4, decode(&log, None).unwrap().0.len());
+
+        // Long values are compressed only with the compression feature.
+        assert_eq!(cfg!(feature = "compression"), records[0].encode(true).len() < value.len());
+        // Compression would make short values longer.
+        assert_eq!(records[1].encode(false), records[1].encode(true));
+    }
+
+    #[test]
+    fn decode_rejects_values_with_unknown_encoding() {
+        assert!(decode(b"SET$k$2$xv", None).is_err());
+        assert!(decode(b"SET$k$3$r\xff\xfe", None).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_malformed_records_followed_by_valid_ones() {
+        let valid = Record::Insert { key: "k".to_string(), value: "v".to_string() }.encode(false);
+        let malformed_records: Vec<&[u8]> = vec![
+            b"GARBAGE$k$", b"PUSHVER$k$x$2$rv", b"EXPIRE$k$-1$", b"SET$k$x$rv", b"SET$\xff$2$rv"
+        ];
+
+        for record in malformed_records {
+            assert!(decode(&[record, valid.as_slice()].concat(), None).is_err(), "{}", String::from_utf8_lossy(record));
+        }
+    }
+
+    #[test]
+    fn log_with_malformed_record_is_not_truncated() {
+        let path = temp_path("malformed");
+        let insert = Record::Insert { key: "k".to_string(), value: "v".to_string() };
+        let log = [b"GARBAGE$k$".as_slice(), &insert.encode(false)].concat();
+        fs::write(&path, &log).unwrap();
+
+        let error = Wal::open(&path, false, None).err().unwrap();
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert_eq!(log, contents);
+    }
+
+    #[test]
+    fn expiry_survives_encoding() {
+        let expires_at = Instant::now() + Duration::from_secs(60);
+        let log = Record::Expire { key: "k".to_string(), expires_at }.encode(false);
+
+        let (records, _) = decode(&log, None).unwrap();
+        let Record::Expire { expires_at: decoded, .. } = records[0] else {
+            panic!("unexpected record {:?}", records[0]);
+        };
+        // Milliseconds are the precision of the log.
+        let difference = decoded.max(expires_at) - decoded.min(expires_at);
+        assert!(difference < Duration::from_millis(10));
+    }
+
+    #[test]
+    fn reopened_log_contains_appended_and_compacted_records() {
+        let path = temp_path("reopen");
+        let _ = fs::remove_file(&path);
+        let insert = |value: &str| Record::Insert { key: "k".to_string(), value: value.to_string() };
+
+        let (mut wal, records) = Wal::open(&path, false, None).unwrap();
+        assert!(records.is_empty());
+        wal.append(&[insert("a"), insert("b")]).unwrap();
+        // A crash in the middle of writing a record.
+        wal.file.write_all(b"SET$k$3$rc").unwrap();
+
+        let (mut wal, records) = Wal::open(&path, true, None).unwrap();
+        assert_eq!(vec![insert("a"), insert("b")], records);
+        wal.compact(&[insert("b")]).unwrap();
+        wal.append(&[Record::Remove { key: "k".to_string() }]).unwrap();
+
+        let (_, records) = Wal::open(&path, false, None).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(vec![insert("b"), Record::Remove { key: "k".to_string() }], records);
+    }
+
+    // Log file that writes only a part of the bytes and then fails,
+    // like a file on a disk that got full.
+    struct FailingFile(File);
+
+    impl Write for FailingFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match buf.len() {
+                0 | 1 => Err(io::Error::other("disk full")),
+                len => self.0.write(&buf[..len / 2])
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl LogFile for FailingFile {
+        fn sync_data(&self) -> io::Result<()> {
+            self.0.sync_data()
+        }
+
+        fn set_len(&self, len: u64) -> io::Result<()> {
+            self.0.set_len(len)
+        }
+    }
+
+    #[test]
+    fn failed_append_leaves_no_part_of_records_in_log() {
+        let path = temp_path("failed");
+        let _ = fs::remove_file(&path);
+        let insert = |value: &str| Record::Insert { key: "k".to_string(), value: value.to_string() };
+        let open_file = || OpenOptions::new().append(true).open(&path).unwrap();
+
+        let (mut wal, _) = Wal::open(&path, false, None).unwrap();
+        wal.append(&[insert("a")]).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        wal.file = Box::new(FailingFile(open_file()));
+        assert!(wal.append(&[insert("b")]).is_err());
+        assert_eq!(len, fs::metadata(&path).unwrap().len());
+
+        // The records are appended again once writing works.
+        wal.file = Box::new(open_file());
+        wal.append(&[insert("b")]).unwrap();
+        let (_, records) = Wal::open(&path, false, None).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(vec![insert("a"), insert("b")], records);
+    }
+
+    #[test]
+    fn log_without_header_is_upgraded() {
+        let path = temp_path("upgrade");
+        let insert = Record::Insert { key: "k".to_string(), value: "v".to_string() };
+        // A log of version 0 with a record cut off by a crash.
+        fs::write(&path, [insert.encode(false).as_slice(), b"DEL$k"].concat()).unwrap();
+
+        let (mut wal, records) = Wal::open(&path, false, None).unwrap();
+        assert_eq!(vec![insert.clone()], records);
+        wal.append(&[Record::Clear]).unwrap();
+        let contents = fs::read(&path).unwrap();
+        assert_eq!([b"KVSWAL1$".as_slice(), &insert.encode(false), b"CLEAR$"].concat(), contents);
+
+        let (_, records) = Wal::open(&path, false, None).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(vec![insert, Record::Clear], records);
+    }
+
+    #[test]
+    fn log_with_cut_off_header_is_started_again() {
+        let path = temp_path("header");
+        fs::write(&path, b"KVSWA").unwrap();
+
+        let (mut wal, records) = Wal::open(&path, false, None).unwrap();
+        assert!(records.is_empty());
+        wal.append(&[Record::Clear]).unwrap();
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(b"KVSWAL1$CLEAR$".as_slice(), contents);
+    }
+
+    #[test]
+    fn log_with_unknown_version_is_not_opened() {
+        let path = temp_path("version");
+        let log = b"KVSWAL2$CLEAR$";
+        fs::write(&path, log).unwrap();
+
+        let error = Wal::open(&path, false, None).err().unwrap();
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        assert_eq!("log has format version 2, but only versions up to 1 are supported", error.to_string());
+        assert_eq!(log.as_slice(), contents);
+    }
+
+    #[test]
+    fn compaction_keeps_log_named_like_temporary_file() {
+        let path = temp_path("named").with_extension("tmp");
+        let _ = fs::remove_file(&path);
+        let insert = Record::Insert { key: "k".to_string(), value: "v".to_string() };
+
+        let (mut wal, _) = Wal::open(&path, false, None).unwrap();
+        wal.compact(std::slice::from_ref(&insert)).unwrap();
+        wal.append(std::slice::from_ref(&insert)).unwrap();
+
+        let (_, records) = Wal::open(&path, false, None).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(vec![insert.clone(), insert], records);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_log_is_read_only_with_its_key() {
+        use super::super::cipher::KEY_LEN;
+
+        let path = temp_path("encrypted");
+        let _ = fs::remove_file(&path);
+        let insert = |value: &str| Record::Insert { key: "secretkey".to_string(), value: value.to_string() };
+
+        // Records written before encryption was enabled are still read.
+        let (mut wal, _) = Wal::open(&path, false, None).unwrap();
+        wal.append(&[insert("plain")]).unwrap();
+        let (mut wal, _) = Wal::open(&path, false, Some(Cipher::new(&[1; KEY_LEN]))).unwrap();
+        wal.append(&[insert("secretvalue"), Record::Remove { key: "other".to_string() }]).unwrap();
+        let written = wal.encode(&[insert("cutoff")]).unwrap();
+        // A crash in the middle of writing a batch.
+        wal.file.write_all(&written[..written.len() - 1]).unwrap();
+
+        let contents = fs::read(&path).unwrap();
+        for plaintext in ["secretvalue", "other"] {
+            assert!(!contents.windows(plaintext.len()).any(|window| window == plaintext.as_bytes()), "{plaintext}");
+        }
+        for cipher in [None, Some(Cipher::new(&[2; KEY_LEN]))] {
+            let error = Wal::open(&path, false, cipher).err().unwrap();
+            assert_eq!(ErrorKind::InvalidData, error.kind());
+        }
+        assert_eq!(contents, fs::read(&path).unwrap());
+
+        let (mut wal, records) = Wal::open(&path, false, Some(Cipher::new(&[1; KEY_LEN]))).unwrap();
+        let remove = Record::Remove { key: "other".to_string() };
+        assert_eq!(vec![insert("plain"), insert("secretvalue"), remove], records);
+        wal.compact(&[insert("secretvalue")]).unwrap();
+        assert!(!fs::read(&path).unwrap().windows(11).any(|window| window == b"secretvalue"));
+
+        let (_, records) = Wal::open(&path, false, Some(Cipher::new(&[1; KEY_LEN]))).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(vec![insert("secretvalue")], records);
+    }
+}
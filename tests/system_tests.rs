@@ -1,16 +1,78 @@
 // author - Patryk Jędrzejczak
 
-// THESE TESTS REQUIRE RUNNING SERVER IN ANOTHER TERMINAL!
+// Tests of the server as clients see it: requests sent over connections,
+// and the public API used by main. Tests that need private items of the
+// crate are unit tests in the modules that define them.
+//
+// Every test starts its own server in the test process, either listening
+// on a free port (start_server) or handling a single connection over an
+// in-memory pipe (connect_in_memory), so no server has to be run beforehand.
 
-use tokio::net::TcpStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use key_value_store::{
+    Client, ClientError, Config, Database, Db, handle_stream, reap_idle_connections, serve, serve_http, serve_metrics,
+    serve_resp
+};
+use std::net::SocketAddr;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::time::{self, Duration};
 
 const BUF_LEN: usize = 1024;
 const DONE_LEN: usize = 5;
 const NOTFOUND_LEN: usize = 9;
 const MIN_FOUND_LEN: usize = 7;
+const TIMEOUT_LEN: usize = 8;
+
+// Starts a server with a fresh database in the background, listening on
+// a free port, and returns its address. Every server has its own database,
+// so tests running in parallel do not see each other's keys.
+async fn start_server(config: Config) -> SocketAddr {
+    start_server_with_db(config, Arc::new(Database::new())).await
+}
+
+// Starts a server with the given database in the background,
+// reaping idle connections if the configuration says so.
+// Returns its address.
+async fn start_server_with_db(config: Config, db: Db) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    if let Some(idle_timeout) = config.idle_timeout {
+        tokio::spawn(reap_idle_connections(db.clone(), idle_timeout));
+    }
+    tokio::spawn(serve(listener, db, Arc::new(config), std::future::pending()));
+    address
+}
+
+// Sends request and reads a response of the expected length.
+async fn exchange(socket: &mut (impl AsyncRead + AsyncWrite + Unpin), request: &str, response_len: usize) -> String {
+    let mut buf = vec![0; response_len];
+    socket.write_all(request.as_bytes()).await.unwrap();
+    socket.read_exact(&mut buf).await.unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+// Sends requests one by one and checks that each of them gets the
+// response paired with it.
+async fn exchange_all<R: AsRef<str>, E: AsRef<str>>(
+    socket: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    expected_responses: impl IntoIterator<Item = (R, E)>
+) {
+    for (request, response) in expected_responses {
+        let (request, response) = (request.as_ref(), response.as_ref());
+        assert_eq!(response, exchange(socket, request, response.len()).await, "{request}");
+    }
+}
+
+// Connects to a fresh server running in the test itself, which handles
+// the connection over an in-memory pipe instead of a socket.
+fn connect_in_memory() -> DuplexStream {
+    let (client, server) = tokio::io::duplex(BUF_LEN);
+    tokio::spawn(handle_stream(server, Arc::new(Database::new()), Arc::new(Config::default())));
+    client
+}
 
-#[ignore]
 #[tokio::test]
 #[ntest::timeout(1000)]
 async fn correct_store_request_works() {
@@ -20,18 +82,17 @@ async fn correct_store_request_works() {
         "STORE$key$qwertyuiopasdfghjklzxcvbnm$","STORE$$$S"
     ];
 
-    let mut socket = TcpStream::connect("127.0.0.1:5555").await.unwrap();
+    let mut socket = connect_in_memory();
 
     let mut buf = vec![0; DONE_LEN];
 
     for request in correct_store_requests {
-        socket.write(request.as_bytes()).await.unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
         let read_num = socket.read_exact(&mut buf).await.unwrap();
         assert_eq!("DONE$".as_bytes(), &buf[0..read_num]);
     }
 }
 
-#[ignore]
 #[tokio::test]
 #[ntest::timeout(1000)]
 async fn correct_store_request_sent_partially_works() {
@@ -39,11 +100,10 @@ async fn correct_store_request_sent_partially_works() {
         "STO", "RE$k", "ey$v", "alu", "e$"
     ];
 
-    let mut socket = TcpStream::connect("127.0.0.1:5555").await.unwrap();
-    socket.set_nodelay(true).unwrap();
+    let mut socket = connect_in_memory();
 
     for request in store_request_fragments {
-        socket.write(request.as_bytes()).await.unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
     }
 
     let mut buf = vec![0; DONE_LEN];
@@ -52,7 +112,6 @@ async fn correct_store_request_sent_partially_works() {
     assert_eq!("DONE$".as_bytes(), &buf[0..read_num]);
 }
 
-#[ignore]
 #[tokio::test]
 #[ntest::timeout(1000)]
 async fn correct_load_request_works() {
@@ -61,12 +120,13 @@ async fn correct_load_request_works() {
         "LOAD$a$L"
     ];
 
-    let mut socket = TcpStream::connect("127.0.0.1:5555").await.unwrap();
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
 
     let mut buf = vec![0; BUF_LEN];
 
     for request in correct_load_requests {
-        socket.write(request.as_bytes()).await.unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
         let read_num = socket.read(&mut buf).await.unwrap();
         // This might fail, if server send answer in more than one package.
         // We hope it does not happen.
@@ -75,7 +135,6 @@ async fn correct_load_request_works() {
     }
 }
 
-#[ignore]
 #[tokio::test]
 #[ntest::timeout(1000)]
 async fn correct_load_request_sent_partially_works() {
@@ -83,11 +142,12 @@ async fn correct_load_request_sent_partially_works() {
         "LOA", "D$spl", "itk", "ey$"
     ];
 
-    let mut socket = TcpStream::connect("127.0.0.1:5555").await.unwrap();
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
     socket.set_nodelay(true).unwrap();
 
     for request in load_request_fragments {
-        socket.write(request.as_bytes()).await.unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
     }
 
     let mut buf = vec![0; NOTFOUND_LEN];
@@ -96,51 +156,50 @@ async fn correct_load_request_sent_partially_works() {
     assert_eq!("NOTFOUND$".as_bytes(), &buf[0..read_num]);
 }
 
-#[ignore]
 #[tokio::test]
 #[ntest::timeout(1000)]
 async fn store_request_overrides_value() {
-    let mut socket = TcpStream::connect("127.0.0.1:5555").await.unwrap();
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
 
     let mut buf1 = vec![0; DONE_LEN];
     let mut buf2 = vec![0; MIN_FOUND_LEN + 1];
     let mut read_num;
 
-    socket.write("STORE$override$a$".as_bytes()).await.unwrap();
+    socket.write_all("STORE$override$a$".as_bytes()).await.unwrap();
     read_num = socket.read(&mut buf1).await.unwrap();
     assert_eq!("DONE$".as_bytes(), &buf1[0..read_num]);
 
-    socket.write("LOAD$override$".as_bytes()).await.unwrap();
+    socket.write_all("LOAD$override$".as_bytes()).await.unwrap();
     read_num = socket.read(&mut buf2).await.unwrap();
     assert_eq!("FOUND$a$".as_bytes(), &buf2[0..read_num]);
 
-    socket.write("STORE$override$b$".as_bytes()).await.unwrap();
+    socket.write_all("STORE$override$b$".as_bytes()).await.unwrap();
     read_num = socket.read(&mut buf1).await.unwrap();
     assert_eq!("DONE$".as_bytes(), &buf1[0..read_num]);
 
-    socket.write("LOAD$override$".as_bytes()).await.unwrap();
+    socket.write_all("LOAD$override$".as_bytes()).await.unwrap();
     read_num = socket.read(&mut buf2).await.unwrap();
     assert_eq!("FOUND$b$".as_bytes(), &buf2[0..read_num]);
 }
 
-#[ignore]
 #[tokio::test]
 #[ntest::timeout(1000)]
 async fn messages_containing_many_requests_work() {
-    let mut socket = TcpStream::connect("127.0.0.1:5555").await.unwrap();
+    let mut socket = connect_in_memory();
 
     let mut buf1 = vec![0; DONE_LEN];
     let mut buf2 = vec![0; MIN_FOUND_LEN + 3];
     let mut read_num;
 
-    socket.write("STORE$mra$mrb$STORE$mrc$mrd$STORE$mre$mrf$".as_bytes()).await.unwrap();
+    socket.write_all("STORE$mra$mrb$STORE$mrc$mrd$STORE$mre$mrf$".as_bytes()).await.unwrap();
     for _ in 0..3 {
         read_num = socket.read_exact(&mut buf1).await.unwrap();
         assert_eq!("DONE$".as_bytes(), &buf1[0..read_num]);
     }
 
     
-    socket.write("LOAD$mra$LOAD$mrc$LOAD$mre$".as_bytes()).await.unwrap();
+    socket.write_all("LOAD$mra$LOAD$mrc$LOAD$mre$".as_bytes()).await.unwrap();
     read_num = socket.read_exact(&mut buf2).await.unwrap();
     assert_eq!("FOUND$mrb$".as_bytes(), &buf2[0..read_num]);
     read_num = socket.read_exact(&mut buf2).await.unwrap();
@@ -149,41 +208,1523 @@ async fn messages_containing_many_requests_work() {
     assert_eq!("FOUND$mrf$".as_bytes(), &buf2[0..read_num]);
 }
 
-#[ignore]
 #[tokio::test]
 #[ntest::timeout(1000)]
 async fn messages_containing_many_mixed_requests_work() {
-    let mut socket = TcpStream::connect("127.0.0.1:5555").await.unwrap();
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
 
     let mut buf1 = vec![0; DONE_LEN];
     let mut buf2 = vec![0; MIN_FOUND_LEN + 2];
     let mut read_num;
 
-    socket.write("STORE$qa$qb$LOAD$qa$STORE".as_bytes()).await.unwrap();
+    socket.write_all("STORE$qa$qb$LOAD$qa$STORE".as_bytes()).await.unwrap();
     read_num = socket.read(&mut buf1).await.unwrap();
     assert_eq!("DONE$".as_bytes(), &buf1[0..read_num]);
     read_num = socket.read(&mut buf2).await.unwrap();
     assert_eq!("FOUND$qb$".as_bytes(), &buf2[0..read_num]);
 
-    socket.write("$qc$qd$LOAD$qc$".as_bytes()).await.unwrap();
+    socket.write_all("$qc$qd$LOAD$qc$".as_bytes()).await.unwrap();
     read_num = socket.read(&mut buf1).await.unwrap();
     assert_eq!("DONE$".as_bytes(), &buf1[0..read_num]);
     read_num = socket.read(&mut buf2).await.unwrap();
     assert_eq!("FOUND$qd$".as_bytes(), &buf2[0..read_num]);
 }
 
-#[ignore]
 #[tokio::test]
 #[ntest::timeout(1000)]
 async fn sending_incorrect_message_closes_connection() {
-    let mut socket = TcpStream::connect("127.0.0.1:5555").await.unwrap();
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    socket.write_all("STORE$key$1$".as_bytes()).await.unwrap();
+
+    while socket.write_all("LOAD$key$".as_bytes()).await.is_ok() {}
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn wait_request_returns_immediately_when_key_exists() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$wa$wb$", "DONE$"),
+        ("WAIT$wa$100000$", "FOUND$wb$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn wait_request_times_out_when_key_does_not_appear() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let mut buf = vec![0; TIMEOUT_LEN];
+
+    socket.write_all("WAIT$wnever$100$".as_bytes()).await.unwrap();
+    let read_num = socket.read_exact(&mut buf).await.unwrap();
+    assert_eq!("TIMEOUT$".as_bytes(), &buf[0..read_num]);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn wait_request_returns_value_stored_by_another_connection() {
+    let address = start_server(Config::default()).await;
+    let mut waiting_socket = TcpStream::connect(address).await.unwrap();
+    let mut storing_socket = TcpStream::connect(address).await.unwrap();
+
+    let mut buf1 = vec![0; DONE_LEN];
+    let mut buf2 = vec![0; MIN_FOUND_LEN + 2];
+    let mut read_num;
+
+    waiting_socket.write_all("WAIT$wc$100000$".as_bytes()).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    storing_socket.write_all("STORE$wc$wd$".as_bytes()).await.unwrap();
+    read_num = storing_socket.read_exact(&mut buf1).await.unwrap();
+    assert_eq!("DONE$".as_bytes(), &buf1[0..read_num]);
+
+    read_num = waiting_socket.read_exact(&mut buf2).await.unwrap();
+    assert_eq!("FOUND$wd$".as_bytes(), &buf2[0..read_num]);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn responses_are_withheld_until_flush() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    socket.set_nodelay(true).unwrap();
+
+    let mut buf = vec![0; BUF_LEN];
+
+    socket.write_all("NOFLUSH$STORE$fa$fb$LOAD$fa$LOAD$fnone$".as_bytes()).await.unwrap();
+    let withheld = tokio::time::timeout(
+        std::time::Duration::from_millis(200), socket.read(&mut buf)
+    ).await;
+    assert!(withheld.is_err());
+
+    socket.write_all("FLUSH$".as_bytes()).await.unwrap();
+    let expected = "DONE$FOUND$fb$NOTFOUND$".as_bytes();
+    let read_num = socket.read_exact(&mut buf[0..expected.len()]).await.unwrap();
+    assert_eq!(expected, &buf[0..read_num]);
+
+    // After FLUSH responses are written immediately again.
+    socket.write_all("LOAD$fa$".as_bytes()).await.unwrap();
+    let read_num = socket.read(&mut buf).await.unwrap();
+    assert_eq!("FOUND$fb$".as_bytes(), &buf[0..read_num]);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn init_request_stores_value_of_absent_key() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("INIT$ia$ib$", "DONE$"),
+        ("LOAD$ia$", "FOUND$ib$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+// The default configuration has an empty sentinel.
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn init_request_overrides_sentinel_value() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$ic$$INIT$ic$id$", "DONE$DONE$"),
+        ("LOAD$ic$", "FOUND$id$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn init_request_does_not_change_initialized_key() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$ie$if$", "DONE$"),
+        ("INIT$ie$ig$", "EXISTS$"),
+        ("LOAD$ie$", "FOUND$if$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn copy_request_copies_value_and_keeps_source() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$ca$cb$STORE$cc$cd$COPY$ca$cc$", "DONE$DONE$DONE$"),
+        ("LOAD$cc$LOAD$ca$", "FOUND$cb$FOUND$cb$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn copy_request_with_missing_source_returns_not_found() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("COPY$cnone$ce$LOAD$ce$", "NOTFOUND$NOTFOUND$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn json_request_switches_responses_to_json() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("JSON$", r#"{"status":"DONE"}"#),
+        ("STORE$ja$jb$", r#"{"status":"DONE"}"#),
+        ("LOAD$ja$", r#"{"status":"FOUND","value":"jb"}"#),
+        ("LOAD$jnone$", r#"{"status":"NOTFOUND"}"#)
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn prefix_request_prepends_prefix_to_keys() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("PREFIX$pa$STORE$pkey$pv$", "DONE$DONE$"),
+        ("LOAD$pkey$", "FOUND$pv$"),
+        ("PREFIX$$", "DONE$"),
+        ("LOAD$papkey$", "FOUND$pv$"),
+        ("LOAD$pkey$", "NOTFOUND$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn usage_request_returns_memory_usage_and_number_of_keys() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let mut buf = vec![0; BUF_LEN];
+
+    socket.write_all("USAGE$".as_bytes()).await.unwrap();
+    let read_num = socket.read(&mut buf).await.unwrap();
+    let response = std::str::from_utf8(&buf[0..read_num]).unwrap();
+
+    let fields: Vec<&str> = response.split('$').collect();
+    assert_eq!(5, fields.len());
+    assert_eq!("USAGE", fields[0]);
+    assert!(fields[1].strip_prefix("disk=").unwrap().parse::<u64>().is_ok());
+    assert!(fields[2].strip_prefix("cache=").unwrap().parse::<usize>().is_ok());
+    assert!(fields[3].strip_prefix("keys=").unwrap().parse::<usize>().is_ok());
+    assert_eq!("", fields[4]);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn usage_request_counts_pairs_of_namespace_and_write_ahead_log() {
+    let path = std::env::temp_dir().join(format!("kvs-wal-usage-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut db = Database::new();
+    db.open_wal(&path, false).unwrap();
+    let address = start_server_with_db(Config::default(), Arc::new(db)).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let requests = "STORE$a$xyz$STORE$bb$v$STOREX$c$expired$0$SELECT$ns$STORE$a$other$";
+    assert_eq!("DONE$".repeat(5), exchange(&mut socket, requests, 25).await);
+    // Expired pairs and pairs of other namespaces are not counted.
+    let disk = std::fs::metadata(&path).unwrap().len();
+    let expected = format!("USAGE$disk={disk}$cache=6$keys=1$");
+    assert_eq!(expected, exchange(&mut socket, "USAGE$", expected.len()).await);
+    let expected = format!("USAGE$disk={disk}$cache=7$keys=2$");
+    assert_eq!(expected, exchange(&mut socket, "SELECT$default$USAGE$", expected.len() + 5).await[5..]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn write_failure_in_the_middle_of_batch_closes_only_that_connection() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let mut buf1 = vec![0; DONE_LEN];
+    let mut buf2 = vec![0; MIN_FOUND_LEN + 2];
+    let mut read_num;
+
+    let big_value = "w".repeat(20_000);
+    socket.write_all(format!("STORE$wfbig${big_value}$").as_bytes()).await.unwrap();
+    read_num = socket.read_exact(&mut buf1).await.unwrap();
+    assert_eq!("DONE$".as_bytes(), &buf1[0..read_num]);
+
+    // Closing the socket without reading responses makes server's writes fail.
+    socket.write_all("LOAD$wfbig$".repeat(100).as_bytes()).await.unwrap();
+    drop(socket);
+
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    socket.write_all("STORE$wfa$wb$LOAD$wfa$".as_bytes()).await.unwrap();
+    read_num = socket.read_exact(&mut buf1).await.unwrap();
+    assert_eq!("DONE$".as_bytes(), &buf1[0..read_num]);
+    read_num = socket.read_exact(&mut buf2).await.unwrap();
+    assert_eq!("FOUND$wb$".as_bytes(), &buf2[0..read_num]);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn limits_request_returns_configured_limits() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("LIMITS$", "LIMITS$maxkey=none$maxval=none$maxtotal=none$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn mdel_request_counts_only_removed_keys() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$mda$a$STORE$mdb$b$", "DONE$DONE$"),
+        ("MDEL$mda$mdnone$mdb$$", "DELETED$2$"),
+        ("LOAD$mda$", "NOTFOUND$"),
+        ("MDEL$mda$mdb$$", "DELETED$0$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn mexists_request_reports_keys_in_request_order() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$mea$a$STORE$mec$c$", "DONE$DONE$"),
+        ("MEXISTS$mea$menone$mec$menone$$", "EXISTS$1010$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn mget_request_returns_values_in_request_order() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$mga$a$STORE$mgc$c$", "DONE$DONE$"),
+        ("MGET$mga$mgnone$mgc$$", "VALUES$3$FOUND$a$NOTFOUND$FOUND$c$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn getorset_request_returns_existing_value_or_stores_default() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$gsa$old$", "DONE$"),
+        ("GETORSET$gsa$new$", "FOUND$old$"),
+        ("LOAD$gsa$", "FOUND$old$"),
+        ("GETORSET$gsb$new$", "FOUND$new$"),
+        ("LOAD$gsb$", "FOUND$new$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn keys_may_contain_uppercase_letters_digits_and_underscores() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$User_42$value$", "DONE$"),
+        ("LOAD$User_42$", "FOUND$value$"),
+        ("LOAD$user_42$", "NOTFOUND$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn storeb_request_stores_values_with_any_ascii_characters() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STOREB$sba$5$$a$b$", "DONE$"),
+        ("LOAD$sba$", "FOUND$$a$b$$"),
+        ("STOREB$sbb$10${\"n\": 42}\n", "DONE$"),
+        ("LOAD$sbb$", "FOUND${\"n\": 42}\n$"),
+        // The value may come in many parts, like every other request.
+        ("STOREB$sbc$4$1", ""),
+        ("2 ", ""),
+        ("$", "DONE$"),
+        ("LOAD$sbc$", "FOUND$12 $$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
 
-    socket.write("STORE$1$value$".as_bytes()).await.unwrap();
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn chunked_load_response_is_reassembled_into_value() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    let value = format!("{}zażółć", "ENDCHUNK$".repeat(100));
+    let request = format!("STOREB$k${}${value}CHUNKED$64$LOAD$k$LOAD$absent$", value.len());
+    socket.write_all(request.as_bytes()).await.unwrap();
 
+    // Chunks are parsed as they arrive, in reads of a few bytes.
+    let mut received = Vec::new();
+    let mut parsed = "DONE$DONE$".len();
+    let mut reassembled = Vec::new();
+    let mut chunks = 0;
+    let mut buf = [0; 7];
     loop {
-        match socket.write("LOAD$key$".as_bytes()).await {
-            Ok(_) => continue,
-            Err(_) => break
-        };
+        let unparsed = received.get(parsed..).unwrap_or_default();
+        if unparsed.starts_with(b"ENDCHUNK$") {
+            parsed += "ENDCHUNK$".len();
+            break;
+        }
+        let chunk = unparsed.strip_prefix(b"CHUNK$").and_then(|rest| {
+            let len_end = rest.iter().position(|&byte| byte == b'$')?;
+            let len: usize = std::str::from_utf8(&rest[..len_end]).unwrap().parse().unwrap();
+            Some((rest.get(len_end + 1..len_end + 1 + len)?, "CHUNK$".len() + len_end + 1 + len))
+        });
+        match chunk {
+            Some((chunk, chunk_len)) => {
+                assert!(chunk.len() <= 64);
+                reassembled.extend_from_slice(chunk);
+                parsed += chunk_len;
+                chunks += 1;
+            },
+            None => {
+                let len = socket.read(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..len]);
+            }
+        }
+    }
+
+    assert_eq!(b"DONE$DONE$", &received[..10]);
+    assert_eq!(value.as_bytes(), reassembled);
+    assert_eq!(value.len().div_ceil(64), chunks);
+    // A key that is absent is answered as without chunks.
+    let mut rest = received[parsed..].to_vec();
+    rest.resize("NOTFOUND$".len(), 0);
+    socket.read_exact(&mut rest[received.len() - parsed..]).await.unwrap();
+    assert_eq!(b"NOTFOUND$", rest.as_slice());
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn delete_request_removes_only_present_key() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$dela$v$", "DONE$"),
+        ("DELETE$dela$", "DELETED$"),
+        ("LOAD$dela$", "NOTFOUND$"),
+        ("DELETE$dela$", "NOTFOUND$"),
+        ("DELETE$delb$", "NOTFOUND$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn exists_request_reports_presence_without_value() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("EXISTS$exa$", "NO$"),
+        ("STORE$exa$value$", "DONE$"),
+        ("EXISTS$exa$", "YES$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn appendifnew_request_appends_only_new_items() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("APPENDIFNEW$aina$x$", "DONE$"),
+        ("APPENDIFNEW$aina$y$", "DONE$"),
+        ("APPENDIFNEW$aina$x$", "SKIPPED$"),
+        ("LOAD$aina$", "FOUND$x\ny$"),
+        ("STORE$ainb$xy$", "DONE$"),
+        ("APPENDIFNEW$ainb$x$", "DONE$"),
+        ("LOAD$ainb$", "FOUND$xy\nx$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn metrics_request_returns_json_with_all_counters() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let mut buf = vec![0; BUF_LEN];
+
+    socket.write_all("STORE$mta$a$LOAD$mta$LOAD$mtnone$".as_bytes()).await.unwrap();
+    let expected = "DONE$FOUND$a$NOTFOUND$";
+    socket.read_exact(&mut buf[0..expected.len()]).await.unwrap();
+    assert_eq!(expected.as_bytes(), &buf[0..expected.len()]);
+
+    socket.write_all("METRICS$".as_bytes()).await.unwrap();
+    // The response is a single JSON object without nested braces.
+    let mut response = Vec::new();
+    while response.last() != Some(&b'}') {
+        let read_num = socket.read(&mut buf).await.unwrap();
+        response.extend_from_slice(&buf[0..read_num]);
+    }
+
+    let metrics: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!("METRICS", metrics["status"]);
+    // The server is used only by this test, so all counters are known.
+    let counters = [("stores", 1), ("loads", 2), ("hits", 1), ("misses", 1), ("errors", 0), ("connections", 1), ("reapedconns", 0), ("truncated", 0)];
+    for (field, value) in counters {
+        assert_eq!(Some(value), metrics[field].as_u64(), "{field}");
+    }
+    assert!(metrics["uptime"].is_u64());
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn half_closed_connection_receives_all_responses() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let mut responses = String::new();
+
+    socket.write_all("STORE$hca$a$LOAD$hca$NOFLUSH$LOAD$hcnone$LOAD$hca$".as_bytes()).await.unwrap();
+    socket.shutdown().await.unwrap();
+
+    socket.read_to_string(&mut responses).await.unwrap();
+    assert_eq!("DONE$FOUND$a$NOTFOUND$FOUND$a$", responses);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn quit_request_closes_connection_after_withheld_responses() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let mut responses = String::new();
+
+    // The connection stays open for writing, so only the server can close it.
+    socket.write_all("STORE$qk$a$NOFLUSH$LOAD$qk$QUIT$STORE$qk$b$".as_bytes()).await.unwrap();
+    socket.read_to_string(&mut responses).await.unwrap();
+    assert_eq!("DONE$FOUND$a$BYE$", responses);
+
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    let mut buf = vec![0; MIN_FOUND_LEN + 1];
+    socket.write_all("LOAD$qk$".as_bytes()).await.unwrap();
+    socket.read_exact(&mut buf).await.unwrap();
+    assert_eq!("FOUND$a$".as_bytes(), buf);
+}
+
+// The default configuration keeps 5 previous values of a key.
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn getver_request_returns_values_pushed_with_pushver() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let mut expected_responses = vec![];
+    for value in ["a", "b", "c", "d", "e", "f", "g"] {
+        expected_responses.push((format!("PUSHVER$pvk${value}$"), "DONE$".to_string()));
+    }
+    for (index, value) in ["g", "f", "e", "d", "c", "b"].iter().enumerate() {
+        expected_responses.push((format!("GETVER$pvk${index}$"), format!("FOUND${value}$")));
+    }
+    // The oldest value is forgotten.
+    expected_responses.push(("GETVER$pvk$6$".to_string(), "NOTFOUND$".to_string()));
+    expected_responses.push(("LOAD$pvk$".to_string(), "FOUND$g$".to_string()));
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn psubscribe_request_pushes_only_matching_changes() {
+    let address = start_server(Config::default()).await;
+    let mut subscriber = TcpStream::connect(address).await.unwrap();
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let mut buf1 = vec![0; DONE_LEN];
+    let mut buf2 = vec![0; BUF_LEN];
+    let mut read_num;
+
+    subscriber.write_all("PSUBSCRIBE$psubx$".as_bytes()).await.unwrap();
+    read_num = subscriber.read_exact(&mut buf1).await.unwrap();
+    assert_eq!("DONE$".as_bytes(), &buf1[0..read_num]);
+
+    socket.write_all("STORE$psubxa$a$STORE$psuby$b$COPY$psubxa$psubxc$".as_bytes()).await.unwrap();
+    for _ in 0..3 {
+        read_num = socket.read_exact(&mut buf1).await.unwrap();
+        assert_eq!("DONE$".as_bytes(), &buf1[0..read_num]);
     }
+
+    let expected = "CHANGED$psubxa$a$CHANGED$psubxc$a$";
+    read_num = subscriber.read_exact(&mut buf2[0..expected.len()]).await.unwrap();
+    assert_eq!(expected.as_bytes(), &buf2[0..read_num]);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn maxset_and_minset_requests_keep_extremum() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("MAXSET$mxa$5$", "VALUE$5$"),
+        ("MAXSET$mxa$3$", "VALUE$5$"),
+        ("MAXSET$mxa$12$", "VALUE$12$"),
+        ("MINSET$mxa$20$", "VALUE$12$"),
+        ("MINSET$mxa$7$", "VALUE$7$"),
+        ("LOAD$mxa$", "FOUND$7$"),
+        ("STORE$mxb$abc$", "DONE$"),
+        ("MAXSET$mxb$1$", "ERROR$not_an_integer$"),
+        ("LOAD$mxb$", "FOUND$abc$"),
+        // Counters of INCR and DECR requests may be negative.
+        ("DECR$mxc$", "VALUE$-1$"),
+        ("MINSET$mxc$-5$", "VALUE$-5$"),
+        ("MAXSET$mxc$-7$", "VALUE$-5$"),
+        ("INCR$mxc$", "VALUE$-4$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn mcas_request_stores_value_only_when_all_conditions_hold() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$mca$x$", "DONE$"),
+        ("STORE$mcb$y$", "DONE$"),
+        ("MCAS$mca$x$mcb$z$THEN$mcc$v$", "MISMATCH$"),
+        ("LOAD$mcc$", "NOTFOUND$"),
+        ("MCAS$mca$x$mcnone$$THEN$mcc$v$", "MISMATCH$"),
+        ("MCAS$mca$x$mcb$y$THEN$mcc$v$", "DONE$"),
+        ("LOAD$mcc$", "FOUND$v$"),
+        ("STORE$MC_1$w$", "DONE$"),
+        ("MCAS$MC_1$w$THENX$$THEN$MC_2$v$", "MISMATCH$"),
+        ("MCAS$MC_1$w$THEN$MC_2$v$", "DONE$"),
+        ("LOAD$MC_2$", "FOUND$v$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn loadt_request_returns_transformed_value() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$lta$value$", "DONE$"),
+        ("LOADT$lta$upper$", "FOUND$VALUE$"),
+        ("LOADT$lta$lower$", "FOUND$value$"),
+        ("LOADT$lta$len$", "FOUND$5$"),
+        ("LOADT$lta$reverse$", "ERROR$badtransform$"),
+        ("LOADT$ltnone$len$", "NOTFOUND$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn incr_and_decr_requests_count_from_zero() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("INCR$ica$", "VALUE$1$"),
+        ("INCR$ica$", "VALUE$2$"),
+        ("DECR$icb$", "VALUE$-1$"),
+        ("LOAD$icb$", "FOUND$-1$"),
+        ("STORE$icc$abc$", "DONE$"),
+        ("INCR$icc$", "ERROR$not_an_integer$"),
+        ("STOREB$icd$19$9223372036854775807", "DONE$"),
+        ("INCR$icd$", "ERROR$overflow$"),
+        ("DECR$icd$", "VALUE$9223372036854775806$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn decrfloor_request_does_not_go_below_floor() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORE$dfa$abc$", "DONE$"),
+        ("DECRFLOOR$dfa$0$", "ERROR$not_an_integer$"),
+        ("INCR$dfb$", "VALUE$1$"),
+        ("INCR$dfb$", "VALUE$2$"),
+        // Decrements above the floor.
+        ("DECRFLOOR$dfb$0$", "VALUE$1$"),
+        ("DECRFLOOR$dfb$0$", "VALUE$0$"),
+        // Would go below the floor, so it is clamped at it.
+        ("DECRFLOOR$dfb$0$", "VALUE$0$clamped$"),
+        ("LOAD$dfb$", "FOUND$0$"),
+        ("DECRFLOOR$dfb$-1$", "VALUE$-1$"),
+        // A value already below the floor is not raised to it.
+        ("DECRFLOOR$dfb$5$", "VALUE$-1$clamped$"),
+        ("DECRFLOOR$dfc$-10$", "VALUE$-1$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn decrdel_request_deletes_counter_at_zero() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("INCR$dda$", "VALUE$1$"),
+        ("INCR$dda$", "VALUE$2$"),
+        // Above zero, the value is returned.
+        ("DECRDEL$dda$", "VALUE$1$"),
+        // At zero, the key is deleted.
+        ("DECRDEL$dda$", "DELETED$"),
+        ("LOAD$dda$", "NOTFOUND$"),
+        ("DECRDEL$dda$", "NOTFOUND$"),
+        ("STORE$ddb$abc$", "DONE$"),
+        ("DECRDEL$ddb$", "ERROR$not_an_integer$"),
+        ("DECR$ddc$", "VALUE$-1$"),
+        ("DECRDEL$ddc$", "DELETED$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn serve_returns_after_connections_finish_on_shutdown() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (shutdown_sender, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(serve(listener, Arc::new(Database::new()), Arc::new(Config::default()), async {
+        let _ = shutdown.await;
+    }));
+
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("DONE$", exchange(&mut socket, "NOFLUSH$STORE$a$b$FLUSH$", 5).await);
+    socket.write_all(b"NOFLUSH$LOAD$a$").await.unwrap();
+    let mut subscriber = TcpStream::connect(address).await.unwrap();
+    assert_eq!("DONE$", exchange(&mut subscriber, "PSUBSCRIBE$$", 5).await);
+
+    shutdown_sender.send(()).unwrap();
+    server.await.unwrap();
+
+    // Withheld responses are written before closing the connection.
+    let mut buf = Vec::new();
+    socket.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(b"FOUND$b$", buf.as_slice());
+    assert_eq!(0, subscriber.read_to_end(&mut buf).await.unwrap());
+    assert!(TcpStream::connect(address).await.is_err());
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn resp_clients_share_database_with_default_clients() {
+    let db = Arc::new(Database::new());
+    let address = start_server_with_db(Config::default(), db.clone()).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let resp_address = listener.local_addr().unwrap();
+    tokio::spawn(serve_resp(listener, db, Arc::new(Config::default()), std::future::pending()));
+
+    let mut resp_socket = TcpStream::connect(resp_address).await.unwrap();
+    assert_eq!("+OK\r\n", exchange(&mut resp_socket, "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\nb\r\n", 5).await);
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("DONE$FOUND$b$", exchange(&mut socket, "STORE$c$d$LOAD$a$", 13).await);
+
+    let responses = "$1\r\nd\r\n$-1\r\n:2\r\n+PONG\r\n";
+    let requests = "*2\r\n$3\r\nget\r\n$1\r\nc\r\n*2\r\n$3\r\nGET\r\n$1\r\nx\r\n\
+        *4\r\n$3\r\nDEL\r\n$1\r\na\r\n$1\r\nc\r\n$1\r\nx\r\n*1\r\n$4\r\nPING\r\n";
+    assert_eq!(responses, exchange(&mut resp_socket, requests, responses.len()).await);
+
+    // Unsupported commands close the connection.
+    let mut buf = Vec::new();
+    resp_socket.write_all(b"*2\r\n$4\r\nINCR\r\n$1\r\na\r\n").await.unwrap();
+    resp_socket.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(b"-ERR bad_request\r\n", buf.as_slice());
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn http_clients_share_database_with_default_clients() {
+    let db = Arc::new(Database::new());
+    let address = start_server_with_db(Config::default(), db.clone()).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_address = listener.local_addr().unwrap();
+    tokio::spawn(serve_http(listener, db, Arc::new(Config::default()), std::future::pending()));
+
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("DONE$", exchange(&mut socket, "STORE$a$b$", 5).await);
+
+    let mut http_socket = TcpStream::connect(http_address).await.unwrap();
+    let responses = "HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\nb\
+        HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n\
+        HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n\
+        HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+    let requests = "GET /kv/a HTTP/1.1\r\nHost: localhost\r\n\r\nGET /kv/c HTTP/1.1\r\n\r\n\
+        PUT /kv/c HTTP/1.1\r\nContent-Length: 5\r\n\r\nx$y\r\nPUT /kv/d HTTP/1.1\r\n\r\n";
+    assert_eq!(responses, exchange(&mut http_socket, requests, responses.len()).await);
+    assert_eq!("FOUND$x$y\r\n$", exchange(&mut socket, "LOAD$c$", 12).await);
+    assert_eq!("FOUND$$", exchange(&mut socket, "LOAD$d$", 7).await);
+
+    // Unsupported requests close the connection.
+    let mut buf = Vec::new();
+    http_socket.write_all(b"DELETE /kv/a HTTP/1.1\r\n\r\n").await.unwrap();
+    http_socket.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 11\r\nConnection: close\r\n\r\nbad_request", buf.as_slice());
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn client_stores_and_loads_values() {
+    let address = start_server(Config::default()).await;
+    let mut client = Client::connect(address).await.unwrap();
+
+    client.store("key", "value").await.unwrap();
+    client.store("Other_1", "Zażółć 1").await.unwrap();
+    assert_eq!(Some("value".to_string()), client.load("key").await.unwrap());
+    assert_eq!(Some("Zażółć 1".to_string()), client.load("Other_1").await.unwrap());
+    assert_eq!(None, client.load("absent").await.unwrap());
+
+    let address = start_server(Config { max_total_bytes: Some(4), ..Config::default() }).await;
+    let mut client = Client::connect(address).await.unwrap();
+    let result = client.store("key", "value").await;
+    assert!(matches!(result, Err(ClientError::UnexpectedResponse(response)) if response == "FULL$"));
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn ping_request_gets_pong_response() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("PONG$PONG$", exchange(&mut socket, "PING$PING$", 10).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn conns_request_counts_open_connections() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("CONNS$1$", exchange(&mut socket, "CONNS$", 8).await);
+
+    let mut others = Vec::new();
+    for _ in 0..3 {
+        let mut other = TcpStream::connect(address).await.unwrap();
+        // Answered request guarantees the server is handling the connection.
+        assert_eq!("NOTFOUND$", exchange(&mut other, "LOAD$k$", 9).await);
+        others.push(other);
+    }
+    assert_eq!("CONNS$4$", exchange(&mut socket, "CONNS$", 8).await);
+
+    // One of the connections ends with an error instead of a disconnection.
+    others[0].write_all(b"INCORRECT$").await.unwrap();
+    drop(others);
+    while exchange(&mut socket, "CONNS$", 8).await != "CONNS$1$" {
+        time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn values_longer_than_read_buffer_are_stored_intact() {
+    let read_buffer_bytes = std::num::NonZeroUsize::new(3).unwrap();
+    let address = start_server(Config { read_buffer_bytes, ..Config::default() }).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let request = "STORE$k$abcdefgh$STOREB$b$10$zażółćLOAD$k$LOAD$b$";
+    let response = "DONE$DONE$FOUND$abcdefgh$FOUND$zażółć$";
+    assert_eq!(response, exchange(&mut socket, request, response.len()).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn large_messages_are_processed_intact() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    // The value is split between many reads, also in the middle of characters.
+    let value = "zażółć".repeat(4000);
+    let request = format!("STOREB$k${}${value}LOAD$k$", value.len());
+    let response = exchange(&mut socket, &request, 5 + "FOUND$$".len() + value.len()).await;
+    assert_eq!(format!("DONE$FOUND${value}$"), response);
+
+    let requests = "STORE$k$v$".repeat(1000);
+    assert_eq!("DONE$".repeat(1000), exchange(&mut socket, &requests, 5000).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn connections_over_limit_wait_for_closed_ones() {
+    let address = start_server(Config { max_connections: Some(1), ..Config::default() }).await;
+    let mut first = TcpStream::connect(address).await.unwrap();
+    assert_eq!("PONG$", exchange(&mut first, "PING$", 5).await);
+
+    // The second connection is not handled while the first one is open.
+    let mut second = TcpStream::connect(address).await.unwrap();
+    second.write_all(b"PING$").await.unwrap();
+    let mut buf = [0; 5];
+    assert!(time::timeout(Duration::from_millis(100), second.read_exact(&mut buf)).await.is_err());
+
+    drop(first);
+    second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"PONG$", &buf);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn pairs_are_restored_from_write_ahead_log() {
+    let path = std::env::temp_dir().join(format!("kvs-wal-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut db = Database::new();
+    db.open_wal(&path, false).unwrap();
+    let address = start_server_with_db(Config::default(), Arc::new(db)).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    // Changes are on the disk before they are answered.
+    assert_eq!("DONE$DONE$DELETED$", exchange(&mut socket, "STORE$a$x$STOREB$b$2$$$DELETE$a$", 18).await);
+
+    let mut restored = Database::new();
+    restored.open_wal(&path, false).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let address = start_server_with_db(Config::default(), Arc::new(restored)).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("NOTFOUND$FOUND$$$$", exchange(&mut socket, "LOAD$a$LOAD$b$", 18).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn changes_are_answered_after_being_persisted_or_queued() {
+    let path = std::env::temp_dir().join(format!("kvs-wal-ack-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut db = Database::new();
+    db.open_wal(&path, false).unwrap();
+    let address = start_server_with_db(Config::default(), Arc::new(db)).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    let logged = |record: &[u8]| std::fs::read(&path).unwrap().windows(record.len()).any(|window| window == record);
+
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "ACK$persisted$STORE$a$x$", 10).await);
+    assert!(logged(b"SET$a$"));
+
+    // The change is answered before it is written, but it is written soon after.
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "ACK$queued$STORE$b$y$", 10).await);
+    while !logged(b"SET$b$") {
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!("ERROR$badmode$", exchange(&mut socket, "ACK$fast$", 14).await);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "encryption")]
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn encrypted_write_ahead_log_is_restored_only_with_its_key() {
+    let path = std::env::temp_dir().join(format!("kvs-wal-encrypted-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut db = Database::new();
+    db.open_encrypted_wal(&path, false, &[3; 32]).unwrap();
+    let address = start_server_with_db(Config::default(), Arc::new(db)).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("DONE$", exchange(&mut socket, "STORE$secret$hidden$", DONE_LEN).await);
+
+    let contents = std::fs::read(&path).unwrap();
+    assert!(!contents.windows(6).any(|window| window == b"hidden" || window == b"secret"));
+    assert!(Database::new().open_wal(&path, false).is_err());
+    assert!(Database::new().open_encrypted_wal(&path, false, &[4; 32]).is_err());
+
+    let mut restored = Database::new();
+    restored.open_encrypted_wal(&path, false, &[3; 32]).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let address = start_server_with_db(Config::default(), Arc::new(restored)).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("FOUND$hidden$", exchange(&mut socket, "LOAD$secret$", 13).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn flush_all_of_expired_pairs_is_written_before_answering() {
+    let path = std::env::temp_dir().join(format!("kvs-wal-flushall-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut db = Database::new();
+    db.open_wal(&path, false).unwrap();
+    let address = start_server_with_db(Config::default(), Arc::new(db)).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    // FLUSHALL removes no pair that has not expired, but it still changes the log.
+    assert_eq!("DONE$FLUSHED$", exchange(&mut socket, "STOREX$e$v$0$FLUSHALL$", 13).await);
+
+    let mut restored = Database::new();
+    restored.open_wal(&path, false).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    // Pairs that have expired count towards the memory limit until they are removed.
+    let address = start_server_with_db(Config { max_total_bytes: Some(2), ..Config::default() }, Arc::new(restored)).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("OK$", exchange(&mut socket, "STORECHECK$k$v$", 3).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn too_large_value_is_rejected_without_storing() {
+    let address = start_server(Config { max_value_bytes: Some(3), ..Config::default() }).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("ERROR$value_too_large$", exchange(&mut socket, "STORE$k$long$", 22).await);
+    assert_eq!("ERROR$value_too_large$", exchange(&mut socket, "STOREB$k$4$a$bc", 22).await);
+    assert_eq!("ERROR$value_too_large$", exchange(&mut socket, "STOREX$k$long$10$", 22).await);
+    assert_eq!("NOTFOUND$DONE$FOUND$abc$", exchange(&mut socket, "LOAD$k$STORE$k$abc$LOAD$k$", 24).await);
+    assert_eq!("LIMITS$maxkey=none$maxval=3$maxtotal=none$", exchange(&mut socket, "LIMITS$", 42).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn store_check_request_validates_without_storing() {
+    let config = Config { max_value_bytes: Some(3), max_total_bytes: Some(6), ..Config::default() };
+    let address = start_server(config).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let expected_responses = vec![
+        ("STORECHECK$k$abc$", "OK$"),
+        ("LOAD$k$", "NOTFOUND$"),
+        ("STORECHECK$k$long$", "ERROR$value_too_large$"),
+        ("STORE$k$abc$", "DONE$"),
+        // An overwrite counts only the difference in size.
+        ("STORECHECK$k$xyz$", "OK$"),
+        ("STORECHECK$key$abc$", "ERROR$full$"),
+        ("STORE$key$abc$", "FULL$"),
+        ("LOAD$k$", "FOUND$abc$")
+    ];
+
+    exchange_all(&mut socket, expected_responses).await;
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn too_large_unfinished_request_closes_connection() {
+    let address = start_server(Config { max_message_bytes: 100, ..Config::default() }).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    // Complete requests do not count towards the limit.
+    let requests = "STORE$k$v$".repeat(20);
+    assert_eq!("DONE$".repeat(20), exchange(&mut socket, &requests, 100).await);
+
+    socket.write_all(format!("STORE$k${}", "a".repeat(100)).as_bytes()).await.unwrap();
+    let mut buf = Vec::new();
+    socket.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(b"ERROR$too_large$", buf.as_slice());
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn expired_pair_is_not_found() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("DONE$", exchange(&mut socket, "STOREX$a$value$60$", 5).await);
+    assert_eq!("FOUND$value$", exchange(&mut socket, "LOAD$a$", 12).await);
+    assert_eq!("DONE$", exchange(&mut socket, "STOREX$a$value$0$", 5).await);
+    assert_eq!("NOTFOUND$", exchange(&mut socket, "LOAD$a$", 9).await);
+    assert_eq!("USAGE$disk=0$cache=0$keys=0$", exchange(&mut socket, "USAGE$", 28).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn expired_pair_is_not_found_by_loadt() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("DONE$", exchange(&mut socket, "STOREX$a$value$0$", 5).await);
+    assert_eq!("NOTFOUND$", exchange(&mut socket, "LOADT$a$len$", 9).await);
+    assert_eq!("USAGE$disk=0$cache=0$keys=0$", exchange(&mut socket, "USAGE$", 28).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn multibyte_characters_are_stored_and_loaded_intact() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    let request = "STOREB$k$10$zażółć".as_bytes();
+
+    // The first read ends in the middle of ż.
+    socket.write_all(&request[..15]).await.unwrap();
+    socket.flush().await.unwrap();
+    time::sleep(Duration::from_millis(10)).await;
+    socket.write_all(&request[15..]).await.unwrap();
+    let mut buf = [0; 5];
+    socket.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"DONE$", &buf);
+
+    socket.write_all(b"LOAD$k$").await.unwrap();
+    let mut buf = [0; 17];
+    socket.read_exact(&mut buf).await.unwrap();
+    assert_eq!("FOUND$zażółć$", std::str::from_utf8(&buf).unwrap());
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn keys_request_returns_sorted_keys() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("KEYS$", exchange(&mut socket, "KEYS$", 5).await);
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "STORE$b$x$STORE$a$y$", 10).await);
+    assert_eq!("KEYS$a$b$", exchange(&mut socket, "KEYS$", 9).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn scan_request_returns_sorted_keys_with_prefix() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("DONE$DONE$DONE$", exchange(&mut socket, "STORE$ab$x$STORE$b$y$STORE$aa$z$", 15).await);
+    assert_eq!("KEYS$aa$ab$", exchange(&mut socket, "SCAN$a$", 11).await);
+    assert_eq!("KEYS$", exchange(&mut socket, "SCAN$c$", 5).await);
+    // Empty prefix lists all keys, and PREFIX request does not apply.
+    assert_eq!("DONE$", exchange(&mut socket, "PREFIX$b$", 5).await);
+    assert_eq!("KEYS$aa$ab$b$", exchange(&mut socket, "SCAN$$", 13).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn flush_all_request_removes_keys_of_all_clients() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    let mut other = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "STORE$a$x$PREFIX$p$", 10).await);
+    assert_eq!("DONE$", exchange(&mut other, "STORE$b$y$", 5).await);
+    assert_eq!("FLUSHED$", exchange(&mut socket, "FLUSHALL$", 8).await);
+    assert_eq!("NOTFOUND$NOTFOUND$", exchange(&mut other, "LOAD$a$LOAD$b$", 18).await);
+    assert_eq!("USAGE$disk=0$cache=0$keys=0$", exchange(&mut other, "USAGE$", 28).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn namespaces_isolate_keys() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    let mut other = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "STORE$a$x$STORE$b$y$", 10).await);
+    assert_eq!("DONE$DONE$", exchange(&mut other, "SELECT$app$STORE$a$z$", 10).await);
+    assert_eq!("FOUND$z$KEYS$a$COUNT$1$", exchange(&mut other, "LOAD$a$KEYS$COUNT$", 23).await);
+    assert_eq!("NOTFOUND$", exchange(&mut other, "LOAD$b$", 9).await);
+    assert_eq!("FOUND$x$KEYS$a$b$COUNT$2$", exchange(&mut socket, "LOAD$a$KEYS$COUNT$", 25).await);
+
+    // PREFIX request works within the namespace.
+    assert_eq!("DONE$DONE$FOUND$z$", exchange(&mut socket, "SELECT$app$PREFIX$a$LOAD$$", 18).await);
+    assert_eq!("DONE$DONE$FOUND$y$", exchange(&mut socket, "SELECT$default$PREFIX$$LOAD$b$", 18).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn compare_and_swap_request_stores_only_expected_value() {
+    let address = start_server(Config { max_total_bytes: Some(4), ..Config::default() }).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("MISMATCH$", exchange(&mut socket, "CAS$k$$a$", 9).await);
+    assert_eq!("DONE$SWAPPED$", exchange(&mut socket, "STORE$k$a$CAS$k$a$b$", 13).await);
+    assert_eq!("MISMATCH$FOUND$b$", exchange(&mut socket, "CAS$k$a$c$LOAD$k$", 17).await);
+    assert_eq!("FULL$FOUND$b$", exchange(&mut socket, "CAS$k$b$long$LOAD$k$", 13).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn count_request_counts_present_keys() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("COUNT$0$", exchange(&mut socket, "COUNT$", 8).await);
+    assert_eq!("DONE$DONE$DONE$", exchange(&mut socket, "STORE$a$x$STORE$b$y$STORE$a$z$", 15).await);
+    assert_eq!("COUNT$2$", exchange(&mut socket, "COUNT$", 8).await);
+    assert_eq!("DONE$DELETED$1$", exchange(&mut socket, "STOREX$c$v$0$MDEL$a$$", 15).await);
+    assert_eq!("COUNT$1$", exchange(&mut socket, "COUNT$", 8).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn dump_restores_pairs_on_another_server() {
+    let source = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(source).await.unwrap();
+    let requests = "STORE$b$x$STOREB$a$5$c$\r\ndSELECT$ns$STORE$c$z$";
+    assert_eq!("DONE$DONE$DONE$DONE$", exchange(&mut socket, requests, 20).await);
+
+    let mut client = Client::connect(source).await.unwrap();
+    let dump = client.dump().await.unwrap();
+    // A dump that was cut off is not restored at all.
+    let target = start_server(Config::default()).await;
+    let mut client = Client::connect(target).await.unwrap();
+    assert!(matches!(client.restore(&dump[..dump.len() - 1]).await, Err(ClientError::InvalidDump)));
+    assert_eq!(None, client.load("a").await.unwrap());
+
+    // Keys of other namespaces are not dumped.
+    assert_eq!(2, client.restore(&dump).await.unwrap());
+    let mut socket = TcpStream::connect(target).await.unwrap();
+    assert_eq!("KEYS$a$b$FOUND$c$\r\nd$", exchange(&mut socket, "KEYS$LOAD$a$", 21).await);
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn json_dump_restores_pairs_on_another_server() {
+    let source = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(source).await.unwrap();
+    let requests = "STORE$b$x$STOREB$a$5$c$\r\ndSELECT$ns$STORE$c$z$";
+    assert_eq!("DONE$DONE$DONE$DONE$", exchange(&mut socket, requests, 20).await);
+
+    let mut client = Client::connect(source).await.unwrap();
+    let dump = client.dump_json().await.unwrap();
+    // The value with control characters is base64-encoded.
+    let expected = "{\"encoding\":\"base64\",\"key\":\"a\",\"value\":\"YyQNCmQ=\"}\n{\"key\":\"b\",\"value\":\"x\"}\n{\"count\":2}\n";
+    assert_eq!(expected, String::from_utf8(dump.clone()).unwrap());
+
+    let target = start_server(Config::default()).await;
+    let mut client = Client::connect(target).await.unwrap();
+    assert!(matches!(client.restore(&dump[..dump.len() - 1]).await, Err(ClientError::InvalidDump)));
+    assert_eq!(2, client.restore(&dump).await.unwrap());
+    let mut socket = TcpStream::connect(target).await.unwrap();
+    assert_eq!("KEYS$a$b$FOUND$c$\r\nd$", exchange(&mut socket, "KEYS$LOAD$a$", 21).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn dump_request_rejects_unknown_formats() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("ERROR$badformat$", exchange(&mut socket, "DUMP$xml$", 16).await);
+    assert_eq!("ERROR$badformat$", exchange(&mut socket, "DUMP$$", 16).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn long_dump_is_truncated_at_max_response_bytes() {
+    let address = start_server(Config { max_response_bytes: Some(20), ..Config::default() }).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "STORE$alpha$x$STORE$beta$y$", 10).await);
+
+    // The magic, the length of the first key and 4 bytes of the key.
+    let expected = "KVSDUMP1\0\0\0\0\0\0\0\x05alphTRUNCATED$";
+    assert_eq!(expected, exchange(&mut socket, "DUMP$binary$", 30).await);
+    // Answers that are not longer than the maximum are not changed.
+    assert_eq!("KEYS$alpha$beta$", exchange(&mut socket, "KEYS$", 16).await);
+    assert_eq!("DONE$KEYS$alpha$beta$gammTRUNCATED$", exchange(&mut socket, "STORE$gamma$z$KEYS$", 35).await);
+    assert_eq!("COUNT$3$", exchange(&mut socket, "COUNT$", 8).await);
+}
+
+// Logs written by servers of a test, to check what they are tagged with.
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn traced_request_is_processed_in_span_with_trace_id() {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    // The runtime of the test has a single thread, so the server logs to this subscriber.
+    let _subscriber = tracing::subscriber::set_default(subscriber);
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("DONE$FOUND$v$", exchange(&mut socket, "trace:4bf9-2_a:STORE$k$v$LOAD$k$", 13).await);
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    let processing = |name: &str| logs.lines()
+        .find(|line| line.contains(&format!("processing request request=\"{name}\"")))
+        .unwrap_or_else(|| panic!("{name} request was not logged"))
+        .to_string();
+    assert!(processing("STORE").contains("request{trace_id=4bf9-2_a}"));
+    // The prefix applies only to the request it precedes.
+    assert!(!processing("LOAD").contains("trace_id"));
+}
+
+// Sends TIME request and parses seconds and nanoseconds from the response.
+async fn server_time(socket: &mut TcpStream) -> (u64, u32) {
+    socket.write_all(b"TIME$").await.unwrap();
+    let mut response = Vec::new();
+    while response.iter().filter(|byte| **byte == b'$').count() < 3 {
+        response.push(socket.read_u8().await.unwrap());
+    }
+    let response = String::from_utf8(response).unwrap();
+    let parts: Vec<&str> = response.split('$').collect();
+    assert_eq!(vec!["TIME", parts[1], parts[2], ""], parts);
+    (parts[1].parse().unwrap(), parts[2].parse().unwrap())
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn time_request_returns_non_decreasing_time() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let first = server_time(&mut socket).await;
+    let second = server_time(&mut socket).await;
+    assert!(first.1 < 1_000_000_000 && second.1 < 1_000_000_000);
+    assert!(first <= second);
+}
+
+#[tokio::test]
+#[ntest::timeout(2000)]
+async fn connection_is_closed_when_client_does_not_read() {
+    let config = Config { write_timeout: Some(Duration::from_millis(100)), ..Config::default() };
+    let address = start_server(config).await;
+    let mut observer = TcpStream::connect(address).await.unwrap();
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    let store = format!("STORE$k${}$", "v".repeat(50_000));
+    assert_eq!("DONE$", exchange(&mut socket, &store, 5).await);
+    assert_eq!("CONNS$2$", exchange(&mut observer, "CONNS$", 8).await);
+
+    // Responses to these requests do not fit in socket buffers.
+    socket.write_all("LOAD$k$".repeat(100).as_bytes()).await.unwrap();
+    while exchange(&mut observer, "CONNS$", 8).await != "CONNS$1$" {
+        time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn idle_connection_is_reaped_and_active_one_survives() {
+    let db: Db = Arc::new(Database::new());
+    let config = Config { idle_timeout: Some(Duration::from_millis(100)), ..Config::default() };
+    let address = start_server_with_db(config, db.clone()).await;
+    let mut idle = TcpStream::connect(address).await.unwrap();
+    let mut active = TcpStream::connect(address).await.unwrap();
+
+    for _ in 0..15 {
+        assert_eq!("NOTFOUND$", exchange(&mut active, "LOAD$k$", 9).await);
+        time::sleep(Duration::from_millis(20)).await;
+    }
+
+    // The server closed the idle connection without writing anything.
+    let mut buf = [0; 1];
+    assert!(matches!(idle.read(&mut buf).await, Ok(0) | Err(_)));
+    assert_eq!("CONNS$1$", exchange(&mut active, "CONNS$", 8).await);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let metrics_address = listener.local_addr().unwrap();
+    tokio::spawn(serve_metrics(listener, db, std::future::pending()));
+    let mut scraper = TcpStream::connect(metrics_address).await.unwrap();
+    scraper.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut metrics = String::new();
+    scraper.read_to_string(&mut metrics).await.unwrap();
+    assert!(metrics.contains("\nkvs_reaped_connections_total 1\n"));
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn snapshot_contains_only_pairs_stored_before_it() {
+    let snapshot_dir = std::env::temp_dir().join(format!("kvs-snapshots-{}", std::process::id()));
+    std::fs::create_dir_all(&snapshot_dir).unwrap();
+    let address = start_server(Config { snapshot_dir: Some(snapshot_dir.clone()), ..Config::default() }).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "STORE$b$y$STORE$a$x$", 10).await);
+    assert_eq!("DONE$", exchange(&mut socket, "SNAPSHOT$first$", 5).await);
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "STORE$a$z$STORE$c$w$", 10).await);
+
+    let snapshot = std::fs::read_to_string(snapshot_dir.join("first")).unwrap();
+    std::fs::remove_dir_all(&snapshot_dir).unwrap();
+    assert_eq!("STORE$a$x$STORE$b$y$", snapshot);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn snapshot_restores_values_stored_with_store_binary_request() {
+    let snapshot_dir = std::env::temp_dir().join(format!("kvs-binary-snapshots-{}", std::process::id()));
+    std::fs::create_dir_all(&snapshot_dir).unwrap();
+    let address = start_server(Config { snapshot_dir: Some(snapshot_dir.clone()), ..Config::default() }).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "STOREB$b$3$1$2STORE$a$x$", 10).await);
+    assert_eq!("DONE$", exchange(&mut socket, "SNAPSHOT$first$", 5).await);
+
+    let snapshot = std::fs::read_to_string(snapshot_dir.join("first")).unwrap();
+    std::fs::remove_dir_all(&snapshot_dir).unwrap();
+    assert_eq!("STORE$a$x$STOREB$b$3$1$2", snapshot);
+
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    assert_eq!("DONE$DONE$", exchange(&mut socket, &snapshot, 10).await);
+    assert_eq!("FOUND$1$2$", exchange(&mut socket, "LOAD$b$", 10).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn snapshot_replaces_previous_one_ignoring_stray_temporary_files() {
+    let snapshot_dir = std::env::temp_dir().join(format!("kvs-replaced-snapshots-{}", std::process::id()));
+    std::fs::create_dir_all(&snapshot_dir).unwrap();
+    // Left by a server that died between writing and renaming.
+    std::fs::write(snapshot_dir.join("first.1.0.tmp"), "STORE$a$").unwrap();
+    let address = start_server(Config { snapshot_dir: Some(snapshot_dir.clone()), ..Config::default() }).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "STORE$a$x$SNAPSHOT$first$", 10).await);
+    assert_eq!("DONE$DONE$", exchange(&mut socket, "STORE$a$y$SNAPSHOT$first$", 10).await);
+
+    let snapshot = std::fs::read_to_string(snapshot_dir.join("first")).unwrap();
+    let mut files: Vec<_> = std::fs::read_dir(&snapshot_dir).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    std::fs::remove_dir_all(&snapshot_dir).unwrap();
+    assert_eq!("STORE$a$y$", snapshot);
+    assert_eq!(vec!["first", "first.1.0.tmp"], files);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn snapshot_request_is_incorrect_without_snapshot_dir() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    socket.write_all(b"SNAPSHOT$first$").await.unwrap();
+    let mut buf = Vec::new();
+    socket.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(b"ERROR$bad_request$", buf.as_slice());
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn set_config_request_changes_max_value_length_at_runtime() {
+    let address = start_server(Config { admin: true, max_value_bytes: Some(5), ..Config::default() }).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    exchange_all(&mut socket, [
+        ("STORE$k$abcd$", "DONE$"),
+        ("SETCONFIG$maxval$3$", "DONE$"),
+        ("LIMITS$", "LIMITS$maxkey=none$maxval=3$maxtotal=none$"),
+        ("STORE$k$abcd$", "ERROR$value_too_large$"),
+        ("STORE$k$abc$", "DONE$"),
+        ("SETCONFIG$maxval$none$", "DONE$"),
+        ("STORE$k$abcdefgh$", "DONE$"),
+        ("SETCONFIG$maxval$many$", "ERROR$badvalue$"),
+        ("SETCONFIG$maxtotal$10$", "IMMUTABLE$"),
+        ("SETCONFIG$addr$1$", "IMMUTABLE$")
+    ]).await;
+
+    // The setting is shared by all connections.
+    let mut other = TcpStream::connect(address).await.unwrap();
+    assert_eq!("LIMITS$maxkey=none$maxval=none$maxtotal=none$", exchange(&mut other, "LIMITS$", 45).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(2000)]
+async fn set_config_request_changes_idle_timeout_at_runtime() {
+    // The default idle timeout is 5 minutes.
+    let address = start_server(Config { admin: true, ..Config::default() }).await;
+    let mut admin = TcpStream::connect(address).await.unwrap();
+    let mut idle = TcpStream::connect(address).await.unwrap();
+
+    assert_eq!("DONE$", exchange(&mut admin, "SETCONFIG$idletimeout$50$", DONE_LEN).await);
+    let mut buf = [0; 1];
+    assert!(matches!(idle.read(&mut buf).await, Ok(0) | Err(_)));
+
+    // Without the timeout at startup, idle connections are never reaped.
+    let address = start_server(Config { admin: true, idle_timeout: None, ..Config::default() }).await;
+    let mut admin = TcpStream::connect(address).await.unwrap();
+    assert_eq!("IMMUTABLE$", exchange(&mut admin, "SETCONFIG$idletimeout$50$", 10).await);
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn set_config_request_is_incorrect_without_admin() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    socket.write_all(b"SETCONFIG$maxval$3$").await.unwrap();
+    let mut buf = Vec::new();
+    socket.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(b"ERROR$bad_request$", buf.as_slice());
+}
+
+#[tokio::test]
+#[ntest::timeout(1000)]
+async fn incorrect_request_gets_error_response() {
+    let address = start_server(Config::default()).await;
+    let mut socket = TcpStream::connect(address).await.unwrap();
+
+    socket.write_all(b"NOFLUSH$STORE$key$value$LOAD*$").await.unwrap();
+    let mut buf = Vec::new();
+    socket.read_to_end(&mut buf).await.unwrap();
+    // Withheld responses are dropped.
+    assert_eq!(b"ERROR$bad_request$", buf.as_slice());
 }